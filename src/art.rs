@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::RgbImage;
+use serde::Deserialize;
+use termion::cursor::Goto;
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::Color;
+use tui::widgets::Widget;
+
+use crate::status_bar;
+
+/// Decoded covers get shrunk to fit in this many pixels on a side, which is still plenty for a
+/// terminal.
+const MAX_SIZE: u32 = 512;
+/// How many decoded covers to hold on to.
+const CACHED: usize = 16;
+/// Kitty wants its payload in chunks of at most this many bytes.
+const KITTY_CHUNK: usize = 4096;
+
+/// How to get pixels on the screen.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// Pick based on what the terminal says it is.
+    Auto,
+    /// ▀ with the top pixel as the foreground and the bottom one as the background, which works
+    /// anywhere with truecolor.
+    HalfBlocks,
+    Kitty,
+    Sixel,
+}
+
+impl Protocol {
+    fn detect() -> Protocol {
+        let var = |name| env::var(name).unwrap_or_default().to_lowercase();
+        let (term, program) = (var("TERM"), var("TERM_PROGRAM"));
+
+        if env::var_os("KITTY_WINDOW_ID").is_some()
+            || term == "xterm-kitty"
+            || ["wezterm", "ghostty"].contains(&program.as_str())
+        {
+            Protocol::Kitty
+        } else if term.contains("sixel")
+            || ["foot", "mlterm", "yaft-256color"].contains(&term.as_str())
+            || program == "mlterm"
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::HalfBlocks
+        }
+    }
+}
+
+/// Album art for the now playing panel.
+pub struct Art {
+    protocol: Protocol,
+    covers: HashMap<PathBuf, Option<RgbImage>>,
+    /// The last cover scaled to fit the panel, and the size it was scaled for.
+    resized: Option<(PathBuf, (u32, u32), RgbImage)>,
+    /// Where an image was last drawn with kitty or sixel, so it's only sent when something changes.
+    placed: Option<(PathBuf, Rect)>,
+}
+
+impl Art {
+    pub fn new(protocol: Protocol) -> Art {
+        let protocol = match protocol {
+            Protocol::Auto => Protocol::detect(),
+            p => p,
+        };
+
+        Art {
+            protocol,
+            covers: HashMap::new(),
+            resized: None,
+            placed: None,
+        }
+    }
+
+    /// Draw `cover` into `area` with half blocks, if that's the protocol in use. The others get
+    /// drawn by Art::place after the rest of the frame.
+    pub fn half_blocks(&mut self, cover: &Path, area: Rect) -> Option<HalfBlocks<'_>> {
+        if self.protocol != Protocol::HalfBlocks {
+            return None;
+        }
+
+        let size = (u32::from(area.width), u32::from(area.height) * 2);
+        let image = self.resize(cover, size)?;
+        Some(HalfBlocks { image })
+    }
+
+    /// Whether drawing `cover` in `area` would mean clearing the screen first, because a sixel
+    /// image is already somewhere else. Sixels are part of the text, so tui doesn't know to redraw
+    /// over them.
+    pub fn needs_clear(&self, cover: Option<&Path>, area: Option<Rect>) -> bool {
+        match (&self.placed, self.protocol) {
+            (Some((placed, placed_area)), Protocol::Sixel) => {
+                cover != Some(placed.as_path()) || area != Some(*placed_area)
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget about the last placed image, after the screen was cleared.
+    pub fn cleared(&mut self) {
+        if self.protocol == Protocol::Sixel {
+            self.placed = None;
+        }
+    }
+
+    /// Forget about the last placed image no matter the protocol, after the terminal was given
+    /// back to the shell for a while and whatever was on it is gone.
+    pub fn forget(&mut self) {
+        self.placed = None;
+    }
+
+    /// Draw `cover` into `area` with kitty or sixel graphics, taking down the last one. Does nothing
+    /// with half blocks, or if the same cover is already in the same place.
+    pub fn place<W: Write>(
+        &mut self,
+        out: &mut W,
+        cover: Option<&Path>,
+        area: Option<Rect>,
+    ) -> io::Result<()> {
+        if self.protocol == Protocol::HalfBlocks {
+            return Ok(());
+        }
+        let placement = match (cover, area) {
+            (Some(c), Some(a)) => Some((c.to_path_buf(), a)),
+            _ => None,
+        };
+        if placement == self.placed {
+            return Ok(());
+        }
+
+        if self.protocol == Protocol::Kitty && self.placed.is_some() {
+            write!(out, "\x1b_Ga=d,d=i,i=1,q=2\x1b\\")?;
+        }
+        self.placed = None;
+
+        let (cover, area) = match placement {
+            Some(p) => p,
+            None => return out.flush(),
+        };
+        let (cell_width, cell_height) = cell_size();
+        let size = (
+            u32::from(area.width) * cell_width,
+            u32::from(area.height) * cell_height,
+        );
+        let protocol = self.protocol;
+        let image = match self.resize(&cover, size) {
+            Some(i) => i,
+            None => return out.flush(),
+        };
+
+        // center it, rounding to whole cells
+        let columns = image.width().div_ceil(cell_width);
+        let rows = image.height().div_ceil(cell_height);
+        let x = area.x + (area.width - columns.min(u32::from(area.width)) as u16) / 2;
+        let y = area.y + (area.height - rows.min(u32::from(area.height)) as u16) / 2;
+        write!(out, "{}", Goto(x + 1, y + 1))?;
+
+        match protocol {
+            Protocol::Kitty => kitty(out, image, columns, rows)?,
+            Protocol::Sixel => sixel(out, image)?,
+            _ => (),
+        }
+        self.placed = Some((cover, area));
+        out.flush()
+    }
+
+    /// The cover scaled to fit in `size` pixels, keeping its aspect ratio.
+    fn resize(&mut self, cover: &Path, size: (u32, u32)) -> Option<&RgbImage> {
+        let fresh = match &self.resized {
+            Some((path, s, _)) => path != cover || *s != size,
+            None => true,
+        };
+        if fresh {
+            let image = self.decode(cover)?;
+            let (width, height) = fit(image.dimensions(), size);
+            let resized = image::imageops::resize(image, width, height, FilterType::Triangle);
+            self.resized = Some((cover.to_path_buf(), size, resized));
+        }
+
+        self.resized.as_ref().map(|(_, _, image)| image)
+    }
+
+    fn decode(&mut self, cover: &Path) -> Option<&RgbImage> {
+        if !self.covers.contains_key(cover) {
+            if self.covers.len() >= CACHED {
+                self.covers.clear();
+            }
+            let image = match image::open(cover) {
+                Ok(i) => Some(i.thumbnail(MAX_SIZE, MAX_SIZE).to_rgb8()),
+                Err(e) => {
+                    status_bar::error(format!("error decoding {}: {}", cover.display(), e));
+                    None
+                }
+            };
+            self.covers.insert(cover.to_path_buf(), image);
+        }
+
+        self.covers.get(cover).and_then(Option::as_ref)
+    }
+}
+
+/// An image drawn two pixels to a cell with ▀.
+pub struct HalfBlocks<'a> {
+    image: &'a RgbImage,
+}
+
+impl Widget for HalfBlocks<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let (width, height) = self.image.dimensions();
+        let columns = width.min(u32::from(area.width)) as u16;
+        let rows = height.div_ceil(2).min(u32::from(area.height)) as u16;
+        let left = area.x + (area.width - columns) / 2;
+        let top = area.y + (area.height - rows) / 2;
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x, y) = (u32::from(column), u32::from(row) * 2);
+                let top_pixel = self.image.get_pixel(x, y);
+                let cell = buf.get_mut(left + column, top + row);
+                cell.set_char('▀').set_fg(rgb(top_pixel.0));
+                if y + 1 < height {
+                    cell.set_bg(rgb(self.image.get_pixel(x, y + 1).0));
+                }
+            }
+        }
+    }
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> Color {
+    Color::Rgb(r, g, b)
+}
+
+/// The largest size with the aspect ratio of `image` that fits in `area`.
+fn fit(image: (u32, u32), area: (u32, u32)) -> (u32, u32) {
+    let (w, h) = (image.0.max(1) as f64, image.1.max(1) as f64);
+    let scale = (area.0 as f64 / w).min(area.1 as f64 / h);
+    (((w * scale) as u32).max(1), ((h * scale) as u32).max(1))
+}
+
+/// How many pixels wide and tall a cell is, guessing 8x16 if the terminal won't say.
+fn cell_size() -> (u32, u32) {
+    match (termion::terminal_size_pixels(), termion::terminal_size()) {
+        (Ok((pw, ph)), Ok((cw, ch))) if pw > 0 && ph > 0 && cw > 0 && ch > 0 => (
+            (u32::from(pw) / u32::from(cw)).max(1),
+            (u32::from(ph) / u32::from(ch)).max(1),
+        ),
+        _ => (8, 16),
+    }
+}
+
+/// Send raw RGB with the kitty graphics protocol, as image 1 so it can be taken down later.
+fn kitty<W: Write>(out: &mut W, image: &RgbImage, columns: u32, rows: u32) -> io::Result<()> {
+    let data = base64(image.as_raw());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        if i == 0 {
+            write!(
+                out,
+                "\x1b_Ga=T,f=24,i=1,q=2,C=1,s={},v={},c={},r={},m={};",
+                image.width(),
+                image.height(),
+                columns,
+                rows,
+                more
+            )?;
+        } else {
+            write!(out, "\x1b_Gm={};", more)?;
+        }
+        out.write_all(chunk)?;
+        write!(out, "\x1b\\")?;
+    }
+
+    Ok(())
+}
+
+/// Encode the image as sixels, with its colors squashed into a 6x6x6 cube.
+fn sixel<W: Write>(out: &mut W, image: &RgbImage) -> io::Result<()> {
+    let (width, height) = image.dimensions();
+    let index = |x: u32, y: u32| -> usize {
+        let [r, g, b] = image.get_pixel(x, y).0;
+        let level = |c: u8| (usize::from(c) * 5 + 127) / 255;
+        level(r) * 36 + level(g) * 6 + level(b)
+    };
+
+    let mut s = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for i in 0..216 {
+        let percent = |level: usize| level * 100 / 5;
+        s.push_str(&format!(
+            "#{};2;{};{};{}",
+            i,
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        ));
+    }
+
+    let mut band = vec![0u8; width as usize * 216];
+    for top in (0..height).step_by(6) {
+        band.iter_mut().for_each(|b| *b = 0);
+        let mut used = [false; 216];
+        for y in top..(top + 6).min(height) {
+            for x in 0..width {
+                let color = index(x, y);
+                used[color] = true;
+                band[color * width as usize + x as usize] |= 1 << (y - top);
+            }
+        }
+
+        for color in (0..216).filter(|&c| used[c]) {
+            s.push_str(&format!("#{}", color));
+            let row = &band[color * width as usize..(color + 1) * width as usize];
+            let mut x = 0;
+            while x < row.len() {
+                let run = row[x..].iter().take_while(|&&b| b == row[x]).count();
+                let c = char::from(63 + row[x]);
+                if run > 3 {
+                    s.push_str(&format!("!{}{}", run, c));
+                } else {
+                    (0..run).for_each(|_| s.push(c));
+                }
+                x += run;
+            }
+            s.push('$');
+        }
+        s.push('-');
+    }
+    s.push_str("\x1b\\");
+
+    out.write_all(s.as_bytes())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
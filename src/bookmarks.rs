@@ -0,0 +1,192 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{status_bar, xdg};
+
+/// How often to check whether another bebop changed the bookmarks.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A named spot in a song to come back to.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Bookmark {
+    pub name: String,
+    pub path: PathBuf,
+    /// Seconds into the song.
+    pub position: f64,
+}
+
+/// Every bookmark in the order they were made, kept in bookmarks.json in the state directory.
+#[derive(Default)]
+struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+    modified: Option<SystemTime>,
+    checked: Option<Instant>,
+}
+
+impl Bookmarks {
+    /// Read the bookmarks again if another bebop saved them since they were last read.
+    fn refresh(&mut self) {
+        if self.checked.is_some_and(|c| c.elapsed() < RELOAD_INTERVAL) {
+            return;
+        }
+        self.checked = Some(Instant::now());
+
+        let modified = modified();
+        if self.modified == modified {
+            return;
+        }
+        match load() {
+            Ok(bookmarks) => {
+                self.bookmarks = bookmarks;
+                self.modified = modified;
+            }
+            Err(e) => status_bar::error(format!("error reading bookmarks: {}", e)),
+        }
+    }
+
+    fn change<F: FnOnce(&mut Vec<Bookmark>)>(&mut self, f: F) {
+        // checking right away, so a change from another bebop doesn't get saved over
+        self.checked = None;
+        self.refresh();
+        f(&mut self.bookmarks);
+        match save(&self.bookmarks) {
+            Ok(()) => self.modified = modified(),
+            Err(e) => status_bar::error(format!("error saving bookmarks: {}", e)),
+        }
+    }
+}
+
+fn bookmarks() -> &'static Mutex<Bookmarks> {
+    static BOOKMARKS: OnceLock<Mutex<Bookmarks>> = OnceLock::new();
+    BOOKMARKS.get_or_init(Mutex::default)
+}
+
+pub fn all() -> Vec<Bookmark> {
+    let mut bookmarks = bookmarks().lock().unwrap();
+    bookmarks.refresh();
+    bookmarks.bookmarks.clone()
+}
+
+pub fn add(bookmark: Bookmark) {
+    bookmarks()
+        .lock()
+        .unwrap()
+        .change(|bookmarks| bookmarks.push(bookmark));
+}
+
+pub fn remove(bookmark: &Bookmark) {
+    bookmarks()
+        .lock()
+        .unwrap()
+        .change(|bookmarks| bookmarks.retain(|b| b != bookmark));
+}
+
+fn path() -> Option<PathBuf> {
+    xdg::state_dir().map(|d| d.join("bookmarks.json"))
+}
+
+fn modified() -> Option<SystemTime> {
+    path()
+        .and_then(|p| fs::metadata(p).ok())
+        .and_then(|m| m.modified().ok())
+}
+
+fn load() -> io::Result<Vec<Bookmark>> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(Vec::new()),
+    };
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(serde_json::from_str(&s)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save(bookmarks: &[Bookmark]) -> io::Result<()> {
+    let path =
+        path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(bookmarks)?)?;
+    fs::rename(tmp, path)
+}
+
+/// The bookmarks panel, toggled on and off, with one bookmark picked to jump to or delete.
+#[derive(Default)]
+pub struct BookmarksView {
+    shown: bool,
+    selected: usize,
+}
+
+impl BookmarksView {
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+    }
+
+    /// Which bookmark is picked, kept within however many there are now.
+    pub fn index(&self) -> usize {
+        self.selected.min(all().len().saturating_sub(1))
+    }
+
+    pub fn selected(&self) -> Option<Bookmark> {
+        all().get(self.index()).cloned()
+    }
+
+    /// Pick the bookmark `by` over.
+    pub fn select(&mut self, by: isize) {
+        let last = all().len().saturating_sub(1) as isize;
+        self.selected = (self.index() as isize + by).clamp(0, last) as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(name: &str, position: f64) -> Bookmark {
+        Bookmark {
+            name: name.to_string(),
+            path: PathBuf::from("/music/Album/01 Song.flac"),
+            position,
+        }
+    }
+
+    #[test]
+    fn add_select_and_remove() {
+        xdg::isolate();
+        let (intro, solo) = (bookmark("intro", 0f64), bookmark("the solo", 151.25));
+        add(intro.clone());
+        add(solo.clone());
+        assert_eq!(all(), [intro.clone(), solo.clone()]);
+        // saved as it goes, for other bebops to read
+        assert_eq!(load().unwrap(), [intro.clone(), solo.clone()]);
+
+        let mut view = BookmarksView::default();
+        assert_eq!(view.selected(), Some(intro.clone()));
+        view.select(5);
+        assert_eq!(view.selected(), Some(solo.clone()));
+        view.select(-1);
+        assert_eq!(view.index(), 0);
+
+        remove(&intro);
+        assert_eq!(all(), vec![solo.clone()]);
+        assert_eq!(load().unwrap(), vec![solo.clone()]);
+        // the selection stays within the bookmarks that are left
+        view.select(1);
+        assert_eq!(view.selected(), Some(solo.clone()));
+        remove(&solo);
+        assert_eq!(view.selected(), None);
+    }
+}
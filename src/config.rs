@@ -0,0 +1,159 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::art::Protocol;
+use crate::layout::Pane;
+use crate::now_playing::Position;
+use crate::playlists::PlaylistConfig;
+use crate::ratings::RatingsConfig;
+use crate::scrobble::ScrobbleConfig;
+use crate::speed::Pitch;
+use crate::theme::ThemeConfig;
+use crate::xdg;
+
+/// Settings from config.toml in the config directory. The older environment variables still work
+/// and win over the file.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub music_dir: Option<PathBuf>,
+    pub status: StatusConfig,
+    pub art: ArtConfig,
+    pub now_playing: NowPlayingConfig,
+    pub explorer: ExplorerConfig,
+    pub playback: PlaybackConfig,
+    pub theme: ThemeConfig,
+    pub mpd: MpdConfig,
+    pub scrobble: ScrobbleConfig,
+    pub ratings: RatingsConfig,
+    /// Smart playlists, listed in the explorer after the artists.
+    pub playlists: Vec<PlaylistConfig>,
+    /// Where everything goes, instead of the classic layout built from the art and now playing
+    /// settings.
+    pub layout: Option<Pane>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct StatusConfig {
+    /// Where to write the now playing status, if anywhere.
+    pub path: Option<PathBuf>,
+    /// What to write, with {title}, {artist}, {album}, {file}, {dir}, {path}, {cover},
+    /// {elapsed}, {duration}, {state} and {volume} filled in.
+    pub format: String,
+    /// Write a JSON object with all of the above instead of using the format.
+    pub json: bool,
+}
+
+impl Default for StatusConfig {
+    fn default() -> StatusConfig {
+        StatusConfig {
+            path: None,
+            format: "{file}\n{artist}\n{cover}\n".to_string(),
+            json: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ExplorerConfig {
+    /// Show the artists, albums and songs side by side, previewing what's below the selection.
+    pub columns: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    /// How many seconds songs fade into each other for, up to 12. 0 turns it off.
+    pub crossfade: f32,
+    /// Play songs from the same album straight into each other instead of crossfading, since
+    /// albums are often meant to flow together.
+    pub gapless: bool,
+    /// What playing faster or slower does to the pitch: "keep" time-stretches so it stays the
+    /// same, "follow" resamples so it goes up and down with the speed.
+    pub pitch: Pitch,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> PlaybackConfig {
+        PlaybackConfig {
+            crossfade: 0f32,
+            gapless: true,
+            pitch: Pitch::Keep,
+        }
+    }
+}
+
+/// Where to speak the MPD protocol, so MPD clients can control bebop. Off unless one is set.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct MpdConfig {
+    /// A TCP address like "127.0.0.1:6600".
+    pub address: Option<String>,
+    /// A Unix socket path.
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ArtConfig {
+    /// Show the current song's cover above the queue. Having an art pane in the layout does too.
+    pub enabled: bool,
+    /// One of "auto", "halfblocks", "kitty" or "sixel".
+    pub protocol: Protocol,
+}
+
+impl Default for ArtConfig {
+    fn default() -> ArtConfig {
+        ArtConfig {
+            enabled: false,
+            protocol: Protocol::Auto,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct NowPlayingConfig {
+    /// One of "hidden", "above" or "beside", relative to the queue.
+    pub position: Position,
+}
+
+impl Default for NowPlayingConfig {
+    fn default() -> NowPlayingConfig {
+        NowPlayingConfig {
+            position: Position::Hidden,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> io::Result<Config> {
+        let mut config = match xdg::config_dir().map(|d| d.join("config.toml")) {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => toml::from_str(&contents)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+                Err(e) => return Err(e),
+            },
+            None => Config::default(),
+        };
+
+        if let Some(dir) = env::var_os("BEBOP_MUSIC_DIR") {
+            config.music_dir = Some(dir.into());
+        }
+        if let Some(path) = env::var_os("BEBOP_STATUS_FILE_PATH") {
+            config.status.path = Some(path.into());
+        }
+        if let Ok(format) = env::var("BEBOP_STATUS_FORMAT") {
+            config.status.format = format;
+        }
+
+        Ok(config)
+    }
+}
@@ -0,0 +1,542 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::equalizer::{Equalizer, Preset, BANDS};
+use crate::{cover, status_bar, xdg, AbLoop, Event, LoopPoint, Playback, Repeat};
+
+/// Something to tell the running player to do, sent over the control socket as one JSON object
+/// per line, like `{"cmd":"seek","seconds":-10,"relative":true}`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Command {
+    Play,
+    Pause,
+    Toggle,
+    Next,
+    Prev,
+    /// Skip to the song at `index` in the queue.
+    Jump {
+        index: usize,
+    },
+    Seek {
+        seconds: f64,
+        #[serde(default)]
+        relative: bool,
+    },
+    Volume {
+        percent: f32,
+        #[serde(default)]
+        relative: bool,
+    },
+    Enqueue {
+        paths: Vec<PathBuf>,
+    },
+    /// Replace the queue and start playing it at `start`.
+    Load {
+        paths: Vec<PathBuf>,
+        #[serde(default)]
+        start: usize,
+    },
+    /// Stop playing and empty the queue.
+    Clear,
+    /// Shuffle the queue, or put it back in order if it's shuffled.
+    Shuffle,
+    Repeat {
+        mode: Repeat,
+    },
+    /// Set the equalizer to one of the presets.
+    Preset {
+        preset: Preset,
+    },
+    /// Set one of the equalizer's bands, counting from 0, or the preamp without one, in dB.
+    Gain {
+        band: Option<usize>,
+        db: f32,
+        #[serde(default)]
+        relative: bool,
+    },
+    /// Play at `rate` times normal speed, or that much faster if it's relative.
+    Speed {
+        rate: f32,
+        #[serde(default)]
+        relative: bool,
+    },
+    /// Set one end of the A-B loop in the current song at the current position, or stop looping.
+    Loop {
+        point: LoopPoint,
+    },
+    /// Bookmark the current position in the current song, named after the song without a name.
+    Bookmark {
+        name: Option<String>,
+    },
+    Status,
+    /// Stop the player, daemon and all.
+    Quit,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Status {
+    /// One of "playing", "paused" or "stopped".
+    pub state: String,
+    pub song: Option<PathBuf>,
+    pub index: usize,
+    pub queue: Vec<PathBuf>,
+    /// Seconds into the current song.
+    pub position: f64,
+    /// How long the current song is in seconds, if that's known.
+    pub duration: Option<f64>,
+    pub volume: f32,
+    /// Cover art for the current song.
+    pub cover: Option<PathBuf>,
+    #[serde(default)]
+    pub shuffle: bool,
+    #[serde(default = "no_repeat")]
+    pub repeat: Repeat,
+    #[serde(default)]
+    pub equalizer: Equalizer,
+    #[serde(default = "normal_speed")]
+    pub speed: f32,
+    #[serde(default)]
+    pub ab_loop: Option<AbLoop>,
+}
+
+fn no_repeat() -> Repeat {
+    Repeat::Off
+}
+
+fn normal_speed() -> f32 {
+    1f32
+}
+
+pub fn status(player: &dyn Playback) -> Status {
+    let state = if player.is_stopped() {
+        "stopped"
+    } else if player.is_paused() {
+        "paused"
+    } else {
+        "playing"
+    };
+
+    let song = player.playing().get(player.index()).cloned();
+    Status {
+        state: state.to_string(),
+        cover: song.as_deref().and_then(cover::find),
+        song,
+        index: player.index(),
+        queue: player.playing().to_vec(),
+        position: player.position().as_secs_f64(),
+        duration: player.duration().map(|d| d.as_secs_f64()),
+        volume: player.volume() * 100f32,
+        shuffle: player.is_shuffled(),
+        repeat: player.repeat(),
+        equalizer: player.equalizer(),
+        speed: player.speed(),
+        ab_loop: player.ab_loop(),
+    }
+}
+
+/// Where the control socket lives, $BEBOP_SOCKET_PATH or bebop.sock in the runtime directory.
+pub fn socket_path() -> PathBuf {
+    match env::var_os("BEBOP_SOCKET_PATH") {
+        Some(p) => PathBuf::from(p),
+        None => xdg::runtime_dir().join("bebop.sock"),
+    }
+}
+
+/// Bind the control socket and pass every command that comes in along as an Event, answering each
+/// one with whatever gets sent back.
+pub fn listen<P: AsRef<Path>>(path: P, sender: Sender<Event>) -> io::Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let listener = bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve(stream, sender) {
+                            status_bar::error(format!("error serving control client: {}", e));
+                        }
+                    });
+                }
+                Err(e) => status_bar::error(format!("error accepting control client: {}", e)),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Bind a Unix socket at `path`, replacing it if it's left over from a crash.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Ok(l) => Ok(l),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            // nobody's listening, so it's left over from a crash
+            fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Pass a command along to the event loop and wait for the answer, or None if it's gone.
+pub fn request(sender: &Sender<Event>, command: Command) -> Option<Response> {
+    let (reply_sender, reply_receiver) = channel();
+    sender.send(Event::Command(command, reply_sender)).ok()?;
+    Some(
+        reply_receiver
+            .recv()
+            .unwrap_or_else(|_| Response::Error("player went away".to_string())),
+    )
+}
+
+fn serve(stream: UnixStream, sender: Sender<Event>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Command>(&line) {
+            Ok(command) => match request(&sender, command) {
+                Some(r) => r,
+                None => break,
+            },
+            Err(e) => Response::Error(format!("bad command: {}", e)),
+        };
+
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    }
+
+    Ok(())
+}
+
+/// Send one command to a running player and wait for the answer.
+pub fn send<P: AsRef<Path>>(path: P, command: &Command) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", serde_json::to_string(command)?)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Turn `bebop ctl` arguments into a Command. Seek, volume and speed take an absolute value, or a
+/// relative one when it starts with + or -.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let (name, rest) = match args.split_first() {
+        Some((name, rest)) => (name.as_str(), rest),
+        None => return Err("no command given".to_string()),
+    };
+
+    let command = match name {
+        "play" => Command::Play,
+        "pause" => Command::Pause,
+        "toggle" => Command::Toggle,
+        "next" => Command::Next,
+        "prev" => Command::Prev,
+        "status" => Command::Status,
+        "clear" => Command::Clear,
+        "shuffle" => Command::Shuffle,
+        "quit" => Command::Quit,
+        "repeat" => {
+            let mode = match rest {
+                [mode] => mode.as_str(),
+                _ => return Err("repeat needs one of off, all or one".to_string()),
+            };
+            let mode = match mode {
+                "off" => Repeat::Off,
+                "all" => Repeat::All,
+                "one" => Repeat::One,
+                _ => return Err(format!("unknown repeat mode \"{}\"", mode)),
+            };
+            Command::Repeat { mode }
+        }
+        "loop" => {
+            let point = match rest {
+                [point] => point.as_str(),
+                _ => return Err("loop needs one of a, b or off".to_string()),
+            };
+            let point = match point {
+                "a" => LoopPoint::A,
+                "b" => LoopPoint::B,
+                "off" => LoopPoint::Off,
+                _ => return Err(format!("unknown loop point \"{}\"", point)),
+            };
+            Command::Loop { point }
+        }
+        "bookmark" => Command::Bookmark {
+            name: Some(rest.join(" ")).filter(|n| !n.is_empty()),
+        },
+        "jump" => {
+            let index = match rest {
+                [index] => index,
+                _ => return Err("jump needs an index into the queue".to_string()),
+            };
+            let index = index
+                .parse()
+                .map_err(|e| format!("bad index \"{}\": {}", index, e))?;
+            Command::Jump { index }
+        }
+        "eq" => match rest {
+            [preset] => {
+                let preset = match preset.as_str() {
+                    "flat" => Preset::Flat,
+                    "bass_boost" => Preset::BassBoost,
+                    "vocal" => Preset::Vocal,
+                    _ => return Err(format!("unknown preset \"{}\"", preset)),
+                };
+                Command::Preset { preset }
+            }
+            [band, amount] => {
+                let band = match band.as_str() {
+                    "preamp" => None,
+                    n => match n.parse::<usize>() {
+                        Ok(n) if (1..=BANDS).contains(&n) => Some(n - 1),
+                        _ => return Err(format!("bands go from 1 to {}", BANDS)),
+                    },
+                };
+                let (db, relative) = parse_amount(std::slice::from_ref(amount))?;
+                Command::Gain {
+                    band,
+                    db: db as f32,
+                    relative,
+                }
+            }
+            _ => return Err("eq needs a preset, or a band and an amount".to_string()),
+        },
+        "speed" => {
+            let (rate, relative) = parse_amount(rest)?;
+            Command::Speed {
+                rate: rate as f32,
+                relative,
+            }
+        }
+        "seek" => {
+            let (seconds, relative) = parse_amount(rest)?;
+            Command::Seek { seconds, relative }
+        }
+        "volume" => {
+            let (percent, relative) = parse_amount(rest)?;
+            Command::Volume {
+                percent: percent as f32,
+                relative,
+            }
+        }
+        "enqueue" | "load" => {
+            if rest.is_empty() {
+                return Err(format!("{} needs at least one path", name));
+            }
+            let paths = rest
+                .iter()
+                .map(|p| fs::canonicalize(p).map_err(|e| format!("{}: {}", p, e)))
+                .collect::<Result<_, _>>()?;
+            if name == "load" {
+                Command::Load { paths, start: 0 }
+            } else {
+                Command::Enqueue { paths }
+            }
+        }
+        _ => return Err(format!("unknown command \"{}\"", name)),
+    };
+
+    Ok(command)
+}
+
+fn parse_amount(args: &[String]) -> Result<(f64, bool), String> {
+    let arg = match args {
+        [arg] => arg,
+        _ => return Err("expected one amount".to_string()),
+    };
+    let relative = arg.starts_with('+') || arg.starts_with('-');
+    match arg.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok((n, relative)),
+        Ok(_) => Err(format!("bad amount \"{}\": not a finite number", arg)),
+        Err(e) => Err(format!("bad amount \"{}\": {}", arg, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::TempDir;
+
+    fn parse(line: &str) -> Result<Command, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse("toggle"), Ok(Command::Toggle));
+        assert_eq!(parse("jump 3"), Ok(Command::Jump { index: 3 }));
+        assert_eq!(
+            parse("seek -10"),
+            Ok(Command::Seek {
+                seconds: -10f64,
+                relative: true
+            })
+        );
+        assert_eq!(
+            parse("volume 40"),
+            Ok(Command::Volume {
+                percent: 40f32,
+                relative: false
+            })
+        );
+        assert_eq!(
+            parse("speed +0.25"),
+            Ok(Command::Speed {
+                rate: 0.25,
+                relative: true
+            })
+        );
+        assert_eq!(
+            parse("repeat one"),
+            Ok(Command::Repeat { mode: Repeat::One })
+        );
+        assert_eq!(
+            parse("eq 2 -3"),
+            Ok(Command::Gain {
+                band: Some(1),
+                db: -3f32,
+                relative: true
+            })
+        );
+        assert_eq!(
+            parse("eq bass_boost"),
+            Ok(Command::Preset {
+                preset: Preset::BassBoost
+            })
+        );
+        assert_eq!(
+            parse("loop a"),
+            Ok(Command::Loop {
+                point: LoopPoint::A
+            })
+        );
+        assert_eq!(
+            parse("bookmark the solo"),
+            Ok(Command::Bookmark {
+                name: Some("the solo".to_string())
+            })
+        );
+        assert_eq!(parse("bookmark"), Ok(Command::Bookmark { name: None }));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse(""), Err("no command given".to_string()));
+        assert_eq!(parse("dance"), Err("unknown command \"dance\"".to_string()));
+        assert_eq!(
+            parse("repeat sometimes"),
+            Err("unknown repeat mode \"sometimes\"".to_string())
+        );
+        assert_eq!(parse("eq 11 1"), Err("bands go from 1 to 10".to_string()));
+        for line in [
+            "seek inf",
+            "seek -inf",
+            "seek nan",
+            "volume NaN",
+            "speed 1 2",
+        ] {
+            assert!(parse(line).is_err(), "{}", line);
+        }
+        assert!(parse("enqueue").is_err());
+    }
+
+    #[test]
+    fn enqueue_whole_paths() {
+        let dir = TempDir::new("ctl");
+        let song = dir.join("song.flac");
+        fs::write(&song, b"").unwrap();
+        let song = fs::canonicalize(song).unwrap();
+
+        // the daemon's running somewhere else, so it gets the whole path
+        let load = parse(&format!(
+            "load {}",
+            dir.join(".").join("song.flac").display()
+        ));
+        assert_eq!(
+            load,
+            Ok(Command::Load {
+                paths: vec![song],
+                start: 0
+            })
+        );
+        let missing = dir.join("missing.flac");
+        assert!(parse(&format!("enqueue {}", missing.display())).is_err());
+    }
+
+    #[test]
+    fn commands_over_the_wire() {
+        let seek: Command = serde_json::from_str(r#"{"cmd":"seek","seconds":-10}"#).unwrap();
+        assert_eq!(
+            seek,
+            Command::Seek {
+                seconds: -10f64,
+                relative: false
+            }
+        );
+        assert!(serde_json::from_str::<Command>(r#"{"cmd":"dance"}"#).is_err());
+
+        for command in [
+            Command::Next,
+            Command::Load {
+                paths: vec![PathBuf::from("/music/a.flac")],
+                start: 1,
+            },
+            Command::Gain {
+                band: None,
+                db: 2.5,
+                relative: false,
+            },
+            Command::Bookmark {
+                name: Some("chorus".to_string()),
+            },
+        ] {
+            let json = serde_json::to_string(&command).unwrap();
+            assert_eq!(serde_json::from_str::<Command>(&json).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn old_statuses_still_read() {
+        // from before shuffle, repeat, the equalizer, speed and loops
+        let json = r#"{"status":{"state":"paused","song":"/music/a.flac","index":0,
+            "queue":["/music/a.flac"],"position":1.5,"duration":null,"volume":0.5,"cover":null}}"#;
+        match serde_json::from_str(json).unwrap() {
+            Response::Status(status) => {
+                assert_eq!(status.repeat, Repeat::Off);
+                assert_eq!(status.speed, 1f32);
+                assert_eq!(status.ab_loop, None);
+            }
+            response => panic!("expected a status, got {:?}", response),
+        }
+    }
+}
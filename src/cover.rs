@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use lofty::picture::{MimeType, Picture, PictureType};
+use lofty::prelude::*;
+
+use crate::{status_bar, xdg};
+
+/// File names (minus the extension) that cover art usually goes by, best first.
+const NAMES: [&str; 3] = ["cover", "folder", "front"];
+const EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+
+/// Find the cover art for a song: an image next to it, or failing that a picture embedded in its
+/// tags, pulled out into the cache directory. Answers are remembered, since this gets asked a lot.
+pub fn find(song: &Path) -> Option<PathBuf> {
+    static FOUND: OnceLock<Mutex<HashMap<PathBuf, Option<PathBuf>>>> = OnceLock::new();
+    let found = FOUND.get_or_init(Default::default);
+
+    if let Some(cover) = found.lock().unwrap().get(song) {
+        return cover.clone();
+    }
+
+    let cover = song
+        .parent()
+        .and_then(in_dir)
+        .or_else(|| match embedded(song) {
+            Ok(cover) => cover,
+            Err(e) => {
+                status_bar::error(format!(
+                    "error extracting cover from {}: {}",
+                    song.display(),
+                    e
+                ));
+                None
+            }
+        });
+    found
+        .lock()
+        .unwrap()
+        .insert(song.to_path_buf(), cover.clone());
+    cover
+}
+
+/// Look for something like cover.jpg or Folder.PNG in `dir`.
+fn in_dir(dir: &Path) -> Option<PathBuf> {
+    let mut candidates: Vec<(usize, PathBuf)> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|de| de.ok().map(|de| de.path()))
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?.to_lowercase();
+            let extension = path.extension()?.to_str()?.to_lowercase();
+            let rank = NAMES.iter().position(|&n| n == stem)?;
+            if EXTENSIONS.contains(&extension.as_str()) && path.is_file() {
+                Some((rank, path))
+            } else {
+                None
+            }
+        })
+        .collect();
+    candidates.sort();
+    candidates.into_iter().next().map(|(_, path)| path)
+}
+
+/// Write the song's embedded picture (an ID3 APIC frame, a FLAC PICTURE block, ...) to the cache,
+/// preferring the front cover if there's more than one.
+fn embedded(song: &Path) -> io::Result<Option<PathBuf>> {
+    let tagged = lofty::read_from_path(song).map_err(io::Error::other)?;
+    let pictures: Vec<&Picture> = tagged.tags().iter().flat_map(|t| t.pictures()).collect();
+    let picture = match pictures
+        .iter()
+        .find(|p| p.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+    {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+
+    let dir = match xdg::cache_dir() {
+        Some(d) => d.join("covers"),
+        None => return Ok(None),
+    };
+    let data = picture.data();
+    let path = dir.join(format!("{:016x}.{}", fnv1a(data), extension(picture)));
+    if !path.is_file() {
+        fs::create_dir_all(&dir)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, &path)?;
+    }
+
+    Ok(Some(path))
+}
+
+fn extension(picture: &Picture) -> &'static str {
+    match picture.mime_type() {
+        Some(MimeType::Jpeg) => return "jpg",
+        Some(MimeType::Png) => return "png",
+        Some(MimeType::Gif) => return "gif",
+        Some(MimeType::Bmp) => return "bmp",
+        Some(MimeType::Tiff) => return "tiff",
+        _ => (),
+    }
+
+    // the MIME type is missing or something lofty doesn't know, so go by the magic number
+    match picture.data() {
+        [0x89, b'P', b'N', b'G', ..] => "png",
+        [0xff, 0xd8, ..] => "jpg",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "webp",
+        _ => "img",
+    }
+}
+
+/// Cheap stable hash to name cached pictures by, so the same picture is only written once.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,383 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use crate::bookmarks::{self, Bookmark};
+use crate::config::Config;
+use crate::control::{self, status, Command, Response};
+use crate::equalizer::Equalizer;
+use crate::explorer::is_song;
+use crate::plays::Tracker;
+use crate::scrobble::Scrobbler;
+use crate::song::format_time;
+use crate::{history, mpd};
+use crate::{status_bar, AbLoop, Event, LoopPoint, Mpris, Playback, Player, Repeat, Session};
+use crate::{Song, StatusFile};
+
+/// The player and everything that has to keep up with it: the control socket, MPRIS, the status
+/// file and the saved session. Runs inside the TUI, or on its own with --daemon so the music keeps
+/// going without a terminal.
+pub struct Daemon {
+    player: Player,
+    status_file: Option<StatusFile>,
+    mpris: Option<Mpris>,
+    tracker: Tracker,
+    scrobbler: Option<Scrobbler>,
+    sender: Sender<Event>,
+    /// Where the explorer was, to save with the session.
+    location: Vec<PathBuf>,
+}
+
+impl Daemon {
+    /// Start the player back up from the last session, and start listening for commands. Anything
+    /// that doesn't work out besides the player itself goes to the status bar.
+    pub fn new(config: &Config, sender: Sender<Event>) -> io::Result<Daemon> {
+        let mut player = Player::new(0.2).map_err(io::Error::other)?;
+        player.set_crossfade(config.playback.crossfade, config.playback.gapless);
+        player.set_pitch(config.playback.pitch);
+
+        let mut location = Vec::new();
+        match Session::load() {
+            Ok(Some(session)) => {
+                location = session.location().to_vec();
+                match session.restore(&mut player) {
+                    Ok(Some(song_switch_receiver)) => {
+                        watch_songs(song_switch_receiver, sender.clone())
+                    }
+                    Ok(None) => (),
+                    Err(e) => status_bar::error(format!("error restoring session: {}", e)),
+                }
+            }
+            Ok(None) => (),
+            Err(e) => status_bar::error(format!("error loading session: {}", e)),
+        }
+
+        if let Err(e) = control::listen(control::socket_path(), sender.clone()) {
+            status_bar::error(format!("error opening control socket: {}", e));
+        }
+        if let Err(e) = mpd::listen(config, sender.clone()) {
+            status_bar::error(format!("error starting MPD server: {}", e));
+        }
+        let mpris = match Mpris::new(sender.clone(), status(&player)) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                status_bar::error(format!("error connecting to D-Bus: {}", e));
+                None
+            }
+        };
+
+        Ok(Daemon {
+            player,
+            status_file: StatusFile::new(&config.status),
+            mpris,
+            tracker: Tracker::default(),
+            scrobbler: Scrobbler::new(&config.scrobble),
+            sender,
+            location,
+        })
+    }
+
+    pub fn player(&self) -> &Player {
+        &self.player
+    }
+
+    pub fn location(&self) -> &[PathBuf] {
+        &self.location
+    }
+
+    pub fn set_location(&mut self, location: Vec<PathBuf>) {
+        self.location = location;
+    }
+
+    /// Deal with an event meant for the player, returning the exit status if it's time to quit.
+    pub fn handle(&mut self, event: Event) -> Option<i32> {
+        match event {
+            Event::Command(Command::Quit, reply) => {
+                let _ = reply.send(Response::Ok);
+                return Some(0);
+            }
+            Event::Command(command, reply) => {
+                let response = self.run(command);
+                // the client hanging up before hearing back isn't our problem
+                let _ = reply.send(response);
+            }
+            Event::SongChanged(remaining) => {
+                log::debug!("song finished, {} left in the queue", remaining);
+                // the Signal that sent this already moved the index past the song that finished
+                let finished = self.player.playing().len().checked_sub(remaining + 1);
+                if let Some(play) = finished.and_then(|i| self.tracker.finished(i)) {
+                    history::record(&play);
+                }
+                match self.player.song_finished() {
+                    Ok(Some(song_switch_receiver)) => {
+                        watch_songs(song_switch_receiver, self.sender.clone())
+                    }
+                    Ok(None) => (),
+                    Err(e) => status_bar::error(format!("error repeating: {}", e)),
+                }
+            }
+            Event::Save => {
+                if let Err(e) = self.save() {
+                    status_bar::error(format!("error saving session: {}", e));
+                }
+            }
+            _ => (),
+        }
+
+        None
+    }
+
+    /// Do what `command` says, keeping an eye on the songs if a new queue starts.
+    pub fn run(&mut self, command: Command) -> Response {
+        log::debug!("command: {:?}", command);
+        match execute(command, &mut self.player) {
+            Ok((response, receiver)) => {
+                if let Some(receiver) = receiver {
+                    watch_songs(receiver, self.sender.clone());
+                }
+                response
+            }
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+
+    /// Bring the history, scrobbler, status file and MPRIS up to date with the player.
+    pub fn update(&mut self) {
+        if let Some(play) = self.tracker.update(&self.player) {
+            history::record(&play);
+        }
+        if let Some(scrobbler) = &mut self.scrobbler {
+            scrobbler.update(self.tracker.current(), self.player.is_paused());
+        }
+        let status = status(&self.player);
+        if let Some(status_file) = &mut self.status_file {
+            if let Err(e) = status_file.update(&status) {
+                status_bar::error(format!("error writing status: {}", e));
+            }
+        }
+        if let Some(mpris) = &self.mpris {
+            if let Err(e) = mpris.update(status) {
+                status_bar::error(format!("error updating MPRIS: {}", e));
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        Session::new(self.location.clone(), &self.player).save()
+    }
+}
+
+fn execute(
+    command: Command,
+    player: &mut Player,
+) -> io::Result<(Response, Option<Receiver<usize>>)> {
+    // here rather than in each client, since anything can send commands to the socket
+    if let Command::Enqueue { paths } | Command::Load { paths, .. } = &command {
+        let not_song = |p: &&PathBuf| !p.is_file() || !p.extension().is_some_and(is_song);
+        if let Some(path) = paths.iter().find(not_song) {
+            let error = format!("{}: not a song", path.display());
+            return Ok((Response::Error(error), None));
+        }
+    }
+
+    let receiver = match command {
+        Command::Play => {
+            player.play();
+            None
+        }
+        Command::Pause => {
+            player.pause();
+            None
+        }
+        Command::Toggle => {
+            player.toggle_pause();
+            None
+        }
+        Command::Next => player.next_song()?,
+        Command::Prev => player.previous_song()?,
+        Command::Jump { index } => player.jump(index)?,
+        Command::Seek { seconds, .. } if !seconds.is_finite() => {
+            return Ok((
+                Response::Error("seek needs a finite number of seconds".to_string()),
+                None,
+            ));
+        }
+        Command::Seek { seconds, relative } => {
+            let seconds = if relative {
+                player.position().as_secs_f64() + seconds
+            } else {
+                seconds
+            };
+            match Duration::try_from_secs_f64(seconds.max(0f64)) {
+                Ok(position) => player.seek(position)?,
+                Err(e) => return Ok((Response::Error(format!("can't seek there: {}", e)), None)),
+            }
+        }
+        Command::Volume { percent, .. } if !percent.is_finite() => {
+            return Ok((
+                Response::Error("volume needs a finite percentage".to_string()),
+                None,
+            ));
+        }
+        Command::Volume { percent, relative } => {
+            let volume = percent / 100f32;
+            if relative {
+                player.set_volume(player.volume() + volume);
+            } else {
+                player.set_volume(volume);
+            }
+            None
+        }
+        Command::Enqueue { paths } => {
+            if paths.is_empty() {
+                return Ok((Response::Error("nothing to enqueue".to_string()), None));
+            }
+            player.enqueue(paths)?
+        }
+        Command::Load { paths, start } => {
+            if start >= paths.len() {
+                let error = format!("can't start at {} of {} songs", start, paths.len());
+                return Ok((Response::Error(error), None));
+            }
+            Some(player.play_queue(start, paths)?)
+        }
+        Command::Clear => {
+            player.clear();
+            None
+        }
+        Command::Shuffle => {
+            let receiver = player.toggle_shuffle()?;
+            if player.is_shuffled() {
+                status_bar::info("shuffle on");
+            } else {
+                status_bar::info("shuffle off");
+            }
+            receiver
+        }
+        Command::Preset { preset } => {
+            player.set_equalizer(Equalizer::preset(preset));
+            status_bar::info(format!("equalizer: {}", preset.name()));
+            None
+        }
+        Command::Gain { db, .. } if !db.is_finite() => {
+            return Ok((
+                Response::Error("gain needs a finite number of dB".to_string()),
+                None,
+            ));
+        }
+        Command::Gain { band, db, relative } => {
+            let mut equalizer = player.equalizer();
+            let db = if relative {
+                equalizer.get(band) + db
+            } else {
+                db
+            };
+            equalizer.set(band, db);
+            player.set_equalizer(equalizer);
+            None
+        }
+        Command::Speed { rate, relative } => {
+            let rate = if relative {
+                player.speed() + rate
+            } else {
+                rate
+            };
+            // rounded so stepping by tenths doesn't drift
+            player.set_speed((rate * 100f32).round() / 100f32);
+            status_bar::info(format!("speed: {}×", player.speed()));
+            None
+        }
+        Command::Loop { point } => {
+            let receiver = player.set_loop(point)?;
+            match (point, player.ab_loop()) {
+                (LoopPoint::A, Some(l)) => status_bar::info(format!("A: {}", format_time(l.a))),
+                (LoopPoint::B, Some(AbLoop { a, b: Some(b) })) => {
+                    status_bar::info(format!("looping {} to {}", format_time(a), format_time(b)))
+                }
+                _ => status_bar::info("loop off"),
+            }
+            receiver
+        }
+        Command::Bookmark { name } => {
+            let path = match player.playing().get(player.index()) {
+                Some(p) if !player.is_stopped() => p.clone(),
+                _ => return Ok((Response::Error("nothing's playing".to_string()), None)),
+            };
+            let position = player.position().as_secs_f64();
+            let name = name.unwrap_or_else(|| {
+                format!("{} at {}", Song::read(&path).title, format_time(position))
+            });
+            status_bar::info(format!("bookmarked {}", name));
+            bookmarks::add(Bookmark {
+                name,
+                path,
+                position,
+            });
+            None
+        }
+        Command::Repeat { mode } => {
+            player.set_repeat(mode);
+            match mode {
+                Repeat::Off => status_bar::info("repeat off"),
+                Repeat::All => status_bar::info("repeating the queue"),
+                Repeat::One => status_bar::info("repeating the song"),
+            }
+            None
+        }
+        Command::Status => return Ok((Response::Status(status(player)), None)),
+        // handled by whoever's running the daemon, since it means stopping them too
+        Command::Quit => None,
+    };
+
+    Ok((Response::Ok, receiver))
+}
+
+/// Pass song changes along to the event loop until the player moves on to another queue.
+fn watch_songs(song_switch_receiver: Receiver<usize>, event_sender: Sender<Event>) {
+    thread::spawn(move || {
+        while let Ok(i) = song_switch_receiver.recv() {
+            if let Err(e) = event_sender.send(Event::SongChanged(i)) {
+                status_bar::error(format!("error sending song change: {}", e));
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::xdg::TempDir;
+
+    #[test]
+    fn only_songs_are_queued() {
+        let dir = TempDir::new("daemon");
+        let (song, cover) = (dir.join("song.flac"), dir.join("cover.jpg"));
+        fs::write(&song, b"").unwrap();
+        fs::write(&cover, b"").unwrap();
+        let (mut player, _) = Player::idle();
+
+        for paths in [
+            vec![cover.clone()],
+            vec![dir.to_path_buf()],
+            vec![dir.join("missing.flac")],
+            vec![song, cover],
+        ] {
+            let commands = [
+                Command::Enqueue {
+                    paths: paths.clone(),
+                },
+                Command::Load { paths, start: 0 },
+            ];
+            for command in commands {
+                let (response, receiver) = execute(command, &mut player).unwrap();
+                assert!(matches!(response, Response::Error(_)), "{:?}", response);
+                assert!(receiver.is_none());
+            }
+        }
+        assert!(player.playing().is_empty());
+    }
+}
@@ -21,6 +21,11 @@ impl DirState {
         Ok(DirState { index: 0, dir })
     }
 
+    /// A list of paths kept in the order they're given.
+    pub fn new(dir: Dir) -> DirState {
+        DirState { index: 0, dir }
+    }
+
     pub fn entry_strings(&self) -> Vec<String> {
         self.dir
             .iter()
@@ -37,6 +42,9 @@ impl DirState {
     }
 
     pub fn select_next(&mut self) -> usize {
+        if self.dir.is_empty() {
+            return 0;
+        }
         if self.index == self.dir.len() - 1 {
             self.index = 0;
         } else {
@@ -46,6 +54,9 @@ impl DirState {
     }
 
     pub fn select_previous(&mut self) -> usize {
+        if self.dir.is_empty() {
+            return 0;
+        }
         if self.index == 0 {
             self.index = self.dir.len() - 1;
         } else {
@@ -69,10 +80,7 @@ impl DirState {
 
     pub fn selected_name(&self) -> Option<String> {
         match self.selected().file_name() {
-            Some(s) => match s.to_os_string().into_string() {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            },
+            Some(s) => s.to_os_string().into_string().ok(),
             None => None,
         }
     }
@@ -81,13 +89,15 @@ impl DirState {
         self.index
     }
 
+    pub fn position<P: AsRef<Path>>(&self, path: P) -> Option<usize> {
+        self.dir.iter().position(|p| p == path.as_ref())
+    }
+
     pub fn find(&self, name: &str) -> Option<usize> {
-        self.entry_strings().iter().position(|s| {
-            if let Some(_) = s.to_lowercase().find(&name.to_lowercase()) {
-                return true;
-            }
-            return false;
-        })
+        let name = name.to_lowercase();
+        self.entry_strings()
+            .iter()
+            .position(|s| s.to_lowercase().contains(&name))
     }
 }
 
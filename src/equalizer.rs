@@ -0,0 +1,413 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::source::Source;
+use serde::{Deserialize, Serialize};
+
+/// How many bands the graphic equalizer has.
+pub const BANDS: usize = 10;
+/// Where each band is centered, in Hz, an octave apart.
+pub const FREQUENCIES: [f32; BANDS] = [
+    31.25, 62.5, 125f32, 250f32, 500f32, 1000f32, 2000f32, 4000f32, 8000f32, 16000f32,
+];
+/// The most any band or the preamp can be turned up or down, in dB.
+pub const MAX_GAIN: f32 = 12f32;
+/// How wide each band is, for an octave.
+const Q: f32 = std::f32::consts::SQRT_2;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    Flat,
+    BassBoost,
+    Vocal,
+}
+
+impl Preset {
+    pub fn next(self) -> Preset {
+        match self {
+            Preset::Flat => Preset::BassBoost,
+            Preset::BassBoost => Preset::Vocal,
+            Preset::Vocal => Preset::Flat,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Flat => "flat",
+            Preset::BassBoost => "bass boost",
+            Preset::Vocal => "vocal",
+        }
+    }
+}
+
+/// How loud each band is, in dB, along with the preamp, which everything goes through first.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+pub struct Equalizer {
+    pub preamp: f32,
+    pub gains: [f32; BANDS],
+}
+
+impl Equalizer {
+    pub fn preset(preset: Preset) -> Equalizer {
+        match preset {
+            Preset::Flat => Equalizer::default(),
+            // turned down up front so the boost doesn't clip
+            Preset::BassBoost => Equalizer {
+                preamp: -5f32,
+                gains: [6f32, 6f32, 4f32, 2f32, 0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+            },
+            Preset::Vocal => Equalizer {
+                preamp: -3f32,
+                gains: [
+                    -3f32, -3f32, -1f32, 1f32, 3f32, 4f32, 4f32, 2f32, 0f32, -1f32,
+                ],
+            },
+        }
+    }
+
+    /// Set band `band`, or the preamp without one, keeping it within MAX_GAIN. NaN counts as
+    /// 0 dB.
+    pub fn set(&mut self, band: Option<usize>, gain: f32) {
+        let gain = if gain.is_nan() { 0f32 } else { gain };
+        let gain = gain.clamp(-MAX_GAIN, MAX_GAIN);
+        match band {
+            Some(band) => {
+                if let Some(g) = self.gains.get_mut(band) {
+                    *g = gain;
+                }
+            }
+            None => self.preamp = gain,
+        }
+    }
+
+    pub fn get(&self, band: Option<usize>) -> f32 {
+        match band {
+            Some(band) => self.gains.get(band).copied().unwrap_or_default(),
+            None => self.preamp,
+        }
+    }
+
+    fn is_flat(&self) -> bool {
+        self.preamp == 0f32 && self.gains.iter().all(|g| *g == 0f32)
+    }
+}
+
+/// The equalizer panel, toggled on and off, with one band picked to turn up or down.
+pub struct EqualizerView {
+    shown: bool,
+    /// None for the preamp.
+    band: Option<usize>,
+    preset: Preset,
+}
+
+impl Default for EqualizerView {
+    fn default() -> EqualizerView {
+        EqualizerView {
+            shown: false,
+            band: Some(0),
+            preset: Preset::Flat,
+        }
+    }
+}
+
+impl EqualizerView {
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+    }
+
+    pub fn band(&self) -> Option<usize> {
+        self.band
+    }
+
+    /// Pick the band `by` over, with the preamp coming before the first one.
+    pub fn select(&mut self, by: isize) {
+        let current = self.band.map_or(0, |b| b as isize + 1);
+        let next = (current + by).clamp(0, BANDS as isize);
+        self.band = (next as usize).checked_sub(1);
+    }
+
+    /// Move on to the next preset, returning it.
+    pub fn next_preset(&mut self) -> Preset {
+        self.preset = self.preset.next();
+        self.preset
+    }
+}
+
+/// Equalizer settings shared with everything playing through them, so they can be changed while
+/// a song plays.
+#[derive(Default)]
+pub struct Shared {
+    equalizer: Mutex<Equalizer>,
+    /// Goes up with every change, so sources can tell without locking.
+    version: AtomicU64,
+}
+
+impl Shared {
+    pub fn get(&self) -> Equalizer {
+        *self.equalizer.lock().unwrap()
+    }
+
+    pub fn set(&self, equalizer: Equalizer) {
+        *self.equalizer.lock().unwrap() = equalizer;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// A peaking filter from the Audio EQ Cookbook, boosting or cutting around one frequency.
+#[derive(Copy, Clone, Debug)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Biquad {
+    /// Turn the frequencies around `frequency` up or down by `gain` dB, over a width set by `q`.
+    pub fn peaking(frequency: f32, gain: f32, q: f32, sample_rate: u32) -> Biquad {
+        let a = 10f32.powf(gain / 40f32);
+        let w0 = 2f32 * PI * frequency / sample_rate as f32;
+        let alpha = w0.sin() / (2f32 * q);
+        let cos = w0.cos();
+        let a0 = 1f32 + alpha / a;
+        Biquad {
+            b0: (1f32 + alpha * a) / a0,
+            b1: (-2f32 * cos) / a0,
+            b2: (1f32 - alpha * a) / a0,
+            a1: (-2f32 * cos) / a0,
+            a2: (1f32 - alpha / a) / a0,
+        }
+    }
+
+    /// Filter one sample, with `state` holding the last two samples in and out.
+    fn process(&self, x: f32, state: &mut [f32; 4]) -> f32 {
+        let [x1, x2, y1, y2] = *state;
+        let y = self.b0 * x + self.b1 * x1 + self.b2 * x2 - self.a1 * y1 - self.a2 * y2;
+        *state = [x, x1, y, y1];
+        y
+    }
+}
+
+/// Runs a source through the preamp and equalizer, picking up changes to them as it plays.
+pub struct Equalize<I> {
+    input: I,
+    shared: Arc<Shared>,
+    version: Option<u64>,
+    sample_rate: u32,
+    channels: u16,
+    preamp: f32,
+    filters: Vec<Biquad>,
+    /// The filters' state for each channel, one after another.
+    state: Vec<[f32; 4]>,
+    /// Which channel the next sample is for.
+    channel: usize,
+}
+
+impl<I> Equalize<I>
+where
+    I: Source<Item = f32>,
+{
+    pub fn new(input: I, shared: Arc<Shared>) -> Equalize<I> {
+        Equalize {
+            sample_rate: input.sample_rate(),
+            channels: input.channels(),
+            input,
+            shared,
+            version: None,
+            preamp: 1f32,
+            filters: Vec::new(),
+            state: Vec::new(),
+            channel: 0,
+        }
+    }
+
+    /// Work the filters out again if the settings or the format changed.
+    fn update(&mut self) {
+        let version = self.shared.version.load(Ordering::Acquire);
+        let (sample_rate, channels) = (self.input.sample_rate(), self.input.channels());
+        let changed = self.version != Some(version)
+            || self.sample_rate != sample_rate
+            || self.channels != channels;
+        if !changed {
+            return;
+        }
+        self.version = Some(version);
+        self.sample_rate = sample_rate;
+        self.channels = channels;
+
+        let equalizer = self.shared.get();
+        self.preamp = 10f32.powf(equalizer.preamp / 20f32);
+        self.filters = if equalizer.is_flat() {
+            Vec::new()
+        } else {
+            FREQUENCIES
+                .iter()
+                .zip(equalizer.gains.iter())
+                // bands at or past Nyquist can't be filtered
+                .filter(|(f, g)| **g != 0f32 && **f < sample_rate as f32 * 0.45)
+                .map(|(f, g)| Biquad::peaking(*f, *g, Q, sample_rate))
+                .collect()
+        };
+        // keeping what the filters remember where it still fits keeps changes from clicking
+        let needed = self.filters.len() * usize::from(channels.max(1));
+        if self.state.len() != needed {
+            self.state = vec![[0f32; 4]; needed];
+        }
+    }
+}
+
+impl<I> Iterator for Equalize<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            self.update();
+        }
+        let mut sample = self.input.next()? * self.preamp;
+
+        let n = self.filters.len();
+        let state = &mut self.state[self.channel * n..(self.channel + 1) * n];
+        for (filter, state) in self.filters.iter().zip(state) {
+            sample = filter.process(sample, state);
+        }
+
+        self.channel = (self.channel + 1) % usize::from(self.channels.max(1));
+        Some(sample.clamp(-1f32, 1f32))
+    }
+}
+
+impl<I> Source for Equalize<I>
+where
+    I: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// Half a second of a sine at `frequency`, at a quarter of full scale so boosts don't clip,
+    /// in every one of `channels`.
+    fn sine(frequency: f32, channels: u16) -> Vec<f32> {
+        (0..SAMPLE_RATE / 2)
+            .flat_map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let sample = 0.25 * (2f32 * PI * frequency * t).sin();
+                std::iter::repeat_n(sample, usize::from(channels))
+            })
+            .collect()
+    }
+
+    fn equalize(samples: &[f32], channels: u16, equalizer: Equalizer) -> Vec<f32> {
+        let shared = Arc::new(Shared::default());
+        shared.set(equalizer);
+        let source = SamplesBuffer::new(channels, SAMPLE_RATE, samples.to_vec());
+        Equalize::new(source, shared).collect()
+    }
+
+    /// How much louder the equalizer makes a sine at `frequency`, in dB, once the filters have
+    /// settled.
+    fn response(frequency: f32, equalizer: Equalizer) -> f32 {
+        let input = sine(frequency, 2);
+        let output = equalize(&input, 2, equalizer);
+        assert_eq!(output.len(), input.len());
+        let rms = |samples: &[f32]| {
+            let settled = &samples[samples.len() / 2..];
+            (settled.iter().map(|s| s * s).sum::<f32>() / settled.len() as f32).sqrt()
+        };
+        20f32 * (rms(&output) / rms(&input)).log10()
+    }
+
+    fn boosted(band: usize, gain: f32) -> Equalizer {
+        let mut equalizer = Equalizer::default();
+        equalizer.set(Some(band), gain);
+        equalizer
+    }
+
+    #[test]
+    fn boosts_and_cuts_at_the_centre() {
+        for band in [2, 5, 8] {
+            let frequency = FREQUENCIES[band];
+            for gain in [6f32, -9f32, MAX_GAIN] {
+                let db = response(frequency, boosted(band, gain));
+                assert!(
+                    (db - gain).abs() < 0.1,
+                    "{} Hz at {} dB: {}",
+                    frequency,
+                    gain,
+                    db
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_far_frequencies_alone() {
+        let equalizer = boosted(5, 9f32);
+        // two octaves either side of 1 kHz
+        for frequency in [250f32, 4000f32] {
+            let db = response(frequency, equalizer);
+            assert!(db.abs() < 0.5, "{} Hz: {} dB", frequency, db);
+        }
+    }
+
+    #[test]
+    fn preamp() {
+        let mut equalizer = Equalizer::default();
+        equalizer.set(None, -6f32);
+        let db = response(440f32, equalizer);
+        assert!((db + 6f32).abs() < 0.01, "{} dB", db);
+    }
+
+    #[test]
+    fn flat_passes_through() {
+        for channels in [1, 2] {
+            let input = sine(440f32, channels);
+            let output = equalize(&input, channels, Equalizer::preset(Preset::Flat));
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn gains_stay_in_range() {
+        let mut equalizer = boosted(0, 3f32);
+        equalizer.set(Some(0), f32::NAN);
+        assert_eq!(equalizer.get(Some(0)), 0f32);
+        equalizer.set(None, f32::INFINITY);
+        assert_eq!(equalizer.get(None), MAX_GAIN);
+        equalizer.set(Some(1), -40f32);
+        assert_eq!(equalizer.get(Some(1)), -MAX_GAIN);
+        equalizer.set(Some(BANDS), 3f32);
+        assert_eq!(equalizer.get(Some(BANDS)), 0f32);
+    }
+}
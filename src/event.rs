@@ -1,6 +1,26 @@
+use std::sync::mpsc::Sender;
+
 use termion::event::Key;
 
+use crate::control::{Command, Response};
+
 pub enum Event {
     Input(Key),
     Redraw,
+    /// Time to redraw so the elapsed time and lyrics keep up with playback.
+    Tick,
+    /// Time to save the session.
+    Save,
+    /// A song finished, with this many left in the queue.
+    SongChanged(usize),
+    /// A command from the control socket, and where to send the answer.
+    Command(Command, Sender<Response>),
+    /// Time to save and quit with this exit status, because of a signal or a panic somewhere.
+    Quit(i32),
+    /// SIGTSTP, to stop until continued.
+    Suspend,
+    /// SIGCONT, after being stopped.
+    Resume,
+    /// Songs were added to, changed in or removed from the music directory.
+    LibraryChanged,
 }
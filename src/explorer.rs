@@ -13,10 +13,24 @@ pub enum State {
     Songs,
 }
 
+/// Songs picked out of the whole library by something other than where they are, like being a
+/// favorite. They're listed after the artists, and picking one goes straight to its songs.
+pub trait View {
+    fn name(&self) -> &str;
+    /// Worked out again every time the view's opened.
+    fn songs(&self) -> Vec<PathBuf>;
+}
+
 pub struct Explorer {
     dirs: [DirState; 3],
     state: State,
     list_state: ListState,
+    /// Whether the directories below the selection get loaded as it moves, so they can be shown
+    /// next to it.
+    columns: bool,
+    /// How many of the entries at the top are artists, with views making up the rest.
+    artists: usize,
+    views: Vec<Box<dyn View>>,
 }
 
 impl Explorer {
@@ -30,9 +44,76 @@ impl Explorer {
         list_state.select(Some(0));
 
         Ok(Explorer {
+            artists: dirs[0].entries(),
             dirs,
             state: State::Artists,
             list_state,
+            columns: false,
+            views: Vec::new(),
+        })
+    }
+
+    /// List views after the artists, replacing any that were there.
+    pub fn set_views(&mut self, views: Vec<Box<dyn View>>) {
+        let mut entries = self.dirs[0].dir()[..self.artists].to_vec();
+        // named by a relative path, so they can't be mistaken for an artist's directory
+        entries.extend(views.iter().map(|v| PathBuf::from(v.name())));
+        let index = self.dirs[0].index().min(entries.len().saturating_sub(1));
+        self.dirs[0] = DirState::new(entries);
+        self.dirs[0].select(index);
+        self.views = views;
+        self.update_selection();
+        self.preview();
+    }
+
+    /// Work out the songs in the selected view again, like after the library changed, keeping
+    /// the same song selected if it's still there.
+    pub fn refresh(&mut self) {
+        if self.selected_view().is_none() {
+            return;
+        }
+        let opened = match self.state {
+            State::Artists => self.columns,
+            _ => true,
+        };
+        if !opened {
+            return;
+        }
+        let (index, selected) = match self.dirs[2].entries() {
+            0 => (0, None),
+            _ => (self.dirs[2].index(), Some(self.dirs[2].selected().clone())),
+        };
+        self.open_view();
+        let len = self.dirs[2].entries();
+        if len > 0 {
+            // or wherever it was, if it's gone
+            let index = selected
+                .and_then(|s| self.dirs[2].position(s))
+                .unwrap_or_else(|| index.min(len - 1));
+            self.dirs[2].select(index);
+        }
+        self.update_selection();
+    }
+
+    /// The view picked at the top, if it's a view and not an artist.
+    fn selected_view(&self) -> Option<&dyn View> {
+        if self.dirs[0].entries() == 0 {
+            return None;
+        }
+        let index = self.dirs[0].index().checked_sub(self.artists)?;
+        self.views.get(index).map(|v| v.as_ref())
+    }
+
+    /// The songs below the selection: the selected song, or every song in the selected album.
+    /// Nothing for artists and views.
+    pub fn selected_songs(&self) -> io::Result<Vec<PathBuf>> {
+        if self.selected_dir().entries() == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(match self.state {
+            State::Artists => Vec::new(),
+            State::Albums => read_below(1, self.selected())?.dir().clone(),
+            State::Songs => vec![self.selected().clone()],
         })
     }
 
@@ -59,31 +140,34 @@ impl Explorer {
     pub fn select_next(&mut self) {
         let index = self.selected_dir_mut().select_next();
         self.list_state.select(Some(index));
+        self.preview();
     }
 
     pub fn select_previous(&mut self) {
         let index = self.selected_dir_mut().select_previous();
         self.list_state.select(Some(index));
+        self.preview();
     }
 
     pub fn select_next_dir(&mut self) -> io::Result<()> {
         match self.state {
+            State::Artists if self.selected_view().is_some() => {
+                self.open_view();
+                self.state = State::Songs;
+            }
             State::Artists => {
-                self.dirs[1] = DirState::read_dir(self.dirs[0].selected(), |p| p.is_dir())?;
+                self.dirs[1] = read_below(0, self.dirs[0].selected())?;
                 self.state = State::Albums;
             }
             State::Albums => {
-                self.dirs[2] =
-                    DirState::read_dir(self.dirs[1].selected(), |p| match p.extension() {
-                        Some(s) => is_song(s),
-                        None => false,
-                    })?;
+                self.dirs[2] = read_below(1, self.dirs[1].selected())?;
                 self.state = State::Songs;
             }
             _ => (),
         }
 
         self.update_selection();
+        self.preview();
 
         Ok(())
     }
@@ -93,6 +177,10 @@ impl Explorer {
             State::Albums => {
                 self.state = State::Artists;
             }
+            // views skip the albums on the way in, so they do on the way out too
+            State::Songs if self.selected_view().is_some() => {
+                self.state = State::Artists;
+            }
             State::Songs => {
                 self.state = State::Albums;
             }
@@ -118,6 +206,7 @@ impl Explorer {
     pub fn top(&mut self) {
         let index = self.selected_dir_mut().select(0);
         self.list_state.select(index);
+        self.preview();
     }
 
     pub fn bottom(&mut self) {
@@ -126,6 +215,7 @@ impl Explorer {
         if len > 0 {
             let index = selected.select(len - 1);
             self.list_state.select(index);
+            self.preview();
         }
     }
 
@@ -133,9 +223,95 @@ impl Explorer {
         if let Some(i) = self.selected_dir().find(s) {
             self.selected_dir_mut().select(i);
             self.list_state.select(Some(i));
+            self.preview();
         }
     }
 
+    /// Show the artists, albums and songs side by side instead of only the current directory.
+    pub fn set_columns(&mut self, columns: bool) {
+        self.columns = columns;
+        self.preview();
+    }
+
+    pub fn columns(&self) -> bool {
+        self.columns
+    }
+
+    /// The artists, the selected artist's albums and the selected album's songs.
+    pub fn dirs(&self) -> &[DirState; 3] {
+        &self.dirs
+    }
+
+    /// Load what's below the selection, if the columns are being shown. Directories that can't be
+    /// read show up empty.
+    fn preview(&mut self) {
+        if !self.columns {
+            return;
+        }
+        let depth = match self.state {
+            State::Artists => 0,
+            State::Albums => 1,
+            State::Songs => return,
+        };
+        if depth == 0 && self.selected_view().is_some() {
+            self.open_view();
+            return;
+        }
+        for i in depth..2 {
+            self.dirs[i + 1] = if self.dirs[i].entries() > 0 {
+                read_below(i, self.dirs[i].selected()).unwrap_or_default()
+            } else {
+                DirState::default()
+            };
+        }
+    }
+
+    /// Fill in the directories below the selected view, with the view's name standing in for an
+    /// album.
+    fn open_view(&mut self) {
+        let (name, songs) = match self.selected_view() {
+            Some(view) => (PathBuf::from(view.name()), view.songs()),
+            None => return,
+        };
+        self.dirs[1] = DirState::new(vec![name]);
+        self.dirs[2] = DirState::new(songs);
+    }
+
+    /// The selected path in each directory, from the artists down to the current one.
+    pub fn location(&self) -> Vec<PathBuf> {
+        let depth = match self.state {
+            State::Artists => 1,
+            State::Albums => 2,
+            State::Songs => 3,
+        };
+        self.dirs[..depth]
+            .iter()
+            .enumerate()
+            .filter(|(i, d)| d.entries() > 0 && !(*i == 1 && self.selected_view().is_some()))
+            .map(|(_, d)| d.selected().clone())
+            .collect()
+    }
+
+    /// Walk back down to a location from Explorer::location, stopping at the first path that
+    /// doesn't exist anymore.
+    pub fn restore(&mut self, location: &[PathBuf]) -> io::Result<()> {
+        for (i, path) in location.iter().enumerate() {
+            if i > 0 {
+                self.select_next_dir()?;
+            }
+            match self.selected_dir().position(path) {
+                Some(index) => {
+                    self.selected_dir_mut().select(index);
+                    self.update_selection();
+                    self.preview();
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn state(&self) -> State {
         self.state
     }
@@ -145,7 +321,20 @@ impl Explorer {
     }
 }
 
-fn is_song(s: &OsStr) -> bool {
+/// Read the directory at `path`, which was picked from `dirs[depth]`: albums below an artist, or
+/// songs below an album.
+fn read_below(depth: usize, path: &Path) -> io::Result<DirState> {
+    if depth == 0 {
+        DirState::read_dir(path, |p| p.is_dir())
+    } else {
+        DirState::read_dir(path, |p| match p.extension() {
+            Some(s) => is_song(s),
+            None => false,
+        })
+    }
+}
+
+pub fn is_song(s: &OsStr) -> bool {
     for &e in &[OsStr::new("wav"), OsStr::new("flac"), OsStr::new("mp3")] {
         if s == e {
             return true;
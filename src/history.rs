@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::logger::civil;
+use crate::plays::{self, Play};
+use crate::{status_bar, xdg};
+
+/// How many weeks of listening time the stats go back.
+const WEEKS: u64 = 8;
+/// How many entries each top list and the recently played list get.
+const TOP: usize = 10;
+/// How often the stats view checks whether the history changed.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+/// Plays shorter than this weren't really heard, and aren't recorded.
+const MIN_LISTENED: Duration = Duration::from_secs(1);
+
+/// One play of a song, as kept in history.jsonl in the state directory.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+    /// When it started, in seconds since the epoch.
+    pub at: u64,
+    pub path: PathBuf,
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    /// How long it was heard for, in seconds.
+    pub listened: f64,
+    /// How long the song is, in seconds.
+    pub length: Option<f64>,
+    /// Whether it played to the end, instead of being skipped.
+    pub completed: bool,
+}
+
+impl Entry {
+    /// Whether it counts as a play of the song for the top lists, the same as for scrobbling.
+    fn counts(&self) -> bool {
+        let seconds = |s: f64| Duration::try_from_secs_f64(s).unwrap_or_default();
+        plays::counts(seconds(self.listened), self.length.map(seconds))
+    }
+}
+
+/// Add a play that ended to the history, unless it was barely heard. The history's only ever
+/// appended to, one JSON object per line.
+pub fn record(play: &Play) {
+    if play.listened < MIN_LISTENED {
+        return;
+    }
+    let entry = Entry {
+        at: play.started,
+        path: play.path.clone(),
+        artist: play.song.artist.clone(),
+        album: play.song.album.clone(),
+        title: play.song.title.clone(),
+        listened: play.listened.as_secs_f64(),
+        length: play.length.map(|d| d.as_secs_f64()),
+        completed: play.completed,
+    };
+    if let Err(e) = append(&entry) {
+        status_bar::error(format!("error writing history: {}", e));
+    }
+}
+
+fn append(entry: &Entry) -> io::Result<()> {
+    let path =
+        path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Everything in the history, oldest first. Lines that can't be read, like one cut off by a
+/// crash, are skipped.
+pub fn load() -> io::Result<Vec<Entry>> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(Vec::new()),
+    };
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+fn path() -> Option<PathBuf> {
+    xdg::state_dir().map(|d| d.join("history.jsonl"))
+}
+
+/// When the history was last written to, to tell whether it needs reading again.
+fn modified() -> Option<SystemTime> {
+    path()
+        .and_then(|p| fs::metadata(p).ok())
+        .and_then(|m| m.modified().ok())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// How many times a song's been played, and when it last was.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Played {
+    pub count: usize,
+    /// In seconds since the epoch.
+    pub last: Option<u64>,
+}
+
+#[derive(Default)]
+struct Plays {
+    songs: HashMap<PathBuf, Played>,
+    modified: Option<SystemTime>,
+    checked: Option<Instant>,
+}
+
+/// How much a song's been played, going by the history. The history's read again when it
+/// changes, checking at most every few seconds.
+pub fn played(path: &Path) -> Played {
+    static PLAYS: OnceLock<Mutex<Plays>> = OnceLock::new();
+    let mut plays = PLAYS.get_or_init(Mutex::default).lock().unwrap();
+
+    if plays.checked.is_none_or(|c| c.elapsed() >= RELOAD_INTERVAL) {
+        plays.checked = Some(Instant::now());
+        let modified = modified();
+        if plays.modified != modified {
+            plays.modified = modified;
+            plays.songs.clear();
+            for entry in load().unwrap_or_default() {
+                let played = plays.songs.entry(entry.path.clone()).or_default();
+                if entry.counts() {
+                    played.count += 1;
+                }
+                played.last = played.last.max(Some(entry.at));
+            }
+        }
+    }
+
+    plays.songs.get(path).copied().unwrap_or_default()
+}
+
+/// What the history adds up to.
+#[derive(Default)]
+pub struct Stats {
+    /// The first day of each week, as days since the epoch, and how much was listened to that
+    /// week, newest first.
+    pub weeks: Vec<(u64, Duration)>,
+    /// Each with how many times it was played, most first.
+    pub artists: Vec<(String, usize)>,
+    pub albums: Vec<(String, usize)>,
+    pub tracks: Vec<(String, usize)>,
+    /// Newest first.
+    pub recent: Vec<Entry>,
+}
+
+impl Stats {
+    /// Add up `entries`, with the weeks going back from `now` in seconds since the epoch.
+    pub fn new(entries: &[Entry], now: u64) -> Stats {
+        let this_week = week(now);
+        let mut weeks = vec![Duration::from_secs(0); WEEKS as usize];
+        let mut artists = HashMap::new();
+        let mut albums = HashMap::new();
+        let mut tracks = HashMap::new();
+
+        for entry in entries {
+            if let Some(ago) = this_week.checked_sub(week(entry.at)) {
+                if let Some(total) = weeks.get_mut(ago as usize) {
+                    *total += Duration::from_secs_f64(entry.listened.max(0f64));
+                }
+            }
+            if !entry.counts() {
+                continue;
+            }
+            *artists.entry(entry.artist.clone()).or_insert(0) += 1;
+            *albums
+                .entry(format!("{} - {}", entry.artist, entry.album))
+                .or_insert(0) += 1;
+            *tracks
+                .entry(format!("{} - {}", entry.artist, entry.title))
+                .or_insert(0) += 1;
+        }
+
+        Stats {
+            weeks: weeks
+                .into_iter()
+                .enumerate()
+                .map(|(ago, total)| ((this_week - ago as u64) * 7 - 3, total))
+                .collect(),
+            artists: top(artists),
+            albums: top(albums),
+            tracks: top(tracks),
+            recent: entries.iter().rev().take(TOP).cloned().collect(),
+        }
+    }
+}
+
+/// Weeks since the epoch, starting on Mondays. The epoch was a Thursday.
+fn week(secs: u64) -> u64 {
+    (secs / 86400 + 3) / 7
+}
+
+fn top(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+    counts.truncate(TOP);
+    counts
+}
+
+/// A date like 2020-06-01 for a number of days since the epoch.
+pub fn date(days: u64) -> String {
+    let (year, month, day) = civil(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The stats panel, toggled on and off, with the stats worked out again whenever the history
+/// changes.
+#[derive(Default)]
+pub struct StatsView {
+    shown: bool,
+    stats: Option<(Option<SystemTime>, Stats)>,
+    checked: Option<Instant>,
+}
+
+impl StatsView {
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+    }
+
+    pub fn stats(&mut self) -> &Stats {
+        let due = self.checked.is_none_or(|c| c.elapsed() >= RELOAD_INTERVAL);
+        if due {
+            self.checked = Some(Instant::now());
+            let modified = modified();
+            let stale = match &self.stats {
+                Some((m, _)) => *m != modified,
+                None => true,
+            };
+            if stale {
+                let entries = load().unwrap_or_else(|e| {
+                    status_bar::error(format!("error reading history: {}", e));
+                    Vec::new()
+                });
+                self.stats = Some((modified, Stats::new(&entries, now())));
+            }
+        }
+
+        &self.stats.get_or_insert_with(|| (None, Stats::default())).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Thursday, 2024-03-14 at noon.
+    const NOW: u64 = 1_710_417_600;
+    const DAY: u64 = 86400;
+
+    fn entry(at: u64, artist: &str, album: &str, title: &str, listened: f64) -> Entry {
+        Entry {
+            at,
+            path: PathBuf::from(format!("/history-test/{}/{}/{}.flac", artist, album, title)),
+            artist: artist.to_string(),
+            album: album.to_string(),
+            title: title.to_string(),
+            listened,
+            length: Some(200f64),
+            completed: false,
+        }
+    }
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                completed: true,
+                ..entry(NOW - DAY, "A", "X", "One", 200f64)
+            },
+            // skipped after a few seconds, so it's only listening time
+            entry(NOW - DAY, "A", "X", "Two", 10f64),
+            entry(NOW - 7 * DAY, "B", "Y", "Three", 100f64),
+            Entry {
+                length: None,
+                ..entry(NOW - DAY, "A", "X", "One", 250f64)
+            },
+        ]
+    }
+
+    #[test]
+    fn what_counts() {
+        let counts: Vec<bool> = entries().iter().map(Entry::counts).collect();
+        assert_eq!(counts, [true, false, true, true]);
+        // half of a long song is more than 4 minutes
+        let long = Entry {
+            length: Some(1000f64),
+            ..entry(0, "A", "X", "Long", 240f64)
+        };
+        assert!(long.counts());
+        // too short to count, even all the way through
+        let short = Entry {
+            length: Some(10f64),
+            completed: true,
+            ..entry(0, "A", "X", "Short", 10f64)
+        };
+        assert!(!short.counts());
+        let corrupt = Entry {
+            length: Some(f64::NAN),
+            ..entry(0, "A", "X", "Corrupt", -1f64)
+        };
+        assert!(!corrupt.counts());
+    }
+
+    #[test]
+    fn append_and_load() {
+        xdg::isolate();
+        for entry in entries() {
+            append(&entry).unwrap();
+        }
+        // a line cut off by a crash, then one written after
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(path().unwrap())
+            .unwrap();
+        file.write_all(b"{\"at\":1,\"pa\n").unwrap();
+        append(&entry(NOW, "C", "Z", "Four", 30f64)).unwrap();
+
+        let loaded = load().unwrap();
+        assert_eq!(loaded.len(), 5);
+        for (loaded, written) in loaded.iter().zip(entries()) {
+            assert_eq!(loaded.path, written.path);
+            assert_eq!(loaded.at, written.at);
+            assert_eq!(loaded.listened, written.listened);
+            assert_eq!(loaded.length, written.length);
+            assert_eq!(loaded.completed, written.completed);
+        }
+        assert_eq!(loaded[4].title, "Four");
+
+        let one = played(&entries()[0].path);
+        assert_eq!(one.count, 2);
+        assert_eq!(one.last, Some(NOW - DAY));
+        let two = played(&entries()[1].path);
+        assert_eq!(two.count, 0);
+        assert_eq!(two.last, Some(NOW - DAY));
+        assert_eq!(played(Path::new("/history-test/never.flac")).count, 0);
+    }
+
+    #[test]
+    fn stats() {
+        let stats = Stats::new(&entries(), NOW);
+        assert_eq!(stats.weeks.len(), WEEKS as usize);
+        // weeks start on the Monday, 2024-03-11
+        assert_eq!(date(stats.weeks[0].0), "2024-03-11");
+        assert_eq!(stats.weeks[0].1, Duration::from_secs(460));
+        assert_eq!(date(stats.weeks[1].0), "2024-03-04");
+        assert_eq!(stats.weeks[1].1, Duration::from_secs(100));
+        assert_eq!(stats.weeks[2].1, Duration::from_secs(0));
+
+        assert_eq!(stats.artists, [("A".to_string(), 2), ("B".to_string(), 1)]);
+        assert_eq!(
+            stats.albums,
+            [("A - X".to_string(), 2), ("B - Y".to_string(), 1)]
+        );
+        assert_eq!(
+            stats.tracks,
+            [("A - One".to_string(), 2), ("B - Three".to_string(), 1)]
+        );
+        let recent: Vec<&str> = stats.recent.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(recent, ["One", "Three", "Two", "One"]);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(18414), "2020-06-01");
+        assert_eq!(date(11016), "2000-02-29");
+    }
+}
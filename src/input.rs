@@ -1,6 +1,8 @@
-use crate::{Event, Explorer, Player, State};
+use crate::control::Command;
+use crate::{ratings, status_bar, AbLoop, Event, Explorer, LoopPoint, Playback, State};
 use std::io;
-use std::sync::mpsc::{Sender, Receiver};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
 use termion::event::Key;
 use termion::input::TermRead;
 
@@ -11,36 +13,63 @@ pub fn send_input(s: Sender<Event>) {
         if let Some(i) = stdin.next() {
             match i {
                 Ok(key) => s.send(Event::Input(key)).unwrap(),
-                Err(e) => eprintln!("error reading key: {}", e),
+                Err(e) => status_bar::error(format!("error reading key: {}", e)),
             }
         }
     }
 }
 
 pub enum Action {
-    Play(Receiver<usize>),
+    /// Something for the player to do, wherever it is.
+    Command(Command),
     Quit,
+    Suspend,
+    ToggleLyrics,
+    ToggleStats,
+    ToggleEqualizer,
+    /// Pick the equalizer band this many over.
+    SelectBand(isize),
+    /// Turn the picked equalizer band up or down by this many dB.
+    Gain(f32),
+    NextPreset,
+    ToggleBookmarks,
+    /// Pick the bookmark this many over.
+    SelectBookmark(isize),
+    /// Play from the picked bookmark.
+    OpenBookmark,
+    DeleteBookmark,
+    ToggleVisualizer,
+    /// Switch between the spectrum and the waveform.
+    NextVisualization,
     None,
 }
 
+/// Starts `search` when what's being typed is a name for a bookmark instead.
+pub const BOOKMARK_PROMPT: char = '@';
+
 pub fn handle_input(
-    event: Event,
+    key: Key,
     explorer: &mut Explorer,
-    player: &mut Player,
+    player: &dyn Playback,
     search: &mut String,
 ) -> io::Result<Action> {
-    let key = match event {
-        Event::Input(k) => k,
-        _ => return Ok(Action::None),
-    };
     if !search.is_empty() {
+        let naming = search.starts_with(BOOKMARK_PROMPT);
         if let Key::Char(c) = key {
             if c == '\n' {
+                let name = search[1..].trim().to_string();
                 search.clear();
+                if naming {
+                    return Ok(Action::Command(Command::Bookmark {
+                        name: Some(name).filter(|n| !n.is_empty()),
+                    }));
+                }
                 return Ok(Action::None);
             }
             search.push(c);
-            explorer.search(&search[1..]);
+            if !naming {
+                explorer.search(&search[1..]);
+            }
         } else if let Key::Backspace = key {
             search.pop();
         } else {
@@ -55,6 +84,10 @@ pub fn handle_input(
         Key::Char('q') => {
             action = Action::Quit;
         }
+        // raw mode means Ctrl-Z comes in as a key instead of SIGTSTP
+        Key::Ctrl('z') => {
+            action = Action::Suspend;
+        }
         Key::Char('j') => {
             explorer.select_next();
         }
@@ -74,46 +107,112 @@ pub fn handle_input(
             explorer.bottom();
         }
         Key::Char('\n') => match explorer.state() {
+            State::Songs if explorer.selected_dir().entries() == 0 => (),
             State::Songs => {
-                player.play_song(explorer.selected().clone())?;
+                action = Action::Command(Command::Load {
+                    paths: vec![explorer.selected().clone()],
+                    start: 0,
+                });
             }
             State::Albums => {
                 explorer.select_next_dir()?;
-                let song_switch_receiver =
-                    player.play_songs(0, explorer.selected_dir().dir().clone())?;
+                let paths = explorer.selected_dir().dir().clone();
                 explorer.select_previous_dir();
-                action = Action::Play(song_switch_receiver);
+                action = Action::Command(Command::Load { paths, start: 0 });
             }
             State::Artists => {
                 explorer.select_next_dir()?;
             }
         },
-        Key::Char('p') => player.toggle_pause(),
+        Key::Char('p') => action = Action::Command(Command::Toggle),
+        Key::Char('L') => action = Action::ToggleLyrics,
+        Key::Char('H') => action = Action::ToggleStats,
+        Key::Char('E') => action = Action::ToggleEqualizer,
+        Key::Char('[') => action = Action::SelectBand(-1),
+        Key::Char(']') => action = Action::SelectBand(1),
+        Key::Char('<') => action = Action::Gain(-1f32),
+        Key::Char('>') => action = Action::Gain(1f32),
+        Key::Char('e') => action = Action::NextPreset,
+        Key::Char('V') => action = Action::ToggleVisualizer,
+        Key::Char('v') => action = Action::NextVisualization,
+        Key::Char('M') => action = Action::ToggleBookmarks,
+        Key::Char('J') => action = Action::SelectBookmark(1),
+        Key::Char('K') => action = Action::SelectBookmark(-1),
+        Key::Char('o') => action = Action::OpenBookmark,
+        Key::Char('x') => action = Action::DeleteBookmark,
+        Key::Char('m') => search.push(BOOKMARK_PROMPT),
+        // A, then B, then off again
+        Key::Char('a') => {
+            let point = match player.ab_loop() {
+                None => LoopPoint::A,
+                Some(AbLoop { b: None, .. }) => LoopPoint::B,
+                Some(_) => LoopPoint::Off,
+            };
+            action = Action::Command(Command::Loop { point });
+        }
+        Key::Char('s') => action = Action::Command(Command::Shuffle),
+        Key::Char('r') => {
+            action = Action::Command(Command::Repeat {
+                mode: player.repeat().next(),
+            });
+        }
         Key::Char('-') => {
-            let volume = player.volume() - 0.01f32;
-            player.set_volume(volume);
+            action = Action::Command(Command::Volume {
+                percent: -1f32,
+                relative: true,
+            });
         }
         Key::Char('+') => {
-            let volume = player.volume() + 0.01f32;
-            player.set_volume(volume);
+            action = Action::Command(Command::Volume {
+                percent: 1f32,
+                relative: true,
+            });
         }
-        Key::Char('b') => {
-            let index = player.index();
-            if index > 0 {
-                action = Action::Play(player.play_songs(index - 1, player.playing().to_vec())?);
-            }
+        Key::Char('{') => {
+            action = Action::Command(Command::Speed {
+                rate: -0.1,
+                relative: true,
+            });
         }
-        Key::Char('w') => {
-            let index = player.index();
-            if index < player.playing().len() - 1 {
-                action = Action::Play(player.play_songs(index + 1, player.playing().to_vec())?);
-            }
+        Key::Char('}') => {
+            action = Action::Command(Command::Speed {
+                rate: 0.1,
+                relative: true,
+            });
         }
+        Key::Char('=') => {
+            action = Action::Command(Command::Speed {
+                rate: 1f32,
+                relative: false,
+            });
+        }
+        Key::Char('b') => action = Action::Command(Command::Prev),
+        Key::Char('w') => action = Action::Command(Command::Next),
         Key::Char('/') => {
             search.push('/');
         }
+        Key::Char(c @ '0'..='5') => {
+            let stars = c as u8 - b'0';
+            with_selected_songs(explorer, |songs| ratings::rate(songs, stars))?;
+            explorer.refresh();
+        }
+        Key::Char('f') => {
+            with_selected_songs(explorer, ratings::toggle_favorite)?;
+            explorer.refresh();
+        }
         _ => (),
     }
 
     Ok(action)
 }
+
+/// Do something with the selected song, or every song in the selected album.
+fn with_selected_songs<F: FnOnce(&[PathBuf])>(explorer: &Explorer, f: F) -> io::Result<()> {
+    let songs = explorer.selected_songs()?;
+    if songs.is_empty() {
+        status_bar::info("pick an album or a song first");
+    } else {
+        f(&songs);
+    }
+    Ok(())
+}
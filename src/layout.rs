@@ -1,67 +1,812 @@
+use std::convert::TryFrom;
 use std::io;
+use std::io::Write;
+use std::path::Path;
 
+use serde::Deserialize;
 use tui::backend::Backend;
-use tui::layout::{Constraint, Direction, Layout};
-use tui::style::{Color, Modifier, Style};
-use tui::widgets::{Block, Borders, List, Text};
-use tui::Terminal;
+use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
+use tui::widgets::{Block, Borders, List, ListState, Paragraph, Text};
+use tui::{Frame, Terminal};
 
-use crate::{Explorer, Player};
+use crate::bookmarks::{self, BookmarksView};
+use crate::equalizer::{EqualizerView, BANDS, FREQUENCIES, MAX_GAIN};
+use crate::history::{self, StatsView};
+use crate::input::BOOKMARK_PROMPT;
+use crate::lyrics::LyricsView;
+use crate::now_playing::{NowPlaying, Position};
+use crate::song::format_time;
+use crate::status_bar::{Level, StatusBar};
+use crate::theme::Theme;
+use crate::visualizer::{Mode, VisualizerView};
+use crate::{cover, ratings, AbLoop, Art, DirState, Explorer, Playback, Repeat, State};
 
-pub fn draw<B: Backend>(
+/// The panels that can go in the layout, and how they're laid out.
+pub struct Panels {
+    pub layout: Pane,
+    pub art: Option<Art>,
+    pub now_playing: NowPlaying,
+    /// Shown in its own pane, or in place of the queue without one, when toggled on.
+    pub lyrics: LyricsView,
+    /// Like lyrics, but for listening stats.
+    pub stats: StatsView,
+    /// And for the equalizer.
+    pub equalizer: EqualizerView,
+    /// And for bookmarks.
+    pub bookmarks: BookmarksView,
+    /// And for the visualizer.
+    pub visualizer: VisualizerView,
+    pub status_bar: StatusBar,
+    /// Which song in the queue is highlighted, kept here since the player might not be.
+    pub queue: ListState,
+}
+
+/// A piece of the screen, holding either a panel or more panes split side by side or on top of
+/// each other.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Pane {
+    #[serde(default)]
+    pub size: Size,
+    #[serde(flatten)]
+    pub content: Content,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Content {
+    Panel { panel: Panel },
+    Split { split: Split, panes: Vec<Pane> },
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Panel {
+    Explorer,
+    Queue,
+    NowPlaying,
+    Lyrics,
+    Stats,
+    Equalizer,
+    Bookmarks,
+    Visualizer,
+    Art,
+}
+
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Split {
+    /// Side by side.
+    Horizontal,
+    /// On top of each other.
+    Vertical,
+}
+
+/// How much of its split a pane takes: a number of cells, a percentage like "30%", or whatever
+/// is left over.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(try_from = "SizeValue")]
+pub enum Size {
+    Cells(u16),
+    Percent(u16),
+    #[default]
+    Fill,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeValue {
+    Cells(u16),
+    Text(String),
+}
+
+impl TryFrom<SizeValue> for Size {
+    type Error = String;
+
+    fn try_from(value: SizeValue) -> Result<Size, String> {
+        let text = match value {
+            SizeValue::Cells(n) => return Ok(Size::Cells(n)),
+            SizeValue::Text(t) => t,
+        };
+        if text == "fill" {
+            return Ok(Size::Fill);
+        }
+        match text.strip_suffix('%').map(str::parse) {
+            Some(Ok(percent)) if percent <= 100 => Ok(Size::Percent(percent)),
+            _ => Err(format!(
+                "bad size \"{}\", expected a number of cells, a percentage or \"fill\"",
+                text
+            )),
+        }
+    }
+}
+
+impl Size {
+    fn constraint(self) -> Constraint {
+        match self {
+            Size::Cells(n) => Constraint::Length(n),
+            Size::Percent(p) => Constraint::Percentage(p),
+            Size::Fill => Constraint::Min(0),
+        }
+    }
+}
+
+impl Pane {
+    fn panel(panel: Panel, size: Size) -> Pane {
+        Pane {
+            size,
+            content: Content::Panel { panel },
+        }
+    }
+
+    fn split(split: Split, size: Size, panes: Vec<Pane>) -> Pane {
+        Pane {
+            size,
+            content: Content::Split { split, panes },
+        }
+    }
+
+    /// The layout from before it could be configured: the explorer on the left, and the queue on
+    /// the right with the cover above it and the now playing panel wherever `now_playing` says.
+    pub fn classic(art: bool, now_playing: Position) -> Pane {
+        let mut right = Vec::new();
+        if art {
+            right.push(Pane::panel(Panel::Art, Size::Percent(50)));
+        }
+        match now_playing {
+            Position::Hidden => right.push(Pane::panel(Panel::Queue, Size::Fill)),
+            Position::Above => {
+                right.push(Pane::panel(
+                    Panel::NowPlaying,
+                    Size::Cells(NowPlaying::HEIGHT),
+                ));
+                right.push(Pane::panel(Panel::Queue, Size::Fill));
+            }
+            Position::Beside => right.push(Pane::split(
+                Split::Horizontal,
+                Size::Fill,
+                vec![
+                    Pane::panel(Panel::Queue, Size::Percent(50)),
+                    Pane::panel(Panel::NowPlaying, Size::Percent(50)),
+                ],
+            )),
+        }
+
+        Pane::split(
+            Split::Horizontal,
+            Size::Fill,
+            vec![
+                Pane::panel(Panel::Explorer, Size::Percent(50)),
+                Pane::split(Split::Vertical, Size::Percent(50), right),
+            ],
+        )
+    }
+
+    /// Whether `panel` is anywhere in this pane.
+    pub fn has(&self, panel: Panel) -> bool {
+        match &self.content {
+            Content::Panel { panel: p } => *p == panel,
+            Content::Split { panes, .. } => panes.iter().any(|p| p.has(panel)),
+        }
+    }
+}
+
+pub fn draw<B: Backend + Write>(
     terminal: &mut Terminal<B>,
     explorer: &mut Explorer,
-    player: &mut Player,
+    player: &dyn Playback,
     search: &str,
+    panels: &mut Panels,
+    theme: &Theme,
 ) -> io::Result<()> {
+    let cover = match panels.art {
+        Some(_) => player
+            .playing()
+            .get(player.index())
+            .and_then(|song| cover::find(song)),
+        None => None,
+    };
+    let cover = cover.as_deref();
+
+    let mut art_area = frame(terminal, explorer, player, search, panels, theme, cover)?;
+    if let Some(art) = &panels.art {
+        if art.needs_clear(cover, art_area) {
+            terminal.clear()?;
+            if let Some(art) = &mut panels.art {
+                art.cleared();
+            }
+            art_area = frame(terminal, explorer, player, search, panels, theme, cover)?;
+        }
+    }
+    if let Some(art) = &mut panels.art {
+        art.place(terminal.backend_mut(), cover, art_area)?;
+    }
+
+    Ok(())
+}
+
+/// Draw everything, returning where the cover art went if it was shown.
+fn frame<B: Backend>(
+    terminal: &mut Terminal<B>,
+    explorer: &mut Explorer,
+    player: &dyn Playback,
+    search: &str,
+    panels: &mut Panels,
+    theme: &Theme,
+    cover: Option<&Path>,
+) -> io::Result<Option<Rect>> {
+    let root = panels.layout.clone();
+    let mut ctx = Context {
+        explorer,
+        player,
+        panels,
+        theme,
+        cover,
+        art_area: None,
+    };
     terminal.draw(|mut f| {
-        let constraints = if search.is_empty() {
-            [Constraint::Percentage(100), Constraint::Percentage(0)]
+        let size = f.size();
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+            .split(size);
+
+        if ctx.visible(&root) {
+            ctx.render(&mut f, &root, rows[0]);
+        }
+
+        if search.is_empty() {
+            ctx.status_line(&mut f, rows[1]);
+        } else if let Some(name) = search.strip_prefix(BOOKMARK_PROMPT) {
+            let prompt = [Text::styled(
+                format!("bookmark name: {}", name),
+                theme.search,
+            )];
+            f.render_widget(Paragraph::new(prompt.iter()), rows[1]);
+        } else {
+            let search_bar = [Text::styled(format!("/{}", search), theme.search)];
+            f.render_widget(Paragraph::new(search_bar.iter()), rows[1]);
+        }
+    })?;
+
+    Ok(ctx.art_area)
+}
+
+/// Everything the panes are drawn from.
+struct Context<'a> {
+    explorer: &'a mut Explorer,
+    player: &'a dyn Playback,
+    panels: &'a mut Panels,
+    theme: &'a Theme,
+    cover: Option<&'a Path>,
+    art_area: Option<Rect>,
+}
+
+impl Context<'_> {
+    /// Whether a pane has anything to show. Hidden panes give their space to the others.
+    fn visible(&self, pane: &Pane) -> bool {
+        match &pane.content {
+            Content::Panel { panel: Panel::Art } => {
+                self.panels.art.is_some() && self.cover.is_some()
+            }
+            Content::Panel {
+                panel: Panel::Lyrics,
+            } => self.panels.lyrics.is_shown(),
+            Content::Panel {
+                panel: Panel::Stats,
+            } => self.panels.stats.is_shown(),
+            Content::Panel {
+                panel: Panel::Equalizer,
+            } => self.panels.equalizer.is_shown(),
+            Content::Panel {
+                panel: Panel::Bookmarks,
+            } => self.panels.bookmarks.is_shown(),
+            Content::Panel {
+                panel: Panel::Visualizer,
+            } => self.panels.visualizer.is_shown(),
+            Content::Panel { .. } => true,
+            Content::Split { panes, .. } => panes.iter().any(|p| self.visible(p)),
+        }
+    }
+
+    fn render<B: Backend>(&mut self, f: &mut Frame<B>, pane: &Pane, area: Rect) {
+        let (split, panes) = match &pane.content {
+            Content::Panel { panel } => return self.panel(f, *panel, area),
+            Content::Split { split, panes } => (split, panes),
+        };
+
+        let visible: Vec<&Pane> = panes.iter().filter(|p| self.visible(p)).collect();
+        let constraints: Vec<Constraint> = visible.iter().map(|p| p.size.constraint()).collect();
+        let areas = Layout::default()
+            .direction(match split {
+                Split::Horizontal => Direction::Horizontal,
+                Split::Vertical => Direction::Vertical,
+            })
+            .constraints(constraints)
+            .split(area);
+        for (pane, area) in visible.into_iter().zip(areas) {
+            self.render(f, pane, area);
+        }
+    }
+
+    /// What the player's doing on the left, and the latest message on the right.
+    fn status_line<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let (player, theme) = (self.player, self.theme);
+        let state = if player.is_stopped() {
+            "■"
+        } else if player.is_paused() {
+            "⏸"
         } else {
-            [Constraint::Percentage(98), Constraint::Percentage(2)]
+            "▶"
         };
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(constraints.as_ref())
-            .split(f.size());
-        let main = Layout::default()
+        let mut status = match player.duration() {
+            Some(d) if !player.is_stopped() => format!(
+                "{} {} / {}",
+                state,
+                format_time(player.position().as_secs_f64()),
+                format_time(d.as_secs_f64())
+            ),
+            _ if !player.is_stopped() => {
+                format!("{} {}", state, format_time(player.position().as_secs_f64()))
+            }
+            _ => state.to_string(),
+        };
+        if player.is_shuffled() {
+            status.push_str("  [shuffle]");
+        }
+        match player.repeat() {
+            Repeat::Off => (),
+            Repeat::All => status.push_str("  [repeat]"),
+            Repeat::One => status.push_str("  [repeat one]"),
+        }
+        match player.ab_loop() {
+            Some(AbLoop { a, b: Some(b) }) => {
+                status.push_str(&format!("  [{} ↻ {}]", format_time(a), format_time(b)))
+            }
+            Some(AbLoop { a, b: None }) => status.push_str(&format!("  [{} ↻]", format_time(a))),
+            None => (),
+        }
+        if player.speed() != 1f32 {
+            status.push_str(&format!("  [{}×]", player.speed()));
+        }
+        status.push_str(&format!("  vol {:.0}%", player.volume() * 100f32));
+
+        let message = match self.panels.status_bar.message() {
+            Some((Level::Error, text)) => Text::styled(text.to_string(), theme.error),
+            Some((Level::Info, text)) => Text::styled(text.to_string(), theme.title),
+            None => Text::raw(""),
+        };
+        let status = [Text::raw(status)];
+        let message = [message];
+        let areas = Layout::default()
             .direction(Direction::Horizontal)
-            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-            .split(chunks[0]);
+            .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+            .split(area);
+        f.render_widget(Paragraph::new(status.iter()).wrap(false), areas[0]);
+        f.render_widget(
+            Paragraph::new(message.iter())
+                .alignment(Alignment::Right)
+                .wrap(false),
+            areas[1],
+        );
+    }
 
-        let dir_strings = explorer.selected_dir().entry_strings();
-        let current_dir = explorer
-            .current_dir_name()
-            .unwrap_or_else(|| "Music".to_string());
-        let block = list(&current_dir, &dir_strings);
-        f.render_stateful_widget(block, main[0], explorer.list_state());
+    /// The explorer as artists, albums and songs side by side, with the current one highlighted.
+    fn columns<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let theme = self.theme;
+        let areas = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints(
+                [
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(35),
+                    Constraint::Percentage(35),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+        let current = match self.explorer.state() {
+            State::Artists => 0,
+            State::Albums => 1,
+            State::Songs => 2,
+        };
+
+        for (i, area) in areas.into_iter().enumerate() {
+            let dirs = self.explorer.dirs();
+            let title = match i {
+                0 => Some("Music".to_string()),
+                _ if dirs[i - 1].entries() > 0 => dirs[i - 1].selected_name(),
+                _ => None,
+            };
+            let title = title.unwrap_or_default();
+            let entries = if i == 2 {
+                song_strings(&dirs[i])
+            } else {
+                dirs[i].entry_strings()
+            };
+            let mut list_state = ListState::default();
+            if dirs[i].entries() > 0 {
+                list_state.select(Some(dirs[i].index()));
+            }
 
-        if !player.playing().is_empty() {
-            player.list_state.select(Some(player.index()));
+            if i == current {
+                let column = list(&title, &entries, theme).highlight_style(theme.highlight);
+                f.render_stateful_widget(column, area, self.explorer.list_state());
+            } else {
+                let column = list(&title, &entries, theme).highlight_style(theme.inactive);
+                f.render_stateful_widget(column, area, &mut list_state);
+            }
         }
-        let playing_strings: Vec<String> = player
-            .playing()
-            .iter()
-            .map(|p| p.file_name().unwrap().to_os_string().into_string().unwrap())
-            .collect();
-        let volume = format!("Volume: {:.0}", player.volume() * 100f32);
-        let block = list(&volume, &playing_strings);
-        f.render_stateful_widget(block, main[1], &mut player.list_state);
+    }
+
+    fn panel<B: Backend>(&mut self, f: &mut Frame<B>, panel: Panel, area: Rect) {
+        let (player, theme) = (self.player, self.theme);
+        match panel {
+            Panel::Explorer if self.explorer.columns() => self.columns(f, area),
+            Panel::Explorer => {
+                let dir_strings = match self.explorer.state() {
+                    State::Songs => song_strings(self.explorer.selected_dir()),
+                    _ => self.explorer.selected_dir().entry_strings(),
+                };
+                let current_dir = self
+                    .explorer
+                    .current_dir_name()
+                    .unwrap_or_else(|| "Music".to_string());
+                let explorer_list =
+                    list(&current_dir, &dir_strings, theme).highlight_style(theme.highlight);
+                f.render_stateful_widget(explorer_list, area, self.explorer.list_state());
+            }
+            // lyrics take over the queue when they're toggled on and don't have a pane of their own
+            Panel::Queue
+                if self.panels.lyrics.is_shown() && !self.panels.layout.has(Panel::Lyrics) =>
+            {
+                lyrics(f, area, player, &mut self.panels.lyrics, theme)
+            }
+            // and so do stats, when lyrics aren't in the way
+            Panel::Queue
+                if self.panels.stats.is_shown() && !self.panels.layout.has(Panel::Stats) =>
+            {
+                stats(f, area, &mut self.panels.stats, theme)
+            }
+            Panel::Queue
+                if self.panels.equalizer.is_shown()
+                    && !self.panels.layout.has(Panel::Equalizer) =>
+            {
+                equalizer(f, area, player, &self.panels.equalizer, theme)
+            }
+            Panel::Queue
+                if self.panels.bookmarks.is_shown()
+                    && !self.panels.layout.has(Panel::Bookmarks) =>
+            {
+                bookmarks(f, area, &self.panels.bookmarks, theme)
+            }
+            Panel::Queue
+                if self.panels.visualizer.is_shown()
+                    && !self.panels.layout.has(Panel::Visualizer) =>
+            {
+                visualizer(f, area, &mut self.panels.visualizer, theme)
+            }
+            Panel::Queue => {
+                if !player.playing().is_empty() {
+                    self.panels.queue.select(Some(player.index()));
+                }
+                let playing_strings: Vec<String> = player
+                    .playing()
+                    .iter()
+                    .map(|p| {
+                        p.file_name()
+                            .unwrap_or(p.as_os_str())
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect();
+                let volume = format!("Volume: {:.0}", player.volume() * 100f32);
+                let (marker, style) = if player.is_paused() {
+                    ("⏸ ", theme.paused)
+                } else {
+                    ("▶ ", theme.playing)
+                };
+                let queue = list(&volume, &playing_strings, theme)
+                    .highlight_style(style)
+                    .highlight_symbol(marker);
+                f.render_stateful_widget(queue, area, &mut self.panels.queue);
+            }
+            Panel::NowPlaying => {
+                let lines = self.panels.now_playing.lines(player, theme);
+                let paragraph = Paragraph::new(lines.iter())
+                    .block(block("Now Playing", theme))
+                    .wrap(false);
+                f.render_widget(paragraph, area);
+            }
+            Panel::Lyrics => lyrics(f, area, player, &mut self.panels.lyrics, theme),
+            Panel::Stats => stats(f, area, &mut self.panels.stats, theme),
+            Panel::Equalizer => equalizer(f, area, player, &self.panels.equalizer, theme),
+            Panel::Bookmarks => bookmarks(f, area, &self.panels.bookmarks, theme),
+            Panel::Visualizer => visualizer(f, area, &mut self.panels.visualizer, theme),
+            Panel::Art => {
+                let (art, cover) = match (&mut self.panels.art, self.cover) {
+                    (Some(a), Some(c)) => (a, c),
+                    _ => return,
+                };
+                let art_block = block("", theme);
+                let inner = art_block.inner(area);
+                f.render_widget(art_block, area);
+                if let Some(half_blocks) = art.half_blocks(cover, inner) {
+                    f.render_widget(half_blocks, inner);
+                }
+                self.art_area = Some(inner);
+            }
+        }
+    }
+}
+
+/// The current song's lyrics, keeping the line being sung highlighted in the middle when they're
+/// synced.
+fn lyrics<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    player: &dyn Playback,
+    view: &mut LyricsView,
+    theme: &Theme,
+) {
+    let block = block("Lyrics", theme);
+    let lyrics = player
+        .playing()
+        .get(player.index())
+        .and_then(|song| view.lyrics(song));
+    let lyrics = match lyrics {
+        Some(l) => l,
+        None => {
+            let text = [Text::raw("No lyrics")];
+            let paragraph = Paragraph::new(text.iter()).block(block);
+            f.render_widget(paragraph, area);
+            return;
+        }
+    };
+
+    let current = lyrics.current(player.position());
+    let lines: Vec<Text> = lyrics
+        .lines()
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let text = format!("{}\n", line.text);
+            if Some(i) == current {
+                Text::styled(text, theme.playing)
+            } else {
+                Text::raw(text)
+            }
+        })
+        .collect();
 
-        let search_bar = Block::default()
-            .borders(Borders::LEFT | Borders::RIGHT | Borders::BOTTOM)
-            .title(&search);
-        f.render_widget(search_bar, chunks[1]);
-    })
+    let scroll = match current {
+        Some(i) => (i as u16).saturating_sub(block.inner(area).height / 2),
+        None => 0,
+    };
+    let paragraph = Paragraph::new(lines.iter())
+        .block(block)
+        .wrap(false)
+        .scroll(scroll);
+    f.render_widget(paragraph, area);
+}
+
+/// Listening time for the last few weeks, the most played artists, albums and tracks, and what
+/// was played last.
+fn stats<B: Backend>(f: &mut Frame<B>, area: Rect, view: &mut StatsView, theme: &Theme) {
+    let stats = view.stats();
+    let mut lines = vec![Text::styled("Listening time\n", theme.title)];
+    let most = stats
+        .weeks
+        .iter()
+        .map(|(_, d)| *d)
+        .max()
+        .unwrap_or_default();
+    for (day, listened) in &stats.weeks {
+        let bar = if most.as_secs() == 0 {
+            0
+        } else {
+            (listened.as_secs() * 20 / most.as_secs()) as usize
+        };
+        lines.push(Text::raw(format!(
+            "  {} {:>9} {}\n",
+            history::date(*day),
+            format_time(listened.as_secs_f64()),
+            "█".repeat(bar)
+        )));
+    }
+
+    for (title, top) in [
+        ("Top artists", &stats.artists),
+        ("Top albums", &stats.albums),
+        ("Top tracks", &stats.tracks),
+    ] {
+        lines.push(Text::styled(format!("\n{}\n", title), theme.title));
+        if top.is_empty() {
+            lines.push(Text::raw("  Nothing yet\n"));
+        }
+        for (name, plays) in top {
+            lines.push(Text::raw(format!("  {:>4}  {}\n", plays, name)));
+        }
+    }
+
+    lines.push(Text::styled("\nRecently played\n", theme.title));
+    if stats.recent.is_empty() {
+        lines.push(Text::raw("  Nothing yet\n"));
+    }
+    for entry in &stats.recent {
+        let skipped = if entry.completed { "" } else { " (skipped)" };
+        lines.push(Text::raw(format!(
+            "  {}  {} - {}{}\n",
+            history::date(entry.at / 86400),
+            entry.artist,
+            entry.title,
+            skipped
+        )));
+    }
+
+    let paragraph = Paragraph::new(lines.iter())
+        .block(block("Stats", theme))
+        .wrap(false);
+    f.render_widget(paragraph, area);
+}
+
+/// The preamp and each band with how far it's turned up or down, the picked one highlighted.
+fn equalizer<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    player: &dyn Playback,
+    view: &EqualizerView,
+    theme: &Theme,
+) {
+    let equalizer = player.equalizer();
+    let bands = std::iter::once(None).chain((0..BANDS).map(Some));
+    let lines: Vec<Text> = bands
+        .map(|band| {
+            let name = match band {
+                None => "preamp".to_string(),
+                Some(b) if FREQUENCIES[b] >= 1000f32 => format!("{}k", FREQUENCIES[b] / 1000f32),
+                Some(b) => format!("{}", FREQUENCIES[b]),
+            };
+            let gain = equalizer.get(band);
+            // a bar out from the middle, left for cuts and right for boosts
+            let cells = (gain.abs() / MAX_GAIN * 12f32).round() as usize;
+            let (left, right) = if gain < 0f32 {
+                (format!("{:>12}", "█".repeat(cells)), String::new())
+            } else {
+                (" ".repeat(12), "█".repeat(cells))
+            };
+            let text = format!("{:>6} {:+5.1} dB {}│{}\n", name, gain, left, right);
+            if band == view.band() {
+                Text::styled(text, theme.highlight)
+            } else {
+                Text::raw(text)
+            }
+        })
+        .collect();
+
+    let paragraph = Paragraph::new(lines.iter())
+        .block(block("Equalizer", theme))
+        .wrap(false);
+    f.render_widget(paragraph, area);
+}
+
+/// Every bookmark with where it is in which song, the picked one highlighted.
+fn bookmarks<B: Backend>(f: &mut Frame<B>, area: Rect, view: &BookmarksView, theme: &Theme) {
+    let entries: Vec<String> = bookmarks::all()
+        .iter()
+        .map(|b| {
+            let file = b.path.file_name().unwrap_or_default().to_string_lossy();
+            format!("{:>7}  {}  ({})", format_time(b.position), b.name, file)
+        })
+        .collect();
+    let mut list_state = ListState::default();
+    if !entries.is_empty() {
+        list_state.select(Some(view.index()));
+    }
+    let list = list("Bookmarks", &entries, theme).highlight_style(theme.highlight);
+    f.render_stateful_widget(list, area, &mut list_state);
+}
+
+/// A spectrum or waveform of what's playing, drawn with block characters.
+fn visualizer<B: Backend>(f: &mut Frame<B>, area: Rect, view: &mut VisualizerView, theme: &Theme) {
+    let block = block("Visualizer", theme);
+    let inner = block.inner(area);
+    let (width, height) = (usize::from(inner.width), usize::from(inner.height));
+    if !view.is_available() {
+        let text = [Text::raw(
+            "Only shown for a player in this bebop, not a daemon",
+        )];
+        let paragraph = Paragraph::new(text.iter()).block(block).wrap(true);
+        f.render_widget(paragraph, area);
+        return;
+    }
+
+    // each row, top to bottom, as what fills each column
+    let rows: Vec<String> = match view.mode() {
+        Mode::Spectrum => {
+            const EIGHTHS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+            let bars = view.spectrum(width);
+            (0..height)
+                .rev()
+                .map(|row| {
+                    bars.iter()
+                        .map(|bar| {
+                            let eighths = (bar * (height * 8) as f32) as usize;
+                            EIGHTHS[eighths.saturating_sub(row * 8).min(8)]
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+        Mode::Waveform => {
+            let columns = view.waveform(width);
+            // which row a sample falls in, with 1 at the top
+            let row_of = |sample: f32| {
+                let row = ((1f32 - sample) / 2f32 * height as f32) as usize;
+                row.min(height.saturating_sub(1))
+            };
+            (0..height)
+                .map(|row| {
+                    columns
+                        .iter()
+                        .map(|(low, high)| {
+                            if (row_of(*high)..=row_of(*low)).contains(&row) {
+                                '█'
+                            } else {
+                                ' '
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        }
+    };
+
+    let lines: Vec<Text> = rows
+        .into_iter()
+        .map(|row| Text::styled(row + "\n", theme.playing))
+        .collect();
+    let paragraph = Paragraph::new(lines.iter()).block(block).wrap(false);
+    f.render_widget(paragraph, area);
+}
+
+/// Songs with whether they're a favorite, their stars and how many times they've been played in
+/// front.
+fn song_strings(dir: &DirState) -> Vec<String> {
+    dir.dir()
+        .iter()
+        .zip(dir.entry_strings())
+        .map(|(path, name)| {
+            let rating = ratings::get(path);
+            let plays = history::played(path).count;
+            let stars = rating.stars.min(ratings::MAX_STARS) as usize;
+            format!(
+                "{} {}{} {:>3} {}",
+                if rating.favorite { "♥" } else { " " },
+                "★".repeat(stars),
+                "☆".repeat(ratings::MAX_STARS as usize - stars),
+                if plays > 0 {
+                    plays.to_string()
+                } else {
+                    String::new()
+                },
+                name
+            )
+        })
+        .collect()
+}
+
+fn block<'a>(title: &'a str, theme: &Theme) -> Block<'a> {
+    Block::default()
+        .title(title)
+        .title_style(theme.title)
+        .borders(Borders::ALL)
+        .border_style(theme.border)
 }
 
 fn list<'a>(
     title: &'a str,
-    items: &'a Vec<String>,
+    items: &'a [String],
+    theme: &Theme,
 ) -> List<'a, impl Iterator<Item = Text<'a>> + 'a> {
-    let block = Block::default().title(title).borders(Borders::ALL);
-    let style = Style::default().bg(Color::Green).modifier(Modifier::BOLD);
-    List::new(items.iter().map(Text::raw))
-        .block(block)
-        .highlight_style(style)
+    List::new(items.iter().map(Text::raw)).block(block(title, theme))
 }
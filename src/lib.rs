@@ -1,12 +1,48 @@
+pub mod art;
+pub mod bookmarks;
+pub mod config;
+pub mod control;
+pub mod cover;
+mod daemon;
 mod dir_state;
+pub mod equalizer;
 mod event;
 mod explorer;
+pub mod history;
 pub mod input;
 pub mod layout;
+pub mod library;
+pub mod logger;
+pub mod lyrics;
+mod mpd;
+mod mpris;
+pub mod now_playing;
 mod player;
+pub mod playlists;
+mod plays;
+mod query;
+pub mod ratings;
+mod remote;
+pub mod screen;
+mod scrobble;
+mod session;
+mod song;
+pub mod speed;
+pub mod status_bar;
+mod status_file;
+pub mod theme;
+pub mod visualizer;
+mod xdg;
 
+pub use art::Art;
+pub use daemon::Daemon;
 pub use dir_state::DirState;
 pub use event::Event;
 pub use explorer::Explorer;
 pub use explorer::State;
-pub use player::Player;
+pub use mpris::Mpris;
+pub use player::{AbLoop, LoopPoint, Playback, Player, Repeat};
+pub use remote::Remote;
+pub use session::Session;
+pub use song::Song;
+pub use status_file::StatusFile;
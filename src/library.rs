@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::explorer::is_song;
+use crate::song::{Details, Song};
+use crate::{status_bar, xdg, Event};
+
+/// How often the music directory's checked for new, changed or deleted songs.
+const SCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the index knows about a song, read from its tags.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Track {
+    pub path: PathBuf,
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    /// In seconds.
+    pub duration: Option<f64>,
+    /// When the file was last changed, in seconds since the epoch, to tell when to read it again.
+    modified: u64,
+}
+
+impl Track {
+    fn read(path: &Path, modified: u64) -> Track {
+        let details = Details::read(path).unwrap_or_else(|_| Details {
+            song: Song::new(path),
+            ..Details::default()
+        });
+        Track {
+            path: path.to_path_buf(),
+            artist: details.song.artist,
+            album: details.song.album,
+            title: details.song.title,
+            genre: details.genre,
+            year: details.year,
+            track: details.track,
+            duration: details.duration.map(|d| d.as_secs_f64()),
+            modified,
+        }
+    }
+}
+
+fn index() -> &'static Mutex<Vec<Track>> {
+    static INDEX: OnceLock<Mutex<Vec<Track>>> = OnceLock::new();
+    INDEX.get_or_init(Mutex::default)
+}
+
+/// Every song in the music directory, sorted by path. Empty until the first scan is done.
+pub fn tracks() -> Vec<Track> {
+    index().lock().unwrap().clone()
+}
+
+/// Keep the index up to date in the background, starting from the last one saved in the cache
+/// directory, and send Event::LibraryChanged whenever something in the music directory changes.
+pub fn watch(music_dir: PathBuf, sender: Sender<Event>) {
+    thread::spawn(move || {
+        let mut tracks: HashMap<PathBuf, Track> = load()
+            .unwrap_or_else(|e| {
+                log::warn!("error reading library cache: {}", e);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|t| (t.path.clone(), t))
+            .collect();
+        *index().lock().unwrap() = sorted(&tracks);
+
+        loop {
+            match scan(&music_dir, &mut tracks) {
+                Ok(true) => {
+                    let sorted = sorted(&tracks);
+                    if let Err(e) = save(&sorted) {
+                        log::warn!("error saving library cache: {}", e);
+                    }
+                    *index().lock().unwrap() = sorted;
+                    if sender.send(Event::LibraryChanged).is_err() {
+                        return;
+                    }
+                }
+                Ok(false) => (),
+                Err(e) => status_bar::error(format!("error scanning music directory: {}", e)),
+            }
+            thread::sleep(SCAN_INTERVAL);
+        }
+    });
+}
+
+/// Bring `tracks` in line with what's in `dir`, only reading the tags of songs that are new or
+/// changed. Returns whether anything was.
+fn scan(dir: &Path, tracks: &mut HashMap<PathBuf, Track>) -> io::Result<bool> {
+    let mut songs = Vec::new();
+    find_songs(dir, &mut songs)?;
+
+    let mut changed = songs.len() != tracks.len();
+    let mut scanned = HashMap::with_capacity(songs.len());
+    for (path, modified) in songs {
+        let track = match tracks.remove(&path) {
+            Some(t) if t.modified == modified => t,
+            _ => {
+                changed = true;
+                Track::read(&path, modified)
+            }
+        };
+        scanned.insert(path, track);
+    }
+    changed |= !tracks.is_empty();
+    *tracks = scanned;
+
+    Ok(changed)
+}
+
+/// Every song below `dir`, however deep, with when it was last changed.
+fn find_songs(dir: &Path, songs: &mut Vec<(PathBuf, u64)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // like a broken link
+        let metadata = match fs::metadata(&path) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            find_songs(&path, songs)?;
+        } else if path.extension().is_some_and(is_song) {
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            songs.push((path, modified));
+        }
+    }
+    Ok(())
+}
+
+fn sorted(tracks: &HashMap<PathBuf, Track>) -> Vec<Track> {
+    let mut sorted: Vec<Track> = tracks.values().cloned().collect();
+    sorted.sort_by(|a, b| a.path.cmp(&b.path));
+    sorted
+}
+
+fn path() -> Option<PathBuf> {
+    xdg::cache_dir().map(|d| d.join("library.json"))
+}
+
+fn load() -> io::Result<Vec<Track>> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(Vec::new()),
+    };
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(serde_json::from_str(&s)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save(tracks: &[Track]) -> io::Result<()> {
+    let path =
+        path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cache directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(tracks)?)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::TempDir;
+
+    #[test]
+    fn scan_and_cache() {
+        xdg::isolate();
+        let dir = TempDir::new("library");
+        fs::create_dir_all(dir.join("Album")).unwrap();
+        for file in ["Album/01 One.flac", "Album/02 Two.mp3", "Album/cover.jpg"] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+
+        let mut tracks = HashMap::new();
+        assert!(scan(&dir, &mut tracks).unwrap());
+        assert!(!scan(&dir, &mut tracks).unwrap());
+        let sorted = sorted(&tracks);
+        let paths: Vec<&PathBuf> = sorted.iter().map(|t| &t.path).collect();
+        assert_eq!(
+            paths,
+            [
+                &dir.join("Album/01 One.flac"),
+                &dir.join("Album/02 Two.mp3")
+            ]
+        );
+
+        save(&sorted).unwrap();
+        let loaded = load().unwrap();
+        assert_eq!(loaded.len(), 2);
+        for (loaded, saved) in loaded.iter().zip(&sorted) {
+            assert_eq!(loaded.path, saved.path);
+            assert_eq!(loaded.title, saved.title);
+            assert_eq!(loaded.modified, saved.modified);
+        }
+
+        fs::remove_file(dir.join("Album/02 Two.mp3")).unwrap();
+        let mut tracks = loaded.into_iter().map(|t| (t.path.clone(), t)).collect();
+        assert!(scan(&dir, &mut tracks).unwrap());
+        assert_eq!(tracks.len(), 1);
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::xdg;
+
+/// Once the log gets this big it's moved to bebop.log.old and started over.
+const MAX_SIZE: u64 = 1024 * 1024;
+
+/// Writes log records to bebop.log in the state directory, one per line.
+struct Logger {
+    level: LevelFilter,
+    file: Mutex<File>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} {}: {}\n",
+            timestamp(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // nowhere left to report a failure to write the log
+        let _ = self.file.lock().unwrap().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
+
+/// Parse a level for --log-level: off, error, warn, info, debug or trace.
+pub fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    s.parse()
+        .map_err(|_| format!("unknown log level \"{}\"", s))
+}
+
+/// Start logging at `level` and up, returning where the log is going.
+pub fn init(level: LevelFilter) -> io::Result<PathBuf> {
+    let path =
+        path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::metadata(&path).is_ok_and(|m| m.len() > MAX_SIZE) {
+        fs::rename(&path, path.with_extension("log.old"))?;
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let logger = Logger {
+        level,
+        file: Mutex::new(file),
+    };
+    log::set_boxed_logger(Box::new(logger)).map_err(io::Error::other)?;
+    log::set_max_level(level);
+
+    Ok(path)
+}
+
+fn path() -> Option<PathBuf> {
+    xdg::state_dir().map(|d| d.join("bebop.log"))
+}
+
+/// The time in UTC, like 2020-06-01T12:34:56.789Z.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil(secs / 86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
+}
+
+/// The date `days` after 1970-01-01, from Howard Hinnant's civil_from_days.
+pub fn civil(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
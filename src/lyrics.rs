@@ -0,0 +1,234 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lofty::prelude::*;
+
+/// A song's lyrics, either plain or with LRC timestamps to follow along with.
+pub struct Lyrics {
+    lines: Vec<Line>,
+    synced: bool,
+}
+
+pub struct Line {
+    pub time: Option<Duration>,
+    pub text: String,
+}
+
+impl Lyrics {
+    /// Look for lyrics in song.lrc or song.txt next to the song, then in its tags (ID3 USLT,
+    /// Vorbis LYRICS and the like).
+    pub fn load(song: &Path) -> Option<Lyrics> {
+        for extension in &["lrc", "txt"] {
+            if let Ok(contents) = fs::read_to_string(song.with_extension(extension)) {
+                return Some(Lyrics::parse(&contents));
+            }
+        }
+
+        let tagged = lofty::read_from_path(song).ok()?;
+        tagged
+            .tags()
+            .iter()
+            .find_map(|tag| tag.get_string(&ItemKey::Lyrics))
+            .map(Lyrics::parse)
+    }
+
+    /// Parse LRC, falling back to plain text if there aren't any timestamps. Lines with more than
+    /// one timestamp show up once for each, and [offset:] is taken into account.
+    pub fn parse(contents: &str) -> Lyrics {
+        let mut offset = 0i64;
+        let mut lines = Vec::new();
+        let mut synced = false;
+
+        for raw in contents.lines() {
+            let mut rest = raw.trim_end_matches('\r');
+            let mut times = Vec::new();
+            while let Some(tag) = rest.strip_prefix('[') {
+                let end = match tag.find(']') {
+                    Some(e) => e,
+                    None => break,
+                };
+                let tag = &tag[..end];
+                if let Some(time) = parse_time(tag) {
+                    times.push(time);
+                } else if let Some(value) = tag.strip_prefix("offset:") {
+                    offset = value.trim().parse().unwrap_or(0);
+                } else if !times.is_empty() || !is_id_tag(tag) {
+                    break;
+                }
+                rest = &rest[end + 2..];
+            }
+
+            let text = rest.trim().to_string();
+            if times.is_empty() {
+                if !rest.is_empty() || !raw.starts_with('[') {
+                    lines.push(Line { time: None, text });
+                }
+            } else {
+                synced = true;
+                lines.extend(times.into_iter().map(|time| Line {
+                    time: Some(time),
+                    text: text.clone(),
+                }));
+            }
+        }
+
+        if synced {
+            // a positive offset means the lyrics should come sooner
+            lines.retain(|l| l.time.is_some());
+            for line in &mut lines {
+                let ms = (line.time.unwrap().as_millis() as i64).saturating_sub(offset);
+                line.time = Some(Duration::from_millis(ms.max(0) as u64));
+            }
+            lines.sort_by_key(|l| l.time);
+        }
+
+        Lyrics { lines, synced }
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// The line being sung at `position`, if the lyrics are synced and it's started.
+    pub fn current(&self, position: Duration) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        self.lines
+            .iter()
+            .rposition(|l| l.time.is_some_and(|t| t <= position))
+    }
+}
+
+/// Parse an LRC timestamp like 01:23.45, 01:23:45 or 01:23.
+fn parse_time(tag: &str) -> Option<Duration> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes: u64 = minutes.trim().parse().ok()?;
+    let (seconds, fraction) = match seconds.find(['.', ':']) {
+        Some(i) => (&seconds[..i], &seconds[i + 1..]),
+        None => (seconds, ""),
+    };
+    let seconds: u64 = seconds.trim().parse().ok()?;
+    // which also means the first three bytes are whole characters
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let millis = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u64>().ok()? * 100,
+        2 => fraction.parse::<u64>().ok()? * 10,
+        _ => fraction[..3].parse::<u64>().ok()?,
+    };
+
+    let seconds = minutes.checked_mul(60)?.checked_add(seconds)?;
+    Some(Duration::from_millis(
+        seconds.checked_mul(1000)?.checked_add(millis)?,
+    ))
+}
+
+/// Whether it's one of the [ar:Artist] style tags at the top of LRC files.
+fn is_id_tag(tag: &str) -> bool {
+    match tag.split_once(':') {
+        Some((key, _)) => key.chars().all(|c| c.is_ascii_alphabetic()),
+        None => false,
+    }
+}
+
+/// The lyrics view, with the current song's lyrics cached so they're only looked for once.
+#[derive(Default)]
+pub struct LyricsView {
+    shown: bool,
+    lyrics: Option<(PathBuf, Option<Lyrics>)>,
+}
+
+impl LyricsView {
+    pub fn is_shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown = !self.shown;
+    }
+
+    pub fn lyrics(&mut self, song: &Path) -> Option<&Lyrics> {
+        let stale = match &self.lyrics {
+            Some((path, _)) => path != song,
+            None => true,
+        };
+        if stale {
+            self.lyrics = Some((song.to_path_buf(), Lyrics::load(song)));
+        }
+
+        self.lyrics.as_ref().and_then(|(_, l)| l.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let ms = |tag| parse_time(tag).map(|d| d.as_millis());
+        assert_eq!(ms("01:23.45"), Some(83_450));
+        assert_eq!(ms("01:23:45"), Some(83_450));
+        assert_eq!(ms("01:23.4"), Some(83_400));
+        assert_eq!(ms("01:23.4567"), Some(83_456));
+        assert_eq!(ms("01:23"), Some(83_000));
+        for tag in [
+            "00:12.1€",
+            "00:12.€€",
+            "00:12.+5",
+            "ar:Artist",
+            "1:2:3:4",
+            "99999999999999999999:00",
+            "999999999999999999:00",
+        ] {
+            assert_eq!(ms(tag), None, "{}", tag);
+        }
+    }
+
+    #[test]
+    fn synced() {
+        let lyrics = Lyrics::parse(
+            "[ar:Artist]\n[offset:500]\n[00:05.00][00:20.00]Chorus\n[00:10.00]Verse\n[00:12.1€]Bad",
+        );
+        assert!(lyrics.is_synced());
+        let lines: Vec<(u128, &str)> = lyrics
+            .lines()
+            .iter()
+            .map(|l| (l.time.unwrap().as_millis(), l.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [(4_500, "Chorus"), (9_500, "Verse"), (19_500, "Chorus")]
+        );
+        assert_eq!(lyrics.current(Duration::from_secs(1)), None);
+        assert_eq!(lyrics.current(Duration::from_secs(10)), Some(1));
+    }
+
+    #[test]
+    fn plain() {
+        let lyrics = Lyrics::parse("One\r\n\nTwo [not a time]");
+        assert!(!lyrics.is_synced());
+        let lines: Vec<&str> = lyrics.lines().iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(lines, ["One", "", "Two [not a time]"]);
+        assert_eq!(lyrics.current(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn huge_offsets() {
+        let lyrics = Lyrics::parse("[offset:-9223372036854775808]\n[00:01.00]Late");
+        assert_eq!(
+            lyrics.lines()[0].time,
+            Some(Duration::from_millis(i64::MAX as u64))
+        );
+        let lyrics = Lyrics::parse("[offset:9223372036854775807]\n[00:01.00]Early");
+        assert_eq!(lyrics.lines()[0].time, Some(Duration::from_millis(0)));
+    }
+}
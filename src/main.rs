@@ -1,132 +1,557 @@
+use std::env;
 use std::io;
 use std::io::Write;
-use std::sync::mpsc::channel;
-use std::thread;
-use std::fs::OpenOptions;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use signal_hook::iterator::Signals;
-use termion::raw::{IntoRawMode, RawTerminal};
-use termion::screen::AlternateScreen;
 use tui::backend::TermionBackend;
+use tui::widgets::ListState;
 use tui::Terminal;
 
-use bebop::input::{Action, handle_input, send_input};
-use bebop::layout::draw;
-use bebop::{Event, Explorer, Player};
+use bebop::bookmarks::{self, Bookmark, BookmarksView};
+use bebop::config::Config;
+use bebop::control::{self, Command, Response};
+use bebop::equalizer::EqualizerView;
+use bebop::history::StatsView;
+use bebop::input::{handle_input, send_input, Action};
+use bebop::layout::{draw, Pane, Panel, Panels};
+use bebop::lyrics::LyricsView;
+use bebop::now_playing::NowPlaying;
+use bebop::status_bar::{self, StatusBar};
+use bebop::theme::Theme;
+use bebop::visualizer::{self, VisualizerView};
+use bebop::{library, logger, playlists, ratings, screen};
+use bebop::{Art, Daemon, Event, Explorer, Playback, Remote, Session};
+use log::LevelFilter;
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Where the player the TUI shows and controls lives.
+// there's only ever one, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum Target {
+    /// In this process, going away with it.
+    Local(Daemon),
+    /// In a daemon somewhere else, attached to over the control socket.
+    Remote(Remote),
+}
+
+impl Target {
+    fn playback(&self) -> &dyn Playback {
+        match self {
+            Target::Local(daemon) => daemon.player(),
+            Target::Remote(remote) => remote,
+        }
+    }
+
+    fn run(&mut self, command: Command) -> Response {
+        match self {
+            Target::Local(daemon) => daemon.run(command),
+            Target::Remote(remote) => {
+                let response = match remote.send(&command) {
+                    Ok(r) => r,
+                    Err(e) => Response::Error(format!("error reaching the daemon: {}", e)),
+                };
+                // show what the command did without waiting for the next tick
+                let _ = remote.refresh();
+                response
+            }
+        }
+    }
+}
+
+/// What the command line asked for.
+struct Options {
+    log_level: LevelFilter,
+    /// Run without the TUI, for `bebop --daemon`.
+    daemon: bool,
+    /// Show a daemon that's already running, for `bebop attach`.
+    attach: bool,
+}
 
 fn main() -> Result<(), io::Error> {
-    let mut player = Player::new(0.2).expect("error creating player");
-    let music_dir = std::env::var("BEBOP_MUSIC_DIR").expect("BEBOP_MUSIC_DIR not set");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("ctl") {
+        return ctl(&args[1..]);
+    }
+    let options = match options(&args) {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("usage: bebop [--daemon] [--log-level off|error|warn|info|debug|trace]");
+            eprintln!("       bebop attach [--log-level LEVEL]");
+            eprintln!("       bebop ctl COMMAND...");
+            process::exit(2);
+        }
+    };
+    if let Err(e) = logger::init(options.log_level) {
+        eprintln!("error opening log: {}", e);
+    }
+    log::info!("starting bebop {}", env!("CARGO_PKG_VERSION"));
+    screen::restore_on_panic();
+
+    let config = Config::load()?;
+    let (event_sender, event_receiver) = channel::<Event>();
+
+    if options.daemon {
+        return daemon(&config, event_sender, event_receiver);
+    }
+    let target = if options.attach {
+        let path = control::socket_path();
+        let remote = Remote::connect(&path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("couldn't attach to {}: {}", path.display(), e),
+            )
+        })?;
+        Target::Remote(remote)
+    } else {
+        Target::Local(Daemon::new(&config, event_sender.clone())?)
+    };
+
+    tui(&config, target, event_sender, event_receiver)
+}
+
+/// Run the player without a terminal until told to quit, for `bebop --daemon`.
+fn daemon(
+    config: &Config,
+    event_sender: Sender<Event>,
+    event_receiver: Receiver<Event>,
+) -> io::Result<()> {
+    let mut daemon = Daemon::new(config, event_sender.clone())?;
+    log::info!(
+        "running as a daemon on {}",
+        control::socket_path().display()
+    );
+
+    let signal_sender = event_sender.clone();
+    let signals = Signals::new([
+        signal_hook::SIGTERM,
+        signal_hook::SIGINT,
+        signal_hook::SIGHUP,
+    ])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            // the terminal it was started from closing is the whole point
+            if signal == signal_hook::SIGHUP {
+                continue;
+            }
+            if let Err(e) = signal_sender.send(Event::Quit(128 + signal)) {
+                status_bar::error(format!("error writing to event channel: {}", e));
+            }
+        }
+    });
+
+    quit_on_panic(event_sender.clone());
+    send_every(SAVE_INTERVAL, event_sender.clone(), || Event::Save);
+    // keeps the position up to date for MPRIS and the status file
+    send_every(TICK_INTERVAL, event_sender, || Event::Tick);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+        daemon.update();
+        match event_receiver.recv() {
+            Ok(Event::Quit(status)) => return status,
+            Ok(event) => {
+                if let Some(status) = daemon.handle(event) {
+                    return status;
+                }
+            }
+            Err(e) => status_bar::error(format!("error receiving event: {}", e)),
+        }
+    }));
+
+    log::info!("quitting");
+    if let Err(e) = daemon.save() {
+        eprintln!("error saving session: {}", e);
+    }
+
+    match result {
+        Ok(0) => Ok(()),
+        Ok(status) => process::exit(status),
+        Err(_) => process::exit(101),
+    }
+}
+
+/// Show the explorer and whatever `target` is playing until it's time to quit.
+fn tui(
+    config: &Config,
+    mut target: Target,
+    event_sender: Sender<Event>,
+    event_receiver: Receiver<Event>,
+) -> io::Result<()> {
+    let music_dir = config
+        .music_dir
+        .as_ref()
+        .expect("BEBOP_MUSIC_DIR not set and no music_dir in config");
     let mut explorer = Explorer::new(music_dir)?;
-    let status_file_path = std::env::var("BEBOP_STATUS_FILE_PATH").unwrap_or_default();
+    explorer.set_columns(config.explorer.columns);
+    ratings::init(&config.ratings);
+    let mut views = ratings::views();
+    views.extend(playlists::views(&config.playlists));
+    explorer.set_views(views);
+    library::watch(music_dir.clone(), event_sender.clone());
+    if let Err(e) = explorer.restore(&location(&target)) {
+        status_bar::error(format!("error restoring location: {}", e));
+    }
+    let theme =
+        Theme::new(&config.theme).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let layout = match &config.layout {
+        Some(layout) => layout.clone(),
+        None => Pane::classic(config.art.enabled, config.now_playing.position),
+    };
+    let mut panels = Panels {
+        art: if layout.has(Panel::Art) {
+            Some(Art::new(config.art.protocol))
+        } else {
+            None
+        },
+        layout,
+        now_playing: NowPlaying::default(),
+        lyrics: LyricsView::default(),
+        stats: StatsView::default(),
+        equalizer: EqualizerView::default(),
+        bookmarks: BookmarksView::default(),
+        visualizer: VisualizerView::new(match &target {
+            Target::Local(daemon) => Some(daemon.player().samples()),
+            Target::Remote(_) => None,
+        }),
+        status_bar: StatusBar::default(),
+        queue: ListState::default(),
+    };
 
-    let stdout = io::stdout().into_raw_mode()?;
-    let screen = AlternateScreen::from(stdout);
-    let backend = TermionBackend::new(screen);
+    let screen = screen::enter()?;
+    let backend = TermionBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
 
-    terminal.hide_cursor()?;
-
     let mut search = String::new();
-    let (event_sender, event_receiver) = channel::<Event>();
 
     let input_sender = event_sender.clone();
     thread::spawn(move || {
         send_input(input_sender);
     });
 
-    let resize_sender = event_sender.clone();
-    let signals = Signals::new(&[signal_hook::SIGWINCH])?;
+    let signal_sender = event_sender.clone();
+    let signals = Signals::new([
+        signal_hook::SIGWINCH,
+        signal_hook::SIGTERM,
+        signal_hook::SIGINT,
+        signal_hook::SIGHUP,
+        libc::SIGTSTP,
+        signal_hook::SIGCONT,
+    ])?;
     thread::spawn(move || {
-        for _ in signals.forever() {
-            if let Err(e) = resize_sender.send(Event::Redraw) {
-                eprintln!("error writing to event channel: {}", e);
+        for signal in signals.forever() {
+            let event = match signal {
+                signal_hook::SIGWINCH => Event::Redraw,
+                libc::SIGTSTP => Event::Suspend,
+                signal_hook::SIGCONT => Event::Resume,
+                // the usual 128 + signal number
+                s => Event::Quit(128 + s),
+            };
+            if let Err(e) = signal_sender.send(event) {
+                status_bar::error(format!("error writing to event channel: {}", e));
             }
         }
     });
 
-    loop {
-        //FIXME: this is really long and bad and gross.
-        //     ewwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwww
-        draw::<TermionBackend<AlternateScreen<RawTerminal<io::Stdout>>>>(
-            &mut terminal,
-            &mut explorer,
-            &mut player,
-            &search,
-        )?;
+    quit_on_panic(event_sender.clone());
+    send_every(SAVE_INTERVAL, event_sender.clone(), || Event::Save);
+    send_every(TICK_INTERVAL, event_sender.clone(), || Event::Tick);
+    animate(panels.visualizer.shown(), event_sender);
 
-        match event_receiver.recv() {
-            Ok(event) => match handle_input(
-                    event,
-                    &mut explorer,
-                    &mut player,
-                    &mut search,
-                ) {
-                Ok(a) => match a {
-                    Action::Play(song_switch_receiver) => {
-                        let songs = player.playing().clone();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| -> io::Result<i32> {
+        loop {
+            //FIXME: this is really long and bad and gross.
+            //     ewwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwwww
+            draw::<TermionBackend<io::Stdout>>(
+                &mut terminal,
+                &mut explorer,
+                target.playback(),
+                &search,
+                &mut panels,
+                &theme,
+            )?;
+            if let Target::Local(daemon) = &mut target {
+                daemon.update();
+            }
 
-                        let path = if status_file_path.is_empty() {
-                            String::new()
-                        } else {
-                            status_file_path.to_owned()
-                        };
-                        if let Err(e) = write_status(&path, &songs[player.index()]) {
-                            eprintln!("error writing status: {}", e);
+            let key = match event_receiver.recv() {
+                Ok(Event::Input(key)) => key,
+                Ok(Event::Tick) => {
+                    if let Target::Remote(remote) = &mut target {
+                        if let Err(e) = remote.refresh() {
+                            status_bar::error(format!("error reaching the daemon: {}", e));
+                        }
+                    }
+                    continue;
+                }
+                Ok(Event::Quit(status)) => {
+                    log::info!("quitting with status {}", status);
+                    return Ok(status);
+                }
+                Ok(Event::Suspend) => {
+                    suspend(&mut terminal, &mut panels)?;
+                    continue;
+                }
+                Ok(Event::Resume) => {
+                    resume(&mut terminal, &mut panels)?;
+                    continue;
+                }
+                Ok(Event::LibraryChanged) => {
+                    explorer.refresh();
+                    continue;
+                }
+                Ok(event) => {
+                    if let Target::Local(daemon) = &mut target {
+                        daemon.set_location(explorer.location());
+                        if let Some(status) = daemon.handle(event) {
+                            return Ok(status);
                         }
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    status_bar::error(format!("error receiving event: {}", e));
+                    continue;
+                }
+            };
 
-                        let redraw_sender = event_sender.clone();
-                        thread::spawn(move || {
-                            while let Ok(i) = song_switch_receiver.recv() {
-                                if let Err(e) = redraw_sender.send(Event::Redraw) {
-                                    eprintln!("error sending redraw on song change: {}", e);
-                                }
-                                if i != 0 {
-                                    if let Err(e) = write_status(&path, &songs[songs.len() - i]) {
-                                        eprintln!("error writing status: {}", e);
-                                    }
-                                }
-                            }
-                        });
+            match handle_input(key, &mut explorer, target.playback(), &mut search) {
+                Ok(a) => match a {
+                    Action::Command(command) => {
+                        if let Response::Error(e) = target.run(command) {
+                            status_bar::error(e);
+                        }
+                    }
+                    Action::Quit => return Ok(0),
+                    Action::Suspend => suspend(&mut terminal, &mut panels)?,
+                    Action::ToggleLyrics => panels.lyrics.toggle(),
+                    Action::ToggleStats => panels.stats.toggle(),
+                    Action::ToggleVisualizer => panels.visualizer.toggle(),
+                    Action::NextVisualization if panels.visualizer.is_shown() => {
+                        panels.visualizer.next_mode()
+                    }
+                    Action::NextVisualization => (),
+                    Action::ToggleBookmarks => panels.bookmarks.toggle(),
+                    // the bookmarks' keys only do anything while they can be seen
+                    Action::SelectBookmark(_) | Action::OpenBookmark | Action::DeleteBookmark
+                        if !panels.bookmarks.is_shown() => {}
+                    Action::SelectBookmark(by) => panels.bookmarks.select(by),
+                    Action::OpenBookmark => {
+                        if let Some(bookmark) = panels.bookmarks.selected() {
+                            open_bookmark(&mut target, bookmark);
+                        }
+                    }
+                    Action::DeleteBookmark => {
+                        if let Some(bookmark) = panels.bookmarks.selected() {
+                            bookmarks::remove(&bookmark);
+                        }
+                    }
+                    Action::ToggleEqualizer => panels.equalizer.toggle(),
+                    // the equalizer's keys only do anything while it can be seen
+                    _ if !panels.equalizer.is_shown() => (),
+                    Action::SelectBand(by) => panels.equalizer.select(by),
+                    Action::Gain(db) => {
+                        let command = Command::Gain {
+                            band: panels.equalizer.band(),
+                            db,
+                            relative: true,
+                        };
+                        if let Response::Error(e) = target.run(command) {
+                            status_bar::error(e);
+                        }
+                    }
+                    Action::NextPreset => {
+                        let preset = panels.equalizer.next_preset();
+                        if let Response::Error(e) = target.run(Command::Preset { preset }) {
+                            status_bar::error(e);
+                        }
                     }
-                    Action::Quit => break,
                     Action::None => (),
-                }
-                Err(e) => eprintln!("error handling input: {}", e),
+                },
+                Err(e) => status_bar::error(format!("error handling input: {}", e)),
             }
-            Err(e) => println!("error receiving event: {}", e),
         }
+    }));
+
+    // out of the alternate screen, so anything printed sticks around
+    drop(terminal);
+    drop(screen);
+    log::info!("quitting");
+    // an attached client leaves the daemon to save its own session
+    if let Target::Local(daemon) = &mut target {
+        daemon.set_location(explorer.location());
+        if let Err(e) = daemon.save() {
+            eprintln!("error saving session: {}", e);
+        }
+    }
+
+    match result {
+        Ok(Ok(0)) => Ok(()),
+        Ok(Ok(status)) => process::exit(status),
+        Ok(Err(e)) => Err(e),
+        // the panic hook already said what happened
+        Err(_) => process::exit(101),
     }
+}
+
+/// Stop like Ctrl-Z would, with the terminal put back first, and pick up where things left off
+/// once continued.
+fn suspend<W: Write>(
+    terminal: &mut Terminal<TermionBackend<W>>,
+    panels: &mut Panels,
+) -> io::Result<()> {
+    log::info!("suspending");
+    screen::suspend()?;
+    resume(terminal, panels)
+}
 
+/// Take the terminal back after being stopped, and draw everything again from scratch.
+fn resume<W: Write>(
+    terminal: &mut Terminal<TermionBackend<W>>,
+    panels: &mut Panels,
+) -> io::Result<()> {
+    screen::resume()?;
+    terminal.clear()?;
+    if let Some(art) = &mut panels.art {
+        art.forget();
+    }
     Ok(())
 }
 
-// TODO: move this to a new file along with the song switch stuff, maybe
-fn write_status(path: &str, playing: &PathBuf) -> io::Result<()> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(path)?;
-    // FIXME: these are fuckin dumb
-    // TODO: strip ".mp3" and maybe track # from filename
-    let song_name = playing.file_name().unwrap().to_str().unwrap();
-    let artist = playing
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap();
-
-    write!(
-        &mut file,
-        "{}\n{}\n{}/cover.jpg\n",
-        song_name,
-        artist,
-        playing.parent().unwrap().to_str().unwrap()
-    )
+/// Parse the arguments for running the player: --daemon or attach, and --log-level LEVEL or
+/// --log-level=LEVEL, which is info without it.
+fn options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        log_level: LevelFilter::Info,
+        daemon: false,
+        attach: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--daemon" {
+            options.daemon = true;
+            continue;
+        }
+        if arg == "attach" {
+            options.attach = true;
+            continue;
+        }
+        let value = match arg.strip_prefix("--log-level") {
+            Some("") => args.next().ok_or("--log-level needs a level")?.as_str(),
+            Some(v) if v.starts_with('=') => &v[1..],
+            _ => return Err(format!("unknown argument \"{}\"", arg)),
+        };
+        options.log_level = logger::parse_level(value)?;
+    }
+    if options.daemon && options.attach {
+        return Err("can't attach to a daemon while being one".to_string());
+    }
+
+    Ok(options)
+}
+
+/// Where the explorer was last time: from the daemon when it's here, or from the saved session
+/// otherwise.
+fn location(target: &Target) -> Vec<PathBuf> {
+    match target {
+        Target::Local(daemon) => daemon.location().to_vec(),
+        Target::Remote(_) => match Session::load() {
+            Ok(Some(session)) => session.location().to_vec(),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                status_bar::error(format!("error loading session: {}", e));
+                Vec::new()
+            }
+        },
+    }
+}
+
+/// Handle `bebop ctl`, sending a command to the running player.
+fn ctl(args: &[String]) -> io::Result<()> {
+    let command = match control::parse_args(args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "usage: bebop ctl play|pause|toggle|next|prev|clear|shuffle|status|quit|jump INDEX|seek [+-]SECONDS|volume [+-]PERCENT|repeat off|all|one|enqueue PATH...|load PATH..."
+            );
+            process::exit(2);
+        }
+    };
+
+    match control::send(control::socket_path(), &command)? {
+        Response::Ok => (),
+        Response::Status(status) => println!("{}", serde_json::to_string_pretty(&status)?),
+        Response::Error(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Quit once the session's saved when anything panics, not just the main thread.
+fn quit_on_panic(event_sender: Sender<Event>) {
+    let panic_sender = Mutex::new(event_sender);
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        hook(info);
+        if let Ok(sender) = panic_sender.lock() {
+            let _ = sender.send(Event::Quit(101));
+        }
+    }));
+}
+
+/// Send an event every `interval` until the event loop's gone.
+fn send_every(interval: Duration, event_sender: Sender<Event>, event: fn() -> Event) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if event_sender.send(event()).is_err() {
+            break;
+        }
+    });
+}
+
+/// Redraw up to visualizer::FPS times a second while `shown` is, so the visualizer keeps moving.
+fn animate(shown: Arc<AtomicBool>, event_sender: Sender<Event>) {
+    let interval = Duration::from_secs(1) / visualizer::FPS;
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if shown.load(Ordering::Relaxed) && event_sender.send(Event::Redraw).is_err() {
+            break;
+        }
+    });
+}
+
+/// Play from a bookmark, staying in the queue if its song is the one playing.
+fn open_bookmark(target: &mut Target, bookmark: Bookmark) {
+    let playback = target.playback();
+    let current = playback.playing().get(playback.index());
+    let mut commands = Vec::new();
+    if current != Some(&bookmark.path) || playback.is_stopped() {
+        commands.push(Command::Load {
+            paths: vec![bookmark.path],
+            start: 0,
+        });
+    }
+    commands.push(Command::Seek {
+        seconds: bookmark.position,
+        relative: false,
+    });
+    for command in commands {
+        if let Response::Error(e) = target.run(command) {
+            status_bar::error(e);
+            return;
+        }
+    }
 }
@@ -0,0 +1,942 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write as _;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::control::{self, Command, Response, Status};
+use crate::explorer::is_song;
+use crate::song::Details;
+use crate::{status_bar, Event, Repeat, Song};
+
+/// The protocol version we claim to speak, for clients that check.
+const VERSION: &str = "0.21.0";
+/// How often idle clients check whether anything changed.
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+/// Error codes for ACK lines, from MPD's protocol.h.
+const ACK_ARG: u32 = 2;
+const ACK_UNKNOWN: u32 = 5;
+const ACK_NO_EXIST: u32 = 50;
+const ACK_SYSTEM: u32 = 52;
+
+/// Everything we answer, for the commands command.
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "commands",
+    "currentsong",
+    "find",
+    "idle",
+    "lsinfo",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "previous",
+    "random",
+    "repeat",
+    "search",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "status",
+    "stop",
+    "tagtypes",
+    "volume",
+];
+
+/// Start speaking the MPD protocol on the TCP address and Unix socket from the config, if there
+/// are any. Commands turn into Events the same as the control socket, and the library is the
+/// music directory, with songs named by their path inside it.
+///
+/// Songs are identified by where they are in the queue, so a song's id is its position.
+pub fn listen(config: &Config, sender: Sender<Event>) -> io::Result<()> {
+    let mpd = &config.mpd;
+    if mpd.address.is_none() && mpd.socket.is_none() {
+        return Ok(());
+    }
+    let library = match &config.music_dir {
+        Some(dir) => Library {
+            root: dir.to_path_buf(),
+        },
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the MPD server needs a music_dir",
+            ))
+        }
+    };
+
+    if let Some(address) = &mpd.address {
+        let listener = TcpListener::bind(address)?;
+        let (library, sender) = (library.clone(), sender.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                accept(stream, library.clone(), sender.clone());
+            }
+        });
+    }
+    if let Some(path) = &mpd.socket {
+        let listener = control::bind(path)?;
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                accept(stream, library.clone(), sender.clone());
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// The streams clients connect with.
+trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<TcpStream> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<UnixStream> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+fn accept<S: Stream>(stream: io::Result<S>, library: Library, sender: Sender<Event>) {
+    let stream = match stream {
+        Ok(s) => s,
+        Err(e) => {
+            status_bar::error(format!("error accepting MPD client: {}", e));
+            return;
+        }
+    };
+    thread::spawn(move || {
+        let client = match stream.try_clone() {
+            Ok(writer) => Client {
+                reader: BufReader::new(stream),
+                writer,
+                library,
+                sender,
+            },
+            Err(e) => {
+                status_bar::error(format!("error serving MPD client: {}", e));
+                return;
+            }
+        };
+        if let Err(e) = client.serve() {
+            status_bar::error(format!("error serving MPD client: {}", e));
+        }
+    });
+}
+
+/// An error for the client, sent as `ACK [code@index] {command} message`.
+struct Ack {
+    code: u32,
+    command: String,
+    message: String,
+}
+
+impl Ack {
+    fn new<S: Into<String>>(code: u32, command: &str, message: S) -> Ack {
+        Ack {
+            code,
+            command: command.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+struct Client<S: Stream> {
+    reader: BufReader<S>,
+    writer: S,
+    library: Library,
+    sender: Sender<Event>,
+}
+
+impl<S: Stream> Client<S> {
+    fn serve(mut self) -> io::Result<()> {
+        writeln!(self.writer, "OK MPD {}", VERSION)?;
+
+        // commands between command_list_begin and command_list_end, and whether each one gets a
+        // list_OK
+        let mut list: Option<(Vec<String>, bool)> = None;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let line = line.trim_end_matches(['\n', '\r']).to_string();
+
+            match (line.as_str(), &mut list) {
+                ("command_list_begin", None) => list = Some((Vec::new(), false)),
+                ("command_list_ok_begin", None) => list = Some((Vec::new(), true)),
+                ("command_list_end", Some(_)) => {
+                    let (commands, list_ok) = list.take().unwrap();
+                    self.run_list(&commands, list_ok)?;
+                }
+                (_, Some((commands, _))) => commands.push(line),
+                ("close", None) => return Ok(()),
+                // only means something while idle, and isn't answered otherwise
+                ("noidle", None) => (),
+                (l, None) if l == "idle" || l.starts_with("idle ") => {
+                    if !self.idle(&line)? {
+                        return Ok(());
+                    }
+                }
+                (_, None) => self.run_list(&[line], false)?,
+            }
+        }
+    }
+
+    /// Run commands and answer with everything they printed, stopping at the first error.
+    fn run_list(&mut self, commands: &[String], list_ok: bool) -> io::Result<()> {
+        let mut out = String::new();
+        for (i, line) in commands.iter().enumerate() {
+            match self.run(line, &mut out) {
+                Ok(()) if list_ok => out.push_str("list_OK\n"),
+                Ok(()) => (),
+                Err(ack) => {
+                    let _ = writeln!(
+                        out,
+                        "ACK [{}@{}] {{{}}} {}",
+                        ack.code, i, ack.command, ack.message
+                    );
+                    return self.writer.write_all(out.as_bytes());
+                }
+            }
+        }
+        out.push_str("OK\n");
+        self.writer.write_all(out.as_bytes())
+    }
+
+    fn run(&mut self, line: &str, out: &mut String) -> Result<(), Ack> {
+        let words = split(line).ok_or_else(|| Ack::new(ACK_ARG, "", "bad quoting"))?;
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (name.as_str(), args),
+            None => return Err(Ack::new(ACK_UNKNOWN, "", "no command given")),
+        };
+        let arg = |i: usize| -> Result<&str, Ack> {
+            args.get(i)
+                .map(String::as_str)
+                .ok_or_else(|| Ack::new(ACK_ARG, name, "missing argument"))
+        };
+
+        match name {
+            "ping" => (),
+            "status" => {
+                let status = self.status(name)?;
+                write_status(out, &status);
+            }
+            "currentsong" => {
+                let status = self.status(name)?;
+                if let Some(song) = status.queue.get(status.index) {
+                    self.write_song(out, song);
+                    write_position(out, status.index);
+                }
+            }
+            "play" | "playid" => match args.first() {
+                Some(i) => {
+                    let index = self.index(name, i)?;
+                    self.command(name, Command::Jump { index })?;
+                }
+                None => self.command(name, Command::Play)?,
+            },
+            "pause" => match args.first().map(String::as_str) {
+                Some("1") => self.command(name, Command::Pause)?,
+                Some("0") => self.command(name, Command::Play)?,
+                Some(a) => return Err(Ack::new(ACK_ARG, name, format!("bad state \"{}\"", a))),
+                None => self.command(name, Command::Toggle)?,
+            },
+            // there's no stopped with a queue, so pausing is the closest
+            "stop" => self.command(name, Command::Pause)?,
+            "next" => self.command(name, Command::Next)?,
+            "previous" => self.command(name, Command::Prev)?,
+            "seek" | "seekid" => {
+                let index = self.index(name, arg(0)?)?;
+                let seconds = seconds(name, arg(1)?)?;
+                if index != self.status(name)?.index {
+                    self.command(name, Command::Jump { index })?;
+                }
+                self.command(
+                    name,
+                    Command::Seek {
+                        seconds,
+                        relative: false,
+                    },
+                )?;
+            }
+            "seekcur" => {
+                let time = arg(0)?;
+                let relative = time.starts_with('+') || time.starts_with('-');
+                let seconds = seconds(name, time)?;
+                self.command(name, Command::Seek { seconds, relative })?;
+            }
+            "setvol" | "volume" => {
+                let percent = number(name, arg(0)?)?;
+                let relative = name == "volume";
+                let lowest = if relative { -100f64 } else { 0f64 };
+                if !(lowest..=100f64).contains(&percent) {
+                    return Err(Ack::new(ACK_ARG, name, "volume out of range"));
+                }
+                let percent = percent as f32;
+                self.command(name, Command::Volume { percent, relative })?;
+            }
+            "random" => {
+                let on = flag(name, arg(0)?)?;
+                if on != self.status(name)?.shuffle {
+                    self.command(name, Command::Shuffle)?;
+                }
+            }
+            "repeat" | "single" => {
+                let on = flag(name, arg(0)?)?;
+                let current = self.status(name)?.repeat;
+                let (repeat, single) = if name == "repeat" {
+                    (on, current == Repeat::One)
+                } else {
+                    (current != Repeat::Off, on)
+                };
+                let mode = match (repeat, single) {
+                    (true, true) => Repeat::One,
+                    (true, false) => Repeat::All,
+                    (false, _) => Repeat::Off,
+                };
+                self.command(name, Command::Repeat { mode })?;
+            }
+            "playlistinfo" | "playlistid" => {
+                let status = self.status(name)?;
+                let range = match args.first() {
+                    Some(a) => range(name, a, status.queue.len())?,
+                    None => 0..status.queue.len(),
+                };
+                for i in range {
+                    self.write_song(out, &status.queue[i]);
+                    write_position(out, i);
+                }
+            }
+            "plchanges" => {
+                let status = self.status(name)?;
+                if arg(0)? != version(&status.queue).to_string() {
+                    for (i, song) in status.queue.iter().enumerate() {
+                        self.write_song(out, song);
+                        write_position(out, i);
+                    }
+                }
+            }
+            "add" | "addid" => {
+                let path = self.library.resolve(name, arg(0)?)?;
+                let paths = if path.is_dir() {
+                    self.library
+                        .songs(&path)
+                        .map_err(|e| Ack::new(ACK_SYSTEM, name, e.to_string()))?
+                } else if path.extension().is_some_and(is_song) {
+                    vec![path]
+                } else {
+                    return Err(Ack::new(ACK_ARG, name, "not a song"));
+                };
+                let position = self.status(name)?.queue.len();
+                self.command(name, Command::Enqueue { paths })?;
+                if name == "addid" {
+                    let _ = writeln!(out, "Id: {}", position);
+                }
+            }
+            "clear" => self.command(name, Command::Clear)?,
+            "lsinfo" => {
+                let path = self.library.resolve(name, args.first().map_or("", |a| a))?;
+                if !path.is_dir() {
+                    self.write_song(out, &path);
+                    return Ok(());
+                }
+                let mut entries: Vec<PathBuf> = fs::read_dir(&path)
+                    .map_err(|e| Ack::new(ACK_SYSTEM, name, e.to_string()))?
+                    .filter_map(|e| e.ok().map(|e| e.path()))
+                    .collect();
+                entries.sort();
+                for entry in entries {
+                    if entry.is_dir() {
+                        let _ = writeln!(out, "directory: {}", self.library.uri(&entry));
+                    } else if entry.extension().is_some_and(is_song) {
+                        self.write_song(out, &entry);
+                    }
+                }
+            }
+            "search" | "find" => {
+                if args.is_empty() || args.len() % 2 != 0 {
+                    return Err(Ack::new(ACK_ARG, name, "expected TYPE WHAT pairs"));
+                }
+                let songs = self
+                    .library
+                    .songs(&self.library.root)
+                    .map_err(|e| Ack::new(ACK_SYSTEM, name, e.to_string()))?;
+                for song in songs {
+                    let details = read(&song);
+                    let matches = args.chunks(2).all(|pair| {
+                        let fields = match pair[0].to_lowercase().as_str() {
+                            "artist" => vec![details.song.artist.clone()],
+                            "album" => vec![details.song.album.clone()],
+                            "title" => vec![details.song.title.clone()],
+                            "file" => vec![self.library.uri(&song)],
+                            _ => vec![
+                                details.song.artist.clone(),
+                                details.song.album.clone(),
+                                details.song.title.clone(),
+                                self.library.uri(&song),
+                            ],
+                        };
+                        fields.iter().any(|f| {
+                            if name == "find" {
+                                *f == pair[1]
+                            } else {
+                                f.to_lowercase().contains(&pair[1].to_lowercase())
+                            }
+                        })
+                    });
+                    if matches {
+                        self.write_song(out, &song);
+                    }
+                }
+            }
+            "outputs" => out.push_str("outputid: 0\noutputname: bebop\noutputenabled: 1\n"),
+            "tagtypes" => out.push_str("tagtype: Artist\ntagtype: Album\ntagtype: Title\n"),
+            "commands" => {
+                for command in COMMANDS {
+                    let _ = writeln!(out, "command: {}", command);
+                }
+            }
+            "notcommands" => (),
+            _ => {
+                return Err(Ack::new(
+                    ACK_UNKNOWN,
+                    "",
+                    format!("unknown command \"{}\"", name),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the player to change, answering with what changed once it does or as soon as the
+    /// client says noidle. Returns false if the client hung up.
+    fn idle(&mut self, line: &str) -> io::Result<bool> {
+        let wanted: Vec<String> = split(line)
+            .unwrap_or_default()
+            .into_iter()
+            .skip(1)
+            .collect();
+        let before = match control::request(&self.sender, Command::Status) {
+            Some(Response::Status(s)) => s,
+            _ => return Ok(false),
+        };
+
+        self.reader.get_ref().set_read_timeout(Some(IDLE_POLL))?;
+        let mut line = String::new();
+        let changed = loop {
+            match self.reader.read_line(&mut line) {
+                Ok(0) => return Ok(false),
+                // anything but noidle isn't allowed while idle, so treat it like noidle
+                Ok(_) => break Vec::new(),
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    let now = match control::request(&self.sender, Command::Status) {
+                        Some(Response::Status(s)) => s,
+                        _ => return Ok(false),
+                    };
+                    let changed: Vec<&str> = changes(&before, &now)
+                        .into_iter()
+                        .filter(|c| wanted.is_empty() || wanted.iter().any(|w| w == c))
+                        .collect();
+                    if !changed.is_empty() {
+                        break changed;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        self.reader.get_ref().set_read_timeout(None)?;
+
+        let mut out = String::new();
+        for subsystem in changed {
+            let _ = writeln!(out, "changed: {}", subsystem);
+        }
+        out.push_str("OK\n");
+        self.writer.write_all(out.as_bytes())?;
+        Ok(true)
+    }
+
+    fn command(&self, name: &str, command: Command) -> Result<(), Ack> {
+        match control::request(&self.sender, command) {
+            Some(Response::Error(e)) => Err(Ack::new(ACK_SYSTEM, name, e)),
+            Some(_) => Ok(()),
+            None => Err(Ack::new(ACK_SYSTEM, name, "player went away")),
+        }
+    }
+
+    fn status(&self, name: &str) -> Result<Status, Ack> {
+        match control::request(&self.sender, Command::Status) {
+            Some(Response::Status(status)) => Ok(status),
+            Some(Response::Error(e)) => Err(Ack::new(ACK_SYSTEM, name, e)),
+            _ => Err(Ack::new(ACK_SYSTEM, name, "player went away")),
+        }
+    }
+
+    /// A position in the queue, checked against how long it is.
+    fn index(&self, name: &str, arg: &str) -> Result<usize, Ack> {
+        let index: usize = arg
+            .parse()
+            .map_err(|_| Ack::new(ACK_ARG, name, format!("bad song index \"{}\"", arg)))?;
+        if index >= self.status(name)?.queue.len() {
+            return Err(Ack::new(ACK_ARG, name, "bad song index"));
+        }
+        Ok(index)
+    }
+
+    fn write_song(&self, out: &mut String, path: &Path) {
+        let details = read(path);
+        let _ = writeln!(out, "file: {}", self.library.uri(path));
+        let song = &details.song;
+        for (tag, value) in [
+            ("Artist", &song.artist),
+            ("Album", &song.album),
+            ("Title", &song.title),
+        ] {
+            if !value.is_empty() {
+                let _ = writeln!(out, "{}: {}", tag, value);
+            }
+        }
+        if let Some(track) = details.track {
+            let _ = writeln!(out, "Track: {}", track);
+        }
+        if let Some(year) = details.year {
+            let _ = writeln!(out, "Date: {}", year);
+        }
+        if let Some(duration) = details.duration {
+            let _ = writeln!(out, "Time: {}", duration.as_secs());
+            let _ = writeln!(out, "duration: {:.3}", duration.as_secs_f64());
+        }
+    }
+}
+
+/// The music directory, with songs named by their path inside it like MPD does.
+#[derive(Clone)]
+struct Library {
+    root: PathBuf,
+}
+
+impl Library {
+    /// The path for a name from a client, which has to be inside the music directory.
+    fn resolve(&self, name: &str, uri: &str) -> Result<PathBuf, Ack> {
+        let relative = Path::new(uri.trim_matches('/'));
+        let escapes = relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        let path = self.root.join(relative);
+        if escapes || !path.exists() {
+            return Err(Ack::new(ACK_NO_EXIST, name, "No such file or directory"));
+        }
+        Ok(path)
+    }
+
+    /// What clients call `path`: where it is in the music directory, or the whole path for songs
+    /// queued from somewhere else.
+    fn uri(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Every song in `dir` and below, in order.
+    fn songs(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut songs = Vec::new();
+        let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .collect();
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() {
+                songs.extend(self.songs(&entry)?);
+            } else if entry.extension().is_some_and(is_song) {
+                songs.push(entry);
+            }
+        }
+        Ok(songs)
+    }
+}
+
+/// The song's details, going by its path when it can't be read.
+fn read(path: &Path) -> Details {
+    Details::read(path).unwrap_or_else(|_| Details {
+        song: Song::new(path),
+        ..Details::default()
+    })
+}
+
+fn write_status(out: &mut String, status: &Status) {
+    let state = match status.state.as_str() {
+        "playing" => "play",
+        "paused" => "pause",
+        _ => "stop",
+    };
+    let _ = writeln!(out, "volume: {:.0}", status.volume);
+    let _ = writeln!(out, "repeat: {}", (status.repeat != Repeat::Off) as u8);
+    let _ = writeln!(out, "random: {}", status.shuffle as u8);
+    let _ = writeln!(out, "single: {}", (status.repeat == Repeat::One) as u8);
+    out.push_str("consume: 0\n");
+    let _ = writeln!(out, "playlist: {}", version(&status.queue));
+    let _ = writeln!(out, "playlistlength: {}", status.queue.len());
+    let _ = writeln!(out, "state: {}", state);
+    if state == "stop" {
+        return;
+    }
+    let _ = writeln!(out, "song: {}\nsongid: {}", status.index, status.index);
+    if status.index + 1 < status.queue.len() {
+        let next = status.index + 1;
+        let _ = writeln!(out, "nextsong: {}\nnextsongid: {}", next, next);
+    }
+    let duration = status.duration.unwrap_or(0f64);
+    let _ = writeln!(out, "time: {}:{}", status.position as u64, duration as u64);
+    let _ = writeln!(out, "elapsed: {:.3}", status.position);
+    let _ = writeln!(out, "duration: {:.3}", duration);
+}
+
+fn write_position(out: &mut String, index: usize) {
+    let _ = writeln!(out, "Pos: {}\nId: {}", index, index);
+}
+
+/// A number for the queue that changes whenever it does, for the playlist field and plchanges.
+fn version(queue: &[PathBuf]) -> u32 {
+    let mut hasher = DefaultHasher::new();
+    queue.hash(&mut hasher);
+    hasher.finish() as u32
+}
+
+/// The subsystems idle clients care about that differ between two statuses.
+fn changes(before: &Status, now: &Status) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if before.queue != now.queue {
+        changed.push("playlist");
+    }
+    // a jump of more than the time between polls means a seek
+    let expected = if before.state == "playing" {
+        before.position + IDLE_POLL.as_secs_f64()
+    } else {
+        before.position
+    };
+    if before.state != now.state
+        || before.song != now.song
+        || before.index != now.index
+        || (now.position - expected).abs() > 2f64
+    {
+        changed.push("player");
+    }
+    if before.volume != now.volume {
+        changed.push("mixer");
+    }
+    if before.shuffle != now.shuffle || before.repeat != now.repeat {
+        changed.push("options");
+    }
+    changed
+}
+
+fn number(name: &str, arg: &str) -> Result<f64, Ack> {
+    arg.parse()
+        .ok()
+        .filter(|n: &f64| n.is_finite())
+        .ok_or_else(|| Ack::new(ACK_ARG, name, format!("bad number \"{}\"", arg)))
+}
+
+/// A number of seconds to seek by or to, as long as it fits in a Duration.
+fn seconds(name: &str, arg: &str) -> Result<f64, Ack> {
+    let seconds = number(name, arg)?;
+    match Duration::try_from_secs_f64(seconds.abs()) {
+        Ok(_) => Ok(seconds),
+        Err(_) => Err(Ack::new(ACK_ARG, name, "time out of range")),
+    }
+}
+
+fn flag(name: &str, arg: &str) -> Result<bool, Ack> {
+    match arg {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Ack::new(ACK_ARG, name, format!("bad boolean \"{}\"", arg))),
+    }
+}
+
+/// A position like 3, or a range like 3:7 or 3: to the end, within a queue of `len` songs.
+fn range(name: &str, arg: &str, len: usize) -> Result<std::ops::Range<usize>, Ack> {
+    let bad = || Ack::new(ACK_ARG, name, format!("bad range \"{}\"", arg));
+    let range = match arg.split_once(':') {
+        Some((start, "")) => start.parse().map_err(|_| bad())?..len,
+        Some((start, end)) => {
+            start.parse().map_err(|_| bad())?..end.parse::<usize>().map_err(|_| bad())?.min(len)
+        }
+        None => {
+            let i: usize = arg.parse().map_err(|_| bad())?;
+            i..i + 1
+        }
+    };
+    if range.start >= len || range.start > range.end {
+        return Err(Ack::new(ACK_ARG, name, "bad song index"));
+    }
+    Ok(range)
+}
+
+/// Split a command line into words. Double quotes keep spaces in a word, and backslashes escape
+/// the next character inside them. None if a quote isn't closed.
+fn split(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut word = String::new();
+        match chars.peek() {
+            None => return Some(words),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => word.push(chars.next()?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Receiver};
+
+    use super::*;
+    use crate::equalizer::Equalizer;
+    use crate::xdg::TempDir;
+
+    /// A player that answers every command, with the status for `queue`, and hands the rest over
+    /// to check.
+    fn player(queue: Vec<PathBuf>) -> (Sender<Event>, Receiver<Command>) {
+        let (sender, events) = channel();
+        let (commands, received) = channel();
+        thread::spawn(move || {
+            for event in events {
+                if let Event::Command(command, reply) = event {
+                    let response = match command {
+                        Command::Status => Response::Status(Status {
+                            state: "playing".to_string(),
+                            song: queue.first().cloned(),
+                            index: 0,
+                            queue: queue.clone(),
+                            position: 0f64,
+                            duration: None,
+                            volume: 50f32,
+                            cover: None,
+                            shuffle: false,
+                            repeat: Repeat::Off,
+                            equalizer: Equalizer::default(),
+                            speed: 1f32,
+                            ab_loop: None,
+                        }),
+                        command => {
+                            let _ = commands.send(command);
+                            Response::Ok
+                        }
+                    };
+                    let _ = reply.send(response);
+                }
+            }
+        });
+        (sender, received)
+    }
+
+    /// A music directory with an album of two songs and its cover.
+    fn library(name: &str) -> TempDir {
+        let root = TempDir::new(&format!("mpd-{}", name));
+        let album = root.join("Artist").join("Album");
+        fs::create_dir_all(&album).unwrap();
+        for file in ["01.flac", "02.mp3", "cover.jpg"] {
+            fs::write(album.join(file), b"").unwrap();
+        }
+        root
+    }
+
+    fn client(root: &Path, sender: Sender<Event>) -> Client<UnixStream> {
+        let (stream, _) = UnixStream::pair().unwrap();
+        Client {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            library: Library {
+                root: root.to_path_buf(),
+            },
+            sender,
+        }
+    }
+
+    fn run(client: &mut Client<UnixStream>, line: &str) -> Result<String, (u32, String)> {
+        let mut out = String::new();
+        client
+            .run(line, &mut out)
+            .map(|()| out)
+            .map_err(|ack| (ack.code, ack.message))
+    }
+
+    #[test]
+    fn split_words() {
+        assert_eq!(
+            split("add  \"a b/c\" d"),
+            Some(vec!["add".into(), "a b/c".into(), "d".into()])
+        );
+        assert_eq!(
+            split(r#"find "say \"hi\"""#),
+            Some(vec!["find".into(), r#"say "hi""#.into()])
+        );
+        assert_eq!(split(""), Some(Vec::new()));
+        assert_eq!(split("add \"unclosed"), None);
+    }
+
+    #[test]
+    fn numbers_and_ranges() {
+        assert_eq!(number("seekcur", "+1.5").ok(), Some(1.5));
+        for bad in ["inf", "-inf", "nan", "NaN", "x"] {
+            assert!(number("seekcur", bad).is_err(), "{}", bad);
+        }
+        assert!(seconds("seekcur", "1e300").is_err());
+        assert_eq!(seconds("seekcur", "-30").ok(), Some(-30f64));
+
+        assert_eq!(range("playlistinfo", "2", 5).ok(), Some(2..3));
+        assert_eq!(range("playlistinfo", "1:3", 5).ok(), Some(1..3));
+        assert_eq!(range("playlistinfo", "1:", 5).ok(), Some(1..5));
+        assert_eq!(range("playlistinfo", "1:9", 5).ok(), Some(1..5));
+        assert!(range("playlistinfo", "5", 5).is_err());
+        assert!(range("playlistinfo", "3:1", 5).is_err());
+        assert!(flag("random", "2").is_err());
+    }
+
+    #[test]
+    fn playback_commands() {
+        let root = library("playback");
+        let (sender, commands) = player(vec![root.join("a.flac"), root.join("b.flac")]);
+        let mut client = client(&root, sender);
+
+        run(&mut client, "seekcur -10").unwrap();
+        assert_eq!(
+            commands.recv().unwrap(),
+            Command::Seek {
+                seconds: -10f64,
+                relative: true
+            }
+        );
+        run(&mut client, "seek 1 30").unwrap();
+        assert_eq!(commands.recv().unwrap(), Command::Jump { index: 1 });
+        assert_eq!(
+            commands.recv().unwrap(),
+            Command::Seek {
+                seconds: 30f64,
+                relative: false
+            }
+        );
+        run(&mut client, "volume -5").unwrap();
+        assert_eq!(
+            commands.recv().unwrap(),
+            Command::Volume {
+                percent: -5f32,
+                relative: true
+            }
+        );
+
+        for line in [
+            "seekcur inf",
+            "seekcur 1e300",
+            "seek 0 nan",
+            "seek 2 0",
+            "volume nan",
+        ] {
+            assert_eq!(run(&mut client, line).unwrap_err().0, ACK_ARG, "{}", line);
+        }
+        assert_eq!(run(&mut client, "setvol 101").unwrap_err().0, ACK_ARG);
+        assert_eq!(run(&mut client, "volume 1e30").unwrap_err().0, ACK_ARG);
+        assert_eq!(run(&mut client, "bogus").unwrap_err().0, ACK_UNKNOWN);
+        assert!(commands.try_recv().is_err());
+    }
+
+    #[test]
+    fn add_only_songs() {
+        let root = library("add");
+        let (sender, commands) = player(Vec::new());
+        let mut client = client(&root, sender);
+        let album = root.join("Artist").join("Album");
+
+        run(&mut client, "add \"Artist/Album\"").unwrap();
+        assert_eq!(
+            commands.recv().unwrap(),
+            Command::Enqueue {
+                paths: vec![album.join("01.flac"), album.join("02.mp3")]
+            }
+        );
+        assert_eq!(
+            run(&mut client, "addid Artist/Album/02.mp3").unwrap(),
+            "Id: 0\n"
+        );
+        assert_eq!(
+            commands.recv().unwrap(),
+            Command::Enqueue {
+                paths: vec![album.join("02.mp3")]
+            }
+        );
+
+        assert_eq!(
+            run(&mut client, "add Artist/Album/cover.jpg")
+                .unwrap_err()
+                .0,
+            ACK_ARG
+        );
+        let missing = run(&mut client, "add Artist/Nope.flac").unwrap_err();
+        assert_eq!(missing.0, ACK_NO_EXIST);
+        assert_eq!(run(&mut client, "add ../etc").unwrap_err().0, ACK_NO_EXIST);
+        assert!(commands.try_recv().is_err());
+    }
+}
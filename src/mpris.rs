@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use zbus::blocking::{connection, Connection};
+use zbus::object_server::SignalContext;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{block_on, fdo, interface};
+
+use crate::control::{Command, Status};
+use crate::speed;
+use crate::{Event, Song};
+
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// The MPRIS2 interfaces on the session bus, so media keys, playerctl and status bars can see and
+/// control the player.
+///
+/// Method calls turn into Commands on the event channel, the same as the control socket. Properties
+/// are answered from the last Status handed to Mpris::update, since the event loop might be busy
+/// emitting signals when D-Bus asks for them.
+pub struct Mpris {
+    connection: Connection,
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    status: Status,
+    /// When status was taken, to work out the position while playing.
+    at: Instant,
+}
+
+impl Shared {
+    fn position(&self) -> f64 {
+        if self.status.state == "playing" {
+            let speed = f64::from(self.status.speed);
+            self.status.position + self.at.elapsed().as_secs_f64() * speed
+        } else {
+            self.status.position
+        }
+    }
+}
+
+impl Mpris {
+    /// Connect to the session bus (whatever $DBUS_SESSION_BUS_ADDRESS points at) and claim
+    /// org.mpris.MediaPlayer2.bebop.
+    pub fn new(sender: Sender<Event>, status: Status) -> zbus::Result<Mpris> {
+        let shared = Arc::new(Mutex::new(Shared {
+            status,
+            at: Instant::now(),
+        }));
+
+        let connection = connection::Builder::session()?
+            .name("org.mpris.MediaPlayer2.bebop")?
+            .serve_at(PATH, Root)?
+            .serve_at(
+                PATH,
+                MprisPlayer {
+                    sender,
+                    shared: shared.clone(),
+                },
+            )?
+            .build()?;
+
+        Ok(Mpris { connection, shared })
+    }
+
+    /// Take a fresh Status and let D-Bus know about whatever changed since the last one.
+    pub fn update(&self, status: Status) -> zbus::Result<()> {
+        let (old, expected_position) = {
+            let mut shared = self.shared.lock().unwrap();
+            let expected_position = shared.position();
+            let old = std::mem::replace(&mut shared.status, status.clone());
+            shared.at = Instant::now();
+            (old, expected_position)
+        };
+
+        let iface_ref = self
+            .connection
+            .object_server()
+            .interface::<_, MprisPlayer>(PATH)?;
+        let ctxt = iface_ref.signal_context();
+        let iface = iface_ref.get();
+
+        block_on(async {
+            if old.state != status.state {
+                iface.playback_status_changed(ctxt).await?;
+            }
+            if old.song != status.song
+                || old.duration != status.duration
+                || old.cover != status.cover
+            {
+                iface.metadata_changed(ctxt).await?;
+            }
+            if old.index != status.index || old.queue.len() != status.queue.len() {
+                iface.can_go_next_changed(ctxt).await?;
+                iface.can_go_previous_changed(ctxt).await?;
+            }
+            if (old.volume - status.volume).abs() > f32::EPSILON {
+                iface.volume_changed(ctxt).await?;
+            }
+            if old.speed != status.speed {
+                iface.rate_changed(ctxt).await?;
+            }
+            // playback jumped somewhere other than where it would have ended up on its own
+            if old.song == status.song && (expected_position - status.position).abs() > 1f64 {
+                MprisPlayer::seeked(ctxt, micros(status.position)).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "bebop"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<&str> {
+        vec!["file"]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<&str> {
+        vec!["audio/mpeg", "audio/flac", "audio/x-wav"]
+    }
+}
+
+struct MprisPlayer {
+    sender: Sender<Event>,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl MprisPlayer {
+    /// Pass a command along without waiting to hear back, since property changes get announced
+    /// through Mpris::update anyway.
+    fn send(&self, command: Command) -> fdo::Result<()> {
+        let (reply_sender, _) = channel();
+        self.sender
+            .send(Event::Command(command, reply_sender))
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    fn next(&self) -> fdo::Result<()> {
+        self.send(Command::Next)
+    }
+
+    fn previous(&self) -> fdo::Result<()> {
+        self.send(Command::Prev)
+    }
+
+    fn pause(&self) -> fdo::Result<()> {
+        self.send(Command::Pause)
+    }
+
+    fn play_pause(&self) -> fdo::Result<()> {
+        self.send(Command::Toggle)
+    }
+
+    fn stop(&self) -> fdo::Result<()> {
+        self.send(Command::Pause)
+    }
+
+    fn play(&self) -> fdo::Result<()> {
+        self.send(Command::Play)
+    }
+
+    /// Seek `offset` microseconds from the current position.
+    fn seek(&self, offset: i64) -> fdo::Result<()> {
+        self.send(Command::Seek {
+            seconds: offset as f64 / 1_000_000f64,
+            relative: true,
+        })
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let current = track_id_for(&self.shared.lock().unwrap().status);
+        if track_id.as_str() != current || position < 0 {
+            return Ok(());
+        }
+        self.send(Command::Seek {
+            seconds: position as f64 / 1_000_000f64,
+            relative: false,
+        })
+    }
+
+    fn open_uri(&self, uri: &str) -> fdo::Result<()> {
+        match uri.strip_prefix("file://") {
+            Some(path) => self.send(Command::Enqueue {
+                paths: vec![PathBuf::from(OsString::from_vec(percent_decode(path)))],
+            }),
+            None => Err(fdo::Error::NotSupported(format!("can't open {}", uri))),
+        }
+    }
+
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        match self.shared.lock().unwrap().status.state.as_str() {
+            "playing" => "Playing",
+            "paused" => "Paused",
+            _ => "Stopped",
+        }
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        f64::from(self.shared.lock().unwrap().status.speed)
+    }
+
+    #[zbus(property)]
+    fn set_rate(&mut self, rate: f64) {
+        // MPRIS says a rate of 0 should pause instead, and bebop doesn't go that slow anyway
+        if rate > 0f64 {
+            let _ = self.send(Command::Speed {
+                rate: rate as f32,
+                relative: false,
+            });
+        }
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        f64::from(speed::MIN_SPEED)
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        f64::from(speed::MAX_SPEED)
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        metadata(&self.shared.lock().unwrap().status)
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        f64::from(self.shared.lock().unwrap().status.volume) / 100f64
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, volume: f64) {
+        let _ = self.send(Command::Volume {
+            percent: (volume * 100f64) as f32,
+            relative: false,
+        });
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        micros(self.shared.lock().unwrap().position())
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        let status = &self.shared.lock().unwrap().status;
+        status.index + 1 < status.queue.len()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        let status = &self.shared.lock().unwrap().status;
+        status.index > 0 && status.index <= status.queue.len()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+fn micros(seconds: f64) -> i64 {
+    (seconds * 1_000_000f64) as i64
+}
+
+fn track_id_for(status: &Status) -> String {
+    match status.song {
+        Some(_) => format!("/org/bebop/track/{}", status.index),
+        None => NO_TRACK.to_string(),
+    }
+}
+
+fn metadata(status: &Status) -> HashMap<String, OwnedValue> {
+    let mut metadata = HashMap::new();
+    let mut insert = |key: &str, value: Value<'_>| {
+        if let Ok(v) = OwnedValue::try_from(value) {
+            metadata.insert(key.to_string(), v);
+        }
+    };
+
+    if let Ok(path) = ObjectPath::try_from(track_id_for(status)) {
+        insert("mpris:trackid", Value::from(path));
+    }
+    let path = match &status.song {
+        Some(p) => p,
+        None => return metadata,
+    };
+
+    let song = Song::read(path);
+    insert("xesam:url", Value::from(file_url(&song.path)));
+    insert("xesam:title", Value::from(song.title.as_str()));
+    insert("xesam:album", Value::from(song.album.as_str()));
+    insert("xesam:artist", Value::from(vec![song.artist.as_str()]));
+    if let Some(cover) = &status.cover {
+        insert("mpris:artUrl", Value::from(file_url(cover)));
+    }
+    if let Some(duration) = status.duration {
+        insert("mpris:length", Value::from(micros(duration)));
+    }
+
+    metadata
+}
+
+/// A file:// URL for `path`, percent-encoding anything that isn't safe in one.
+fn file_url(path: &Path) -> String {
+    let mut url = String::from("file://");
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            url.push(b as char);
+        } else {
+            url.push_str(&format!("%{:02X}", b));
+        }
+    }
+    url
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
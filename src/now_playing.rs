@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tui::widgets::Text;
+
+use crate::song::{format_time, Details};
+use crate::theme::Theme;
+use crate::Playback;
+
+/// Where the now playing panel goes relative to the queue.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    Hidden,
+    Above,
+    Beside,
+}
+
+/// Everything about the current song, with its details cached so tags are only read when the
+/// song changes.
+#[derive(Default)]
+pub struct NowPlaying {
+    details: Option<(PathBuf, Details)>,
+}
+
+impl NowPlaying {
+    /// How many rows the panel needs, borders included.
+    pub const HEIGHT: u16 = 9;
+
+    pub fn lines(&mut self, player: &dyn Playback, theme: &Theme) -> Vec<Text<'static>> {
+        let song = match player.playing().get(player.index()) {
+            Some(s) => s,
+            None => return vec![Text::raw("Nothing playing")],
+        };
+        let details = self.details(song);
+
+        let state = if player.is_stopped() {
+            "■ Stopped"
+        } else if player.is_paused() {
+            "⏸ Paused"
+        } else {
+            "▶ Playing"
+        };
+        let duration = player.duration().or(details.duration);
+        let time = match duration {
+            Some(d) => format!(
+                "{} / {}",
+                format_time(player.position().as_secs_f64()),
+                format_time(d.as_secs_f64())
+            ),
+            None => format_time(player.position().as_secs_f64()),
+        };
+
+        let album = match details.year {
+            Some(year) => format!("{} ({})", details.song.album, year),
+            None => details.song.album.clone(),
+        };
+        let track = match (details.track, details.tracks) {
+            (Some(n), Some(total)) => format!("{}/{}", n, total),
+            (Some(n), None) => n.to_string(),
+            _ => "-".to_string(),
+        };
+
+        let mut format = vec![details.codec.clone()];
+        if let Some(bitrate) = details.bitrate {
+            format.push(format!("{} kbps", bitrate));
+        }
+        if let Some(rate) = details.sample_rate {
+            format.push(format!("{:.1} kHz", f64::from(rate) / 1000f64));
+        }
+        if let Some(channels) = details.channels {
+            format.push(format!("{} ch", channels));
+        }
+
+        let state = match player.speed() {
+            speed if speed != 1f32 => format!("{}  {}  {}×\n", state, time, speed),
+            _ => format!("{}  {}\n", state, time),
+        };
+        let state = if player.is_paused() && !player.is_stopped() {
+            Text::styled(state, theme.paused)
+        } else {
+            Text::raw(state)
+        };
+
+        vec![
+            state,
+            Text::raw(format!("Title:  {}\n", details.song.title)),
+            Text::raw(format!("Artist: {}\n", details.song.artist)),
+            Text::raw(format!("Album:  {}\n", album)),
+            Text::raw(format!("Track:  {}\n", track)),
+            Text::raw(format!("Format: {}\n", format.join(", "))),
+            Text::raw(format!("Path:   {}\n", song.display())),
+        ]
+    }
+
+    fn details(&mut self, song: &Path) -> &Details {
+        let stale = match &self.details {
+            Some((path, _)) => path != song,
+            None => true,
+        };
+        if stale {
+            let details = Details::read(song).unwrap_or_else(|_| Details {
+                song: crate::Song::new(song),
+                codec: "unknown".to_string(),
+                ..Details::default()
+            });
+            self.details = Some((song.to_path_buf(), details));
+        }
+
+        &self.details.as_ref().unwrap().1
+    }
+}
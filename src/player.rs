@@ -1,51 +1,201 @@
+use std::f32::consts::FRAC_PI_2;
 use std::fs::File;
 use std::io;
 use std::io::BufReader;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use rodio::source::Source;
+use rodio::source::{Source, UniformSourceIterator};
 use rodio::Sample;
-use tui::widgets::ListState;
+use serde::{Deserialize, Serialize};
+
+use crate::equalizer::{self, Equalize, Equalizer};
+use crate::speed::{self, Pitch, Speed};
+use crate::visualizer::{self, Tap};
+use crate::{song, status_bar};
+
+/// The longest songs can fade into each other for.
+const MAX_CROSSFADE: Duration = Duration::from_secs(12);
+
+/// A song ready to go into the sink.
+type Queued = Box<dyn Source<Item = f32> + Send>;
+
+/// What's playing and how, whether the player's in this process or in a daemon somewhere else.
+pub trait Playback {
+    fn playing(&self) -> &[PathBuf];
+    fn index(&self) -> usize;
+    /// How far into the current song playback is.
+    fn position(&self) -> Duration;
+    /// How long the current song is, if that's known.
+    fn duration(&self) -> Option<Duration>;
+    fn is_paused(&self) -> bool;
+    /// Whether there's nothing left to play.
+    fn is_stopped(&self) -> bool;
+    fn volume(&self) -> f32;
+    fn is_shuffled(&self) -> bool;
+    fn repeat(&self) -> Repeat;
+    fn equalizer(&self) -> Equalizer;
+    /// How fast songs play, where 1 is normal.
+    fn speed(&self) -> f32;
+    /// The points being looped between in the current song, if any.
+    fn ab_loop(&self) -> Option<AbLoop>;
+}
+
+/// What happens when a song ends.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Repeat {
+    Off,
+    /// Start the queue over once it runs out.
+    All,
+    /// Play the same song again.
+    One,
+}
+
+impl Repeat {
+    pub fn next(self) -> Repeat {
+        match self {
+            Repeat::Off => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+        }
+    }
+}
+
+/// Points in a song to loop between, for practicing a passage. Until B is set there's only a
+/// start, and nothing loops.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub struct AbLoop {
+    /// Seconds into the song.
+    pub a: f64,
+    pub b: Option<f64>,
+}
+
+/// Which end of an A-B loop to set at the current position, or to stop looping.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LoopPoint {
+    A,
+    B,
+    Off,
+}
+
+/// Where each new sink's samples go.
+enum Output {
+    Device {
+        _stream: rodio::OutputStream,
+        handle: rodio::OutputStreamHandle,
+    },
+    /// Handed over for tests to pull through themselves.
+    #[cfg(test)]
+    Idle(Sender<rodio::queue::SourcesQueueOutput<f32>>),
+}
 
 pub struct Player {
-    pub list_state: ListState,
-    _stream: rodio::OutputStream,
-    stream_handle: rodio::OutputStreamHandle,
+    output: Output,
     sink: rodio::Sink,
     volume: f32,
     playing: Vec<PathBuf>,
     remaining: Arc<AtomicUsize>,
+    position: Arc<AtomicU64>,
+    duration: Arc<AtomicU64>,
+    song_switch_sender: Option<Sender<usize>>,
+    repeat: Repeat,
+    /// The queue in its original order, while it's shuffled.
+    unshuffled: Option<Vec<PathBuf>>,
+    /// How long each song fades into the next for, if at all.
+    crossfade: Duration,
+    /// Whether songs in the same album go straight into each other instead of crossfading.
+    gapless: bool,
+    /// What every song plays through on the way to the sink.
+    equalizer: Arc<equalizer::Shared>,
+    speed: Arc<speed::Shared>,
+    /// Whether playing faster or slower keeps the pitch.
+    pitch: Pitch,
+    /// The A-B loop and the song it's in.
+    ab_loop: Option<(PathBuf, AbLoop)>,
+    /// The latest samples played, for the visualizer.
+    samples: Arc<visualizer::Samples>,
 }
 
 impl Player {
     pub fn new(volume: f32) -> Result<Player, rodio::StreamError> {
-        let list_state = ListState::default();
+        let (stream, stream_handle) = rodio::OutputStream::try_default()?;
+        Ok(Player::with_output(
+            Output::Device {
+                _stream: stream,
+                handle: stream_handle,
+            },
+            volume,
+        ))
+    }
+
+    /// A player that isn't connected to anything, along with where each sink it opens ends up.
+    #[cfg(test)]
+    pub fn idle() -> (Player, Receiver<rodio::queue::SourcesQueueOutput<f32>>) {
+        let (sender, receiver) = channel();
+        (Player::with_output(Output::Idle(sender), 1f32), receiver)
+    }
 
-        let (_stream, stream_handle) = rodio::OutputStream::try_default()?;
+    fn with_output(output: Output, volume: f32) -> Player {
         let (sink, _) = rodio::Sink::new_idle();
 
         let playing = Vec::new();
         let remaining = Arc::new(AtomicUsize::new(0));
+        let position = Arc::new(AtomicU64::new(0));
+        let duration = Arc::new(AtomicU64::new(0));
 
-        Ok(Player {
-            list_state,
-            _stream,
-            stream_handle,
+        Player {
+            output,
             sink,
             volume,
             playing,
             remaining,
-        })
+            position,
+            duration,
+            song_switch_sender: None,
+            repeat: Repeat::Off,
+            unshuffled: None,
+            crossfade: Duration::from_secs(0),
+            gapless: true,
+            equalizer: Arc::default(),
+            speed: Arc::default(),
+            pitch: Pitch::Keep,
+            ab_loop: None,
+            samples: Arc::default(),
+        }
+    }
+
+    /// Fade songs into each other for `seconds`, up to 12. It takes effect from the next song
+    /// that gets queued.
+    pub fn set_crossfade(&mut self, seconds: f32, gapless: bool) {
+        self.crossfade = Duration::try_from_secs_f32(seconds)
+            .unwrap_or_default()
+            .min(MAX_CROSSFADE);
+        self.gapless = gapless;
+    }
+
+    /// Whether playing faster or slower keeps the pitch. It takes effect from the next song that
+    /// gets queued.
+    pub fn set_pitch(&mut self, pitch: Pitch) {
+        self.pitch = pitch;
     }
 
     fn reset_sink(&mut self) {
-        // FIXME: actually handle the error instead of just expecting
-        self.sink = rodio::Sink::try_new(&self.stream_handle).expect("error opening sink");
+        let (sink, queue) = rodio::Sink::new_idle();
+        match &self.output {
+            // FIXME: actually handle the error instead of just expecting
+            Output::Device { handle, .. } => handle.play_raw(queue).expect("error opening sink"),
+            #[cfg(test)]
+            Output::Idle(sender) => {
+                let _ = sender.send(queue);
+            }
+        }
+        self.sink = sink;
         self.sink.set_volume(self.volume);
     }
 
@@ -54,28 +204,356 @@ impl Player {
     }
 
     pub fn play_songs(&mut self, start: usize, dir: Vec<PathBuf>) -> io::Result<Receiver<usize>> {
+        self.play_songs_from(start, dir, Duration::from_secs(0), false)
+    }
+
+    /// Like play_songs, but starts `offset` into the first song, optionally paused.
+    pub fn play_songs_from(
+        &mut self,
+        start: usize,
+        dir: Vec<PathBuf>,
+        offset: Duration,
+        paused: bool,
+    ) -> io::Result<Receiver<usize>> {
+        // the loop only carries on if it's in the song being started
+        let ab_loop = match &self.ab_loop {
+            Some((song, ab_loop)) if dir.get(start) == Some(song) => Some(*ab_loop),
+            _ => None,
+        };
+        let (sender, receiver) = channel::<usize>();
+        // before the old queue goes, so if a song can't be played it keeps playing
+        let sources = self.sources(&dir[start..], offset, ab_loop, sender.clone())?;
+
         self.reset_sink();
+        if paused {
+            self.sink.pause();
+        }
         let remaining = &self.remaining;
         remaining.store(dir.len() - start, Ordering::Relaxed);
-        self.playing = dir.clone();
-
-        let (sender, receiver) = channel::<usize>();
-        for path in dir[start..].to_vec() {
-            let f = File::open(path)?;
-            let source = rodio::Decoder::new(BufReader::new(f)).expect("error decoding file");
-            self.sink
-                .append(Signal::new(source, self.remaining.clone(), sender.clone()));
+        self.position
+            .store(offset.as_millis() as u64, Ordering::Relaxed);
+        self.duration.store(0, Ordering::Relaxed);
+        self.playing = dir;
+        if ab_loop.is_none() {
+            self.ab_loop = None;
+        }
+        self.song_switch_sender = Some(sender);
+        for source in sources {
+            self.play_through(source);
         }
 
         Ok(receiver)
     }
 
-    pub fn playing(&self) -> &Vec<PathBuf> {
-        &self.playing
+    /// Start a new queue at `start`, shuffling what comes after it if shuffle is on.
+    pub fn play_queue(&mut self, start: usize, dir: Vec<PathBuf>) -> io::Result<Receiver<usize>> {
+        if self.unshuffled.is_none() {
+            return self.play_songs(start, dir);
+        }
+        self.unshuffled = Some(dir.clone());
+        let mut shuffled = dir;
+        let from = (start + 1).min(shuffled.len());
+        shuffle(&mut shuffled[from..]);
+        self.play_songs(start, shuffled)
     }
 
-    pub fn index(&self) -> usize {
-        self.playing.len() - self.remaining.load(Ordering::Relaxed)
+    /// Add songs to the end of the queue. If nothing is queued up anymore, they start playing and
+    /// the new song switch receiver is returned. The same goes for when the last song has to fade
+    /// into them, since it's already in the sink without a fade: the queue starts over from where
+    /// it is, like when shuffling.
+    pub fn enqueue(&mut self, songs: Vec<PathBuf>) -> io::Result<Option<Receiver<usize>>> {
+        if songs.is_empty() {
+            return Ok(None);
+        }
+        let index = self.index();
+        // a song that loops never gets to its end to fade out of
+        let looping =
+            index + 1 == self.playing.len() && self.ab_loop().is_some_and(|l| l.b.is_some());
+        let fades = match self.playing.last() {
+            Some(last) if !looping && !self.sink.empty() => {
+                let left = song::duration(last).unwrap_or_default();
+                !self.fade(last, &songs[0], left).is_zero()
+            }
+            _ => false,
+        };
+        match &self.song_switch_sender {
+            Some(_) if fades => {
+                let mut playing = self.playing.clone();
+                playing.extend(songs);
+                let (position, paused) = (self.position(), self.is_paused());
+                self.play_songs_from(index, playing, position, paused)
+                    .map(Some)
+            }
+            Some(sender) if !self.sink.empty() => {
+                let sources = self.sources(&songs, Duration::from_secs(0), None, sender.clone())?;
+                // counted before they're in the sink, so they can't finish before they are
+                self.remaining.fetch_add(songs.len(), Ordering::Relaxed);
+                self.playing.extend(songs);
+                for source in sources {
+                    self.play_through(source);
+                }
+                Ok(None)
+            }
+            _ => {
+                let start = self.playing.len();
+                let mut playing = self.playing.clone();
+                playing.extend(songs);
+                self.play_songs(start, playing).map(Some)
+            }
+        }
+    }
+
+    /// Decode `songs` and get each ready to play, fading into the next where it should. Nothing
+    /// goes into the sink, so if any of them can't be decoded, nothing's changed.
+    fn sources(
+        &self,
+        songs: &[PathBuf],
+        offset: Duration,
+        ab_loop: Option<AbLoop>,
+        sender: Sender<usize>,
+    ) -> io::Result<Vec<Queued>> {
+        let mut sources: Vec<Queued> = Vec::with_capacity(songs.len());
+        // how much of the song was already heard fading in under the one before it
+        let mut faded_in = Duration::from_secs(0);
+        for (i, path) in songs.iter().enumerate() {
+            let source = decode(path)?;
+            let length = source.total_duration().or_else(|| song::duration(path));
+            let skip = if i == 0 { offset } else { faded_in };
+            let mut signal = Signal::new(
+                source.skip_duration(skip),
+                self.remaining.clone(),
+                self.position.clone(),
+                self.duration.clone(),
+                sender.clone(),
+            )
+            .starting_at(skip)
+            .with_length(length);
+
+            let looping = match ab_loop {
+                Some(AbLoop { a, b: Some(b) }) if i == 0 => Some((a, b)),
+                _ => None,
+            };
+            if let Some((a, b)) = looping {
+                let (a, b) = (Duration::from_secs_f64(a), Duration::from_secs_f64(b));
+                let path = path.clone();
+                signal = signal.looping(a, b, move || {
+                    decode(&path)
+                        .map(|s| s.skip_duration(a))
+                        .map_err(|e| status_bar::error(format!("error looping: {}", e)))
+                        .ok()
+                });
+            }
+
+            // a song that loops never gets to its end to fade out of
+            let fade = match (songs.get(i + 1), length) {
+                (Some(next), Some(length)) if looping.is_none() => {
+                    self.fade(path, next, length.saturating_sub(skip))
+                }
+                _ => Duration::from_secs(0),
+            };
+            if fade.is_zero() {
+                sources.push(Box::new(signal.convert_samples()));
+            } else {
+                let start = length.unwrap_or_default().saturating_sub(skip + fade);
+                let next = decode(&songs[i + 1])?;
+                let crossfade = Crossfade::new(signal, next, start, fade);
+                sources.push(Box::new(crossfade.convert_samples()));
+            }
+            faded_in = fade;
+        }
+
+        Ok(sources)
+    }
+
+    /// Append a song to the sink by way of the speed, the equalizer and the visualizer's tap.
+    fn play_through<S>(&self, source: S)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        // speed goes outside of Signal so positions stay in the song's own time
+        let source = Speed::new(source, self.speed.clone(), self.pitch);
+        let source = Equalize::new(source, self.equalizer.clone());
+        self.sink.append(Tap::new(source, self.samples.clone()));
+    }
+
+    /// How long the song at `path` should fade into `next` for, with `left` of it to go once it
+    /// starts. Songs too short to fade over don't.
+    fn fade(&self, path: &Path, next: &Path, left: Duration) -> Duration {
+        // repeating the song means the next one never gets to start
+        if self.crossfade.is_zero() || self.repeat == Repeat::One {
+            return Duration::from_secs(0);
+        }
+        if self.gapless && path.parent() == next.parent() {
+            return Duration::from_secs(0);
+        }
+        let next_length = song::duration(next).unwrap_or_default();
+        self.crossfade.min(left / 2).min(next_length / 2)
+    }
+
+    /// Set one end of the A-B loop at the current position, or stop looping. Setting B starts the
+    /// loop, jumping back to A.
+    pub fn set_loop(&mut self, point: LoopPoint) -> io::Result<Option<Receiver<usize>>> {
+        let song = match self.playing.get(self.index()) {
+            Some(s) if !self.is_stopped() => s.clone(),
+            _ => return Err(io::Error::other("nothing's playing")),
+        };
+        let position = self.position().as_secs_f64();
+        // looping only takes effect or stops once the song's started over
+        let looped = self.ab_loop().is_some_and(|l| l.b.is_some());
+        match point {
+            LoopPoint::A => {
+                self.ab_loop = Some((
+                    song,
+                    AbLoop {
+                        a: position,
+                        b: None,
+                    },
+                ));
+                if looped {
+                    return self.seek(self.position());
+                }
+                Ok(None)
+            }
+            LoopPoint::B => {
+                let a = match self.ab_loop() {
+                    Some(l) if l.a < position => l.a,
+                    Some(_) => return Err(io::Error::other("B has to come after A")),
+                    None => return Err(io::Error::other("set A first")),
+                };
+                self.ab_loop = Some((
+                    song,
+                    AbLoop {
+                        a,
+                        b: Some(position),
+                    },
+                ));
+                self.seek(Duration::from_secs_f64(a))
+            }
+            LoopPoint::Off => {
+                self.ab_loop = None;
+                if looped {
+                    return self.seek(self.position());
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Skip to the next song, if there is one.
+    pub fn next_song(&mut self) -> io::Result<Option<Receiver<usize>>> {
+        let index = self.index();
+        if index + 1 < self.playing.len() {
+            self.play_songs(index + 1, self.playing.clone()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Go back to the previous song, if there is one.
+    pub fn previous_song(&mut self) -> io::Result<Option<Receiver<usize>>> {
+        let index = self.index();
+        if index > 0 && index <= self.playing.len() {
+            self.play_songs(index - 1, self.playing.clone()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Skip to the song at `index` in the queue, if there is one.
+    pub fn jump(&mut self, index: usize) -> io::Result<Option<Receiver<usize>>> {
+        if index < self.playing.len() {
+            self.play_songs(index, self.playing.clone()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Stop playing and empty the queue.
+    pub fn clear(&mut self) {
+        self.reset_sink();
+        self.playing.clear();
+        if self.unshuffled.is_some() {
+            self.unshuffled = Some(Vec::new());
+        }
+        self.remaining.store(0, Ordering::Relaxed);
+        self.position.store(0, Ordering::Relaxed);
+        self.duration.store(0, Ordering::Relaxed);
+        self.song_switch_sender = None;
+    }
+
+    /// Restart the current song at `position`, keeping the rest of the queue and the pause state.
+    pub fn seek(&mut self, position: Duration) -> io::Result<Option<Receiver<usize>>> {
+        let index = self.index();
+        if index >= self.playing.len() {
+            return Ok(None);
+        }
+        let paused = self.is_paused();
+        self.play_songs_from(index, self.playing.clone(), position, paused)
+            .map(Some)
+    }
+
+    /// Deal with a song ending, starting it or the queue over if repeat says to.
+    pub fn song_finished(&mut self) -> io::Result<Option<Receiver<usize>>> {
+        let index = self.index();
+        match self.repeat {
+            Repeat::One if index > 0 && index <= self.playing.len() => {
+                self.play_songs(index - 1, self.playing.clone()).map(Some)
+            }
+            Repeat::All if self.is_stopped() && !self.playing.is_empty() => {
+                self.play_songs(0, self.playing.clone()).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    /// Shuffle the songs after the current one, or put the queue back in order, keeping the
+    /// current song going.
+    pub fn toggle_shuffle(&mut self) -> io::Result<Option<Receiver<usize>>> {
+        let current = self.playing.get(self.index()).cloned();
+        let playing = match self.unshuffled.take() {
+            Some(mut order) => {
+                // anything enqueued while shuffled goes at the end
+                let added: Vec<PathBuf> = self
+                    .playing
+                    .iter()
+                    .filter(|p| !order.contains(p))
+                    .cloned()
+                    .collect();
+                order.extend(added);
+                order
+            }
+            None => {
+                self.unshuffled = Some(self.playing.clone());
+                let mut shuffled = self.playing.clone();
+                let start = (self.index() + 1).min(shuffled.len());
+                shuffle(&mut shuffled[start..]);
+                shuffled
+            }
+        };
+
+        let current = match current {
+            Some(c) => c,
+            None => {
+                self.playing = playing;
+                return Ok(None);
+            }
+        };
+        let index = playing.iter().position(|p| *p == current).unwrap_or(0);
+        let (position, paused) = (self.position(), self.is_paused());
+        self.play_songs_from(index, playing, position, paused)
+            .map(Some)
+    }
+
+    pub fn play(&self) {
+        self.sink.play()
+    }
+
+    pub fn pause(&self) {
+        self.sink.pause()
     }
 
     pub fn toggle_pause(&self) {
@@ -86,40 +564,153 @@ impl Player {
         }
     }
 
-    pub fn volume(&self) -> f32 {
+    pub fn set_volume(&mut self, v: f32) {
+        self.volume = v.clamp(0f32, 1f32);
+        self.sink.set_volume(self.volume);
+    }
+
+    /// Change the equalizer, including for the song that's playing.
+    pub fn set_equalizer(&self, equalizer: Equalizer) {
+        self.equalizer.set(equalizer);
+    }
+
+    /// The latest samples played, as they go out to be heard.
+    pub fn samples(&self) -> Arc<visualizer::Samples> {
+        self.samples.clone()
+    }
+
+    /// Play faster or slower, between 0.5 and 2 times as fast, including the song that's playing.
+    pub fn set_speed(&self, speed: f32) {
+        self.speed.set(speed);
+    }
+}
+
+impl Playback for Player {
+    fn playing(&self) -> &[PathBuf] {
+        &self.playing
+    }
+
+    fn index(&self) -> usize {
+        self.playing.len() - self.remaining.load(Ordering::Relaxed)
+    }
+
+    fn position(&self) -> Duration {
+        Duration::from_millis(self.position.load(Ordering::Relaxed))
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        match self.duration.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.sink.is_paused()
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.sink.empty()
+    }
+
+    fn volume(&self) -> f32 {
         self.volume
     }
 
-    pub fn set_volume(&mut self, v: f32) {
-        self.volume = if v < 0f32 {
-            0f32
-        } else if v > 1f32 {
-            1f32
-        } else {
-            v
-        };
-        self.sink.set_volume(self.volume);
+    fn is_shuffled(&self) -> bool {
+        self.unshuffled.is_some()
+    }
+
+    fn repeat(&self) -> Repeat {
+        self.repeat
+    }
+
+    fn equalizer(&self) -> Equalizer {
+        self.equalizer.get()
+    }
+
+    fn speed(&self) -> f32 {
+        self.speed.get()
+    }
+
+    fn ab_loop(&self) -> Option<AbLoop> {
+        match &self.ab_loop {
+            Some((song, ab_loop)) if self.playing.get(self.index()) == Some(song) => Some(*ab_loop),
+            _ => None,
+        }
     }
 }
 
 /// Send a message on the given Sender and decrement an AtomicUsize when the inner Source is empty.
 /// Like rodio's built in Done, but with a channel.
+///
+/// Also keeps track of how far into the inner Source playback is and how long it is, in
+/// milliseconds.
 pub struct Signal<I> {
     input: I,
     num: Arc<AtomicUsize>,
+    position: Arc<AtomicU64>,
+    duration: Arc<AtomicU64>,
     sender: Sender<usize>,
     sent: bool,
+    samples: u64,
+    offset: u64,
+    length: Option<Duration>,
+    /// Where to loop back to and from, in milliseconds, and how to start the source over at the
+    /// start of the loop.
+    ab_loop: Option<(u64, u64, Restart<I>)>,
 }
 
+type Restart<I> = Box<dyn FnMut() -> Option<I> + Send>;
+
 impl<I> Signal<I> {
-    pub fn new(input: I, num: Arc<AtomicUsize>, sender: Sender<usize>) -> Signal<I> {
+    pub fn new(
+        input: I,
+        num: Arc<AtomicUsize>,
+        position: Arc<AtomicU64>,
+        duration: Arc<AtomicU64>,
+        sender: Sender<usize>,
+    ) -> Signal<I> {
         Signal {
             input,
             num,
+            position,
+            duration,
             sender,
             sent: false,
+            samples: 0,
+            offset: 0,
+            length: None,
+            ab_loop: None,
         }
     }
+
+    /// Count the position from `offset` instead of zero, for sources that have been skipped into.
+    pub fn starting_at(mut self, offset: Duration) -> Signal<I> {
+        self.offset = offset.as_millis() as u64;
+        self
+    }
+
+    /// Let it be known how long the whole source is, since that can't be asked of it after it's
+    /// been skipped into.
+    pub fn with_length(mut self, length: Option<Duration>) -> Signal<I> {
+        self.length = length;
+        self
+    }
+
+    /// Go back to `a` whenever playback gets to `b`, with `restart` giving the source again
+    /// skipped to `a`. Starting past `b` plays on instead.
+    pub fn looping<F>(mut self, a: Duration, b: Duration, restart: F) -> Signal<I>
+    where
+        F: FnMut() -> Option<I> + Send + 'static,
+    {
+        self.ab_loop = Some((
+            a.as_millis() as u64,
+            b.as_millis() as u64,
+            Box::new(restart),
+        ));
+        self
+    }
 }
 
 impl<I: Source> Iterator for Signal<I>
@@ -130,13 +721,43 @@ where
     type Item = I::Item;
 
     fn next(&mut self) -> Option<I::Item> {
+        if let Some((a, b, restart)) = &mut self.ab_loop {
+            let channels = u64::from(self.input.channels().max(1));
+            let rate = u64::from(self.input.sample_rate()) * channels;
+            let position = self.offset + (self.samples * 1000).checked_div(rate).unwrap_or(0);
+            // only between frames, so the channels don't get swapped
+            if self.offset < *b && position >= *b && self.samples.is_multiple_of(channels) {
+                match restart() {
+                    Some(input) => {
+                        self.input = input;
+                        self.offset = *a;
+                        self.samples = 0;
+                    }
+                    None => self.ab_loop = None,
+                }
+            }
+        }
+
         let next = self.input.next();
-        if !self.sent && next.is_none() {
+        if next.is_some() {
+            if self.samples == 0 {
+                let length = self.length.map_or(0, |d| d.as_millis() as u64);
+                self.duration.store(length, Ordering::Relaxed);
+            }
+            let rate = u64::from(self.input.sample_rate()) * u64::from(self.input.channels());
+            self.samples += 1;
+            if let Some(ms) = (self.samples * 1000).checked_div(rate) {
+                self.position.store(self.offset + ms, Ordering::Relaxed);
+            }
+        } else if !self.sent {
             // with Ordering::Relaxed these might happen out of order, but idk xd
             let n = self.num.fetch_sub(1, Ordering::Relaxed);
             let result = self.sender.send(self.num.load(Ordering::Relaxed));
             if n != 1 && result.is_err() {
-                eprintln!("error writing to channel: {}", result.err().unwrap());
+                status_bar::error(format!(
+                    "error writing to channel: {}",
+                    result.err().unwrap()
+                ));
             }
             self.sent = true;
         }
@@ -165,3 +786,342 @@ where
         self.input.total_duration()
     }
 }
+
+/// Fades a source out over the end of it while fading the start of the next song in, so one runs
+/// into the other instead of cutting. The curves are equal power, so it doesn't dip in the middle.
+///
+/// Only as much of `next`'s start as the fade lasts gets mixed in: the rest of it plays as its own
+/// source afterwards, skipping what was already heard here. That's counted here rather than with
+/// take_duration, which cuts stereo sources short.
+pub struct Crossfade<I, N>
+where
+    I: Source,
+    I::Item: Sample,
+    N: Source,
+    N::Item: Sample,
+{
+    input: I,
+    next: UniformSourceIterator<N, I::Item>,
+    channels: u16,
+    sample_rate: u32,
+    /// How many samples of `input` play before the fade starts, and how many it lasts.
+    start: u64,
+    length: u64,
+    samples: u64,
+}
+
+impl<I, N> Crossfade<I, N>
+where
+    I: Source,
+    I::Item: Sample,
+    N: Source,
+    N::Item: Sample,
+{
+    /// Fade from `input` to `next` over `length`, starting `start` into `input`.
+    pub fn new(input: I, next: N, start: Duration, length: Duration) -> Crossfade<I, N> {
+        let (channels, sample_rate) = (input.channels(), input.sample_rate());
+        let samples = |d: Duration| {
+            let frames = (d.as_secs_f64() * f64::from(sample_rate)) as u64;
+            frames * u64::from(channels)
+        };
+        Crossfade {
+            next: UniformSourceIterator::new(next, channels, sample_rate),
+            input,
+            channels,
+            sample_rate,
+            start: samples(start),
+            length: samples(length).max(1),
+            samples: 0,
+        }
+    }
+
+    fn fading(&self) -> bool {
+        self.samples >= self.start
+    }
+}
+
+impl<I, N> Iterator for Crossfade<I, N>
+where
+    I: Source,
+    I::Item: Sample,
+    N: Source,
+    N::Item: Sample,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        if !self.fading() {
+            self.samples += 1;
+            return self.input.next();
+        }
+
+        let faded = self.samples - self.start;
+        let t = (faded as f32 / self.length as f32).min(1f32);
+        self.samples += 1;
+        let out = self.input.next().map(|s| s.amplify((t * FRAC_PI_2).cos()));
+        let fade_in = if faded < self.length {
+            self.next.next().map(|s| s.amplify((t * FRAC_PI_2).sin()))
+        } else {
+            None
+        };
+        match (out, fade_in) {
+            (Some(a), Some(b)) => Some(a.saturating_add(b)),
+            // the next song carries on if this one ends early, so none of it gets skipped
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+impl<I, N> Source for Crossfade<I, N>
+where
+    I: Source,
+    I::Item: Sample,
+    N: Source,
+    N::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.fading() {
+            None
+        } else {
+            self.input.current_frame_len()
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        if self.fading() {
+            self.channels
+        } else {
+            self.input.channels()
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        if self.fading() {
+            self.sample_rate
+        } else {
+            self.input.sample_rate()
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn decode(path: &Path) -> io::Result<rodio::Decoder<BufReader<File>>> {
+    let f = File::open(path)?;
+    rodio::Decoder::new(BufReader::new(f))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Fisher-Yates with xorshift seeded from the clock, which is plenty random for a queue.
+fn shuffle<T>(items: &mut [T]) {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    let mut state = nanos | 1;
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+    use crate::xdg::TempDir;
+
+    /// `seconds` of `value` in every sample.
+    fn constant(value: f32, channels: u16, seconds: u32) -> SamplesBuffer<f32> {
+        let len = (1000 * seconds * u32::from(channels)) as usize;
+        SamplesBuffer::new(channels, 1000, vec![value; len])
+    }
+
+    #[test]
+    fn crossfade_is_equal_power() {
+        // the last 400ms of one song under the first 400ms of the next, like append does, with
+        // the rest of the next song left for later
+        let fade = Duration::from_millis(400);
+        let out: Vec<f32> = Crossfade::new(
+            constant(0.5, 2, 1),
+            constant(0.5, 2, 1),
+            Duration::from_millis(600),
+            fade,
+        )
+        .collect();
+        assert_eq!(out.len(), 2000);
+        // untouched before the fade starts
+        assert!(out[..1200].iter().all(|s| *s == 0.5));
+        // halfway through, both are at cos(π/4), so the power's the same as either alone
+        let middle = out[1600];
+        assert!(
+            (middle - 0.5 * std::f32::consts::SQRT_2).abs() < 0.01,
+            "{}",
+            middle
+        );
+        // and by the end it's all the next song
+        assert!(out[1990..].iter().all(|s| (s - 0.5).abs() < 0.01));
+    }
+
+    #[test]
+    fn crossfade_carries_on_with_the_next_song() {
+        let fade = Duration::from_millis(200);
+        let out: Vec<f32> = Crossfade::new(
+            constant(0.5, 1, 1),
+            constant(0.25, 1, 2),
+            Duration::from_millis(900),
+            fade,
+        )
+        .collect();
+        // the first song ran out halfway through the fade, but none of the next one's skipped
+        assert_eq!(out.len(), 1100);
+        let expected = 0.25 * (0.75 * FRAC_PI_2).sin();
+        assert!((out[1050] - expected).abs() < 1e-3, "{}", out[1050]);
+    }
+
+    /// A stereo source where each frame holds its own number, negated on the right, at 1000 frames
+    /// a second so frames are milliseconds.
+    fn ramp(from: u32, to: u32) -> SamplesBuffer<f32> {
+        let samples: Vec<f32> = (from..to).flat_map(|i| [i as f32, -(i as f32)]).collect();
+        SamplesBuffer::new(2, 1000, samples)
+    }
+
+    #[test]
+    fn signal_loops_and_says_when_it_ends() {
+        let (sender, receiver) = channel();
+        let (num, position) = (Arc::new(AtomicUsize::new(2)), Arc::new(AtomicU64::new(0)));
+        let mut signal = Signal::new(
+            ramp(0, 400),
+            num.clone(),
+            position.clone(),
+            Arc::default(),
+            sender,
+        )
+        .looping(
+            Duration::from_millis(200),
+            Duration::from_millis(300),
+            || Some(ramp(200, 400)),
+        );
+
+        let frames: Vec<f32> = signal.by_ref().take(2 * 450).collect();
+        let left: Vec<f32> = frames.iter().step_by(2).copied().collect();
+        assert_eq!(left[299], 299f32);
+        // back to A at B, a whole frame at a time, as many times as it gets there
+        assert_eq!(left[300], 200f32);
+        assert_eq!(left[399], 299f32);
+        assert_eq!(left[400], 200f32);
+        assert_eq!(left[449], 249f32);
+        assert!(frames.chunks(2).all(|f| f[0] == -f[1]));
+        assert_eq!(position.load(Ordering::Relaxed), 250);
+        assert!(receiver.try_recv().is_err());
+
+        // a song playing to the end says how many are left after it
+        let (sender, receiver) = channel();
+        let signal = Signal::new(
+            ramp(0, 10),
+            num.clone(),
+            position.clone(),
+            Arc::default(),
+            sender,
+        )
+        .starting_at(Duration::from_secs(60));
+        assert_eq!(signal.count(), 20);
+        assert_eq!(position.load(Ordering::Relaxed), 60_010);
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(num.load(Ordering::Relaxed), 1);
+    }
+
+    /// A tenth of a second of silence, as a mono WAV file.
+    fn wav(path: &Path) {
+        let (rate, frames) = (8000u32, 800u32);
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + frames * 2).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * 2).to_le_bytes());
+        // two bytes a frame, 16 bits a sample
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(frames * 2).to_le_bytes());
+        wav.resize(wav.len() + frames as usize * 2, 0);
+        fs::write(path, wav).unwrap();
+    }
+
+    #[test]
+    fn songs_that_cant_be_decoded_leave_the_queue_alone() {
+        let dir = TempDir::new("player");
+        let (good, corrupt) = (dir.join("good.wav"), dir.join("corrupt.wav"));
+        wav(&good);
+        // like a download that never got going
+        fs::write(&corrupt, b"").unwrap();
+        let (mut player, sinks) = Player::idle();
+
+        let switches = player.enqueue(vec![good.clone()]).unwrap().unwrap();
+        let sink = sinks.try_recv().unwrap();
+        assert!(player.enqueue(vec![good.clone(), corrupt.clone()]).is_err());
+        assert!(player.play_songs(0, vec![good.clone(), corrupt]).is_err());
+        assert!(sinks.try_recv().is_err());
+        assert_eq!(player.playing(), [good]);
+        assert_eq!(player.index(), 0);
+
+        // once the one song that made it in is over, so is the queue
+        sink.take(4000).for_each(drop);
+        assert_eq!(switches.try_recv(), Ok(0));
+        assert!(switches.try_recv().is_err());
+        assert_eq!(player.index(), 1);
+        assert!(player.is_stopped());
+    }
+
+    #[test]
+    fn enqueued_songs_are_faded_into() {
+        let dir = TempDir::new("player-fade");
+        let songs: Vec<PathBuf> = ["a.wav", "b.wav", "c.wav"]
+            .iter()
+            .map(|s| dir.join(s))
+            .collect();
+        for song in &songs {
+            wav(song);
+        }
+        let (mut player, sinks) = Player::idle();
+        player.set_crossfade(1f32, false);
+
+        player.enqueue(vec![songs[0].clone()]).unwrap().unwrap();
+        assert!(sinks.try_recv().is_ok());
+        // the first song has to be put in again to fade into the second
+        let switches = player.enqueue(vec![songs[1].clone()]).unwrap().unwrap();
+        let sink = sinks.try_recv().unwrap();
+        assert_eq!(player.playing(), &songs[..2]);
+        assert_eq!(player.index(), 0);
+
+        // without the fade, a tenth of a second each is 1600 samples, but halfway through the
+        // first they start overlapping
+        let mut samples = 0;
+        for _ in sink.take(4000) {
+            if player.is_stopped() {
+                break;
+            }
+            samples += 1;
+        }
+        assert!(samples < 1400, "{}", samples);
+        assert_eq!(switches.try_iter().collect::<Vec<_>>(), [1, 0]);
+
+        // and with nothing to fade, they're just added on
+        player.set_crossfade(0f32, false);
+        player.enqueue(vec![songs[0].clone()]).unwrap().unwrap();
+        assert!(sinks.try_recv().is_ok());
+        assert!(player.enqueue(vec![songs[2].clone()]).unwrap().is_none());
+        assert!(sinks.try_recv().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::explorer::View;
+use crate::query::Query;
+use crate::{library, status_bar};
+
+/// A playlist made of whatever songs in the library match a query, worked out again whenever
+/// it's opened.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PlaylistConfig {
+    pub name: String,
+    /// See Query.
+    pub query: String,
+}
+
+struct SmartPlaylist {
+    name: String,
+    query: Query,
+}
+
+impl View for SmartPlaylist {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn songs(&self) -> Vec<PathBuf> {
+        self.query.run(&library::tracks())
+    }
+}
+
+/// The playlists from the config for the explorer, leaving out any with a query that doesn't
+/// make sense or a name that can't be shown.
+pub fn views(playlists: &[PlaylistConfig]) -> Vec<Box<dyn View>> {
+    let mut views: Vec<Box<dyn View>> = Vec::new();
+    for playlist in playlists {
+        // views are named like paths, so a slash would cut the name short, and these don't have
+        // a file name at all
+        let name = playlist.name.replace('/', "-");
+        if matches!(name.as_str(), "" | "." | "..") {
+            status_bar::error(format!("playlist \"{}\" needs a name", playlist.name));
+            continue;
+        }
+        match Query::parse(&playlist.query) {
+            Ok(query) => views.push(Box::new(SmartPlaylist { name, query })),
+            Err(e) => status_bar::error(format!("error in playlist {}: {}", playlist.name, e)),
+        }
+    }
+    views
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let playlists: Vec<PlaylistConfig> = ["Jazz", "AC/DC", "", ".", "..", "/", "Broken"]
+            .iter()
+            .map(|name| PlaylistConfig {
+                name: name.to_string(),
+                query: match *name {
+                    "Broken" => "year ~ 19",
+                    _ => r#"genre = "jazz""#,
+                }
+                .to_string(),
+            })
+            .collect();
+        let views = views(&playlists);
+        let names: Vec<&str> = views.iter().map(|v| v.name()).collect();
+        assert_eq!(names, ["Jazz", "AC-DC", "-"]);
+        for name in names {
+            assert!(PathBuf::from(name).file_name().is_some(), "{}", name);
+        }
+    }
+}
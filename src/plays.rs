@@ -0,0 +1,259 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::song::Details;
+use crate::{Playback, Song};
+
+/// A song counts as listened to after half of it or this much, whichever comes first.
+const LISTEN_AFTER: Duration = Duration::from_secs(240);
+/// Songs shorter than this never count.
+const MIN_LENGTH: Duration = Duration::from_secs(30);
+/// How close to the end a song has to get to have been played all the way through.
+const END_SLACK: Duration = Duration::from_secs(2);
+
+/// One time through a song, from when it started until something else did.
+pub struct Play {
+    /// Counts up with every play, to tell plays of the same song apart.
+    pub id: u64,
+    pub path: PathBuf,
+    index: usize,
+    pub song: Song,
+    pub length: Option<Duration>,
+    /// When it started, in seconds since the epoch.
+    pub started: u64,
+    /// How long it was actually heard for, not counting pauses or seeks.
+    pub listened: Duration,
+    /// How far into the song playback got.
+    pub position: Duration,
+    /// Whether it played to the end instead of being skipped.
+    pub completed: bool,
+    last: Instant,
+}
+
+impl Play {
+    /// Whether it was listened to long enough to count.
+    pub fn counts(&self) -> bool {
+        counts(self.listened, self.length)
+    }
+}
+
+/// Whether hearing `listened` of a song `length` long counts as listening to it, going by the
+/// usual scrobbling rules. The history's top lists go by the same ones.
+pub fn counts(listened: Duration, length: Option<Duration>) -> bool {
+    match length {
+        Some(length) if length < MIN_LENGTH => false,
+        Some(length) => listened >= (length / 2).min(LISTEN_AFTER),
+        None => listened >= LISTEN_AFTER,
+    }
+}
+
+/// Follows the player from one update to the next, working out when each song starts and stops
+/// being played, and how much of it was heard.
+#[derive(Default)]
+pub struct Tracker {
+    play: Option<Play>,
+    next_id: u64,
+}
+
+impl Tracker {
+    /// Catch up with the player, returning the play that ended if the song changed.
+    pub fn update(&mut self, player: &dyn Playback) -> Option<Play> {
+        let index = player.index();
+        let song = player.playing().get(index).filter(|_| !player.is_stopped());
+        let same = match (&self.play, song) {
+            (Some(play), Some(path)) => play.path == *path && play.index == index,
+            (None, None) => true,
+            _ => false,
+        };
+
+        let mut ended = None;
+        if !same {
+            ended = self.play.take().map(|mut play| {
+                // the player moving on by itself between updates looks the same as a skip
+                play.completed = play.length.is_some_and(|l| play.position + END_SLACK >= l);
+                play
+            });
+            self.play = song.map(|path| self.start(path, index, player));
+        }
+
+        if let Some(play) = &mut self.play {
+            let now = Instant::now();
+            if !player.is_paused() {
+                play.listened += now - play.last;
+            }
+            play.last = now;
+            play.position = player.position();
+        }
+
+        ended
+    }
+
+    /// The song at `index` played to the end, so whatever plays next is a new play, even if it's
+    /// the same song again. Returns the play that ended, unless the tracker already moved on.
+    pub fn finished(&mut self, index: usize) -> Option<Play> {
+        if self.play.as_ref()?.index != index {
+            return None;
+        }
+        self.play.take().map(|mut play| {
+            play.completed = true;
+            play
+        })
+    }
+
+    pub fn current(&self) -> Option<&Play> {
+        self.play.as_ref()
+    }
+
+    fn start(&mut self, path: &PathBuf, index: usize, player: &dyn Playback) -> Play {
+        let details = Details::read(path).unwrap_or_else(|e| {
+            log::debug!("no tags for {}: {}", path.display(), e);
+            Details {
+                song: Song::new(path),
+                ..Details::default()
+            }
+        });
+        self.next_id += 1;
+
+        Play {
+            id: self.next_id,
+            path: path.clone(),
+            index,
+            song: details.song,
+            length: details.duration.or_else(|| player.duration()),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            listened: Duration::from_secs(0),
+            position: Duration::from_secs(0),
+            completed: false,
+            last: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::equalizer::Equalizer;
+    use crate::{AbLoop, Repeat};
+
+    /// Just enough of a player to follow.
+    struct Fake {
+        playing: Vec<PathBuf>,
+        index: usize,
+        position: Duration,
+        paused: bool,
+    }
+
+    impl Playback for Fake {
+        fn playing(&self) -> &[PathBuf] {
+            &self.playing
+        }
+
+        fn index(&self) -> usize {
+            self.index
+        }
+
+        fn position(&self) -> Duration {
+            self.position
+        }
+
+        fn duration(&self) -> Option<Duration> {
+            Some(Duration::from_secs(100))
+        }
+
+        fn is_paused(&self) -> bool {
+            self.paused
+        }
+
+        fn is_stopped(&self) -> bool {
+            self.index >= self.playing.len()
+        }
+
+        fn volume(&self) -> f32 {
+            1f32
+        }
+
+        fn is_shuffled(&self) -> bool {
+            false
+        }
+
+        fn repeat(&self) -> Repeat {
+            Repeat::Off
+        }
+
+        fn equalizer(&self) -> Equalizer {
+            Equalizer::default()
+        }
+
+        fn speed(&self) -> f32 {
+            1f32
+        }
+
+        fn ab_loop(&self) -> Option<AbLoop> {
+            None
+        }
+    }
+
+    fn player() -> Fake {
+        Fake {
+            playing: vec![PathBuf::from("/plays-test/a.flac"); 2],
+            index: 0,
+            position: Duration::from_secs(0),
+            paused: true,
+        }
+    }
+
+    #[test]
+    fn follows_the_player() {
+        let mut player = player();
+        let mut tracker = Tracker::default();
+        assert!(tracker.update(&player).is_none());
+        let first = tracker.current().unwrap();
+        assert_eq!((first.id, first.song.title.as_str()), (1, "a"));
+        assert_eq!(first.length, Some(Duration::from_secs(100)));
+
+        // paused time doesn't count
+        thread::sleep(Duration::from_millis(20));
+        tracker.update(&player);
+        assert_eq!(tracker.current().unwrap().listened, Duration::from_secs(0));
+        player.paused = false;
+        tracker.update(&player);
+        thread::sleep(Duration::from_millis(20));
+        player.position = Duration::from_secs(30);
+        tracker.update(&player);
+        assert!(tracker.current().unwrap().listened >= Duration::from_millis(20));
+        assert!(!tracker.current().unwrap().counts());
+
+        // the same song again is another play, and moving on early is a skip
+        player.index = 1;
+        let skipped = tracker.update(&player).unwrap();
+        assert_eq!(skipped.id, 1);
+        assert_eq!(skipped.position, Duration::from_secs(30));
+        assert!(!skipped.completed);
+        assert_eq!(tracker.current().unwrap().id, 2);
+
+        assert!(tracker.finished(0).is_none());
+        let finished = tracker.finished(1).unwrap();
+        assert!(finished.completed);
+        assert!(tracker.current().is_none());
+
+        player.index = 2;
+        assert!(tracker.update(&player).is_none());
+        assert!(tracker.current().is_none());
+    }
+
+    #[test]
+    fn near_the_end_counts_as_completed() {
+        let mut player = player();
+        let mut tracker = Tracker::default();
+        tracker.update(&player);
+        player.position = Duration::from_secs(99);
+        tracker.update(&player);
+        player.index = 1;
+        assert!(tracker.update(&player).unwrap().completed);
+    }
+}
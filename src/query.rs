@@ -0,0 +1,617 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history;
+use crate::library::Track;
+use crate::ratings;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Field {
+    Artist,
+    Album,
+    Title,
+    Genre,
+    Path,
+    Year,
+    Track,
+    /// In seconds.
+    Duration,
+    Rating,
+    Favorite,
+    /// How many times it's been played.
+    Plays,
+    /// Days since it was last played.
+    Played,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Kind {
+    Text,
+    Number,
+    Bool,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        Some(match name {
+            "artist" => Field::Artist,
+            "album" => Field::Album,
+            "title" => Field::Title,
+            "genre" => Field::Genre,
+            "path" => Field::Path,
+            "year" => Field::Year,
+            "track" => Field::Track,
+            "duration" => Field::Duration,
+            "rating" => Field::Rating,
+            "favorite" => Field::Favorite,
+            "plays" => Field::Plays,
+            "played" => Field::Played,
+            _ => return None,
+        })
+    }
+
+    fn kind(self) -> Kind {
+        match self {
+            Field::Artist | Field::Album | Field::Title | Field::Genre | Field::Path => Kind::Text,
+            Field::Favorite => Kind::Bool,
+            _ => Kind::Number,
+        }
+    }
+
+    /// The field's value for a track, or None if it doesn't have one, like a song with no year.
+    fn value(self, track: &Track, now: u64) -> Option<Value> {
+        let text = |s: &str| Some(Value::Text(s.to_lowercase()));
+        let number = |n: Option<u32>| n.map(|n| Value::Number(f64::from(n)));
+        match self {
+            Field::Artist => text(&track.artist),
+            Field::Album => text(&track.album),
+            Field::Title => text(&track.title),
+            Field::Genre => track.genre.as_deref().and_then(text),
+            Field::Path => text(&track.path.to_string_lossy()),
+            Field::Year => number(track.year),
+            Field::Track => number(track.track),
+            Field::Duration => track.duration.map(Value::Number),
+            Field::Rating => Some(Value::Number(f64::from(ratings::get(&track.path).stars))),
+            Field::Favorite => Some(Value::Bool(ratings::get(&track.path).favorite)),
+            Field::Plays => Some(Value::Number(history::played(&track.path).count as f64)),
+            Field::Played => history::played(&track.path)
+                .last
+                .map(|last| Value::Number(now.saturating_sub(last) as f64 / 86400f64)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Value {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl Value {
+    fn kind(&self) -> Kind {
+        match self {
+            Value::Text(_) => Kind::Text,
+            Value::Number(_) => Kind::Number,
+            Value::Bool(_) => Kind::Bool,
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        match self {
+            Value::Text(s) => !s.is_empty(),
+            Value::Number(n) => *n != 0f64,
+            Value::Bool(b) => *b,
+        }
+    }
+
+    fn cmp(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Compare(Field, Op, Value),
+    Set(Field),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn matches(&self, track: &Track, now: u64) -> bool {
+        match self {
+            Expr::Compare(field, op, value) => {
+                let actual = match field.value(track, now) {
+                    Some(v) => v,
+                    None => return false,
+                };
+                if let (Op::Contains, Value::Text(a), Value::Text(b)) = (op, &actual, value) {
+                    return a.contains(b.as_str());
+                }
+                let ordering = match actual.cmp(value) {
+                    Some(o) => o,
+                    None => return false,
+                };
+                match op {
+                    Op::Eq => ordering == Ordering::Equal,
+                    Op::Ne => ordering != Ordering::Equal,
+                    Op::Lt => ordering == Ordering::Less,
+                    Op::Le => ordering != Ordering::Greater,
+                    Op::Gt => ordering == Ordering::Greater,
+                    Op::Ge => ordering != Ordering::Less,
+                    Op::Contains => false,
+                }
+            }
+            Expr::Set(field) => field.value(track, now).is_some_and(|v| v.is_set()),
+            Expr::Not(e) => !e.matches(track, now),
+            Expr::And(a, b) => a.matches(track, now) && b.matches(track, now),
+            Expr::Or(a, b) => a.matches(track, now) || b.matches(track, now),
+        }
+    }
+}
+
+/// Which songs to pick out of the library, like
+/// `genre = "jazz" and year < 1970 and rating >= 4 sort by year desc limit 50`.
+///
+/// Conditions compare a field to a value with `=`, `!=`, `<`, `<=`, `>`, `>=` or `~` (contains),
+/// and combine with `and`, `or`, `not` and parentheses. A field on its own is true when it's set,
+/// like `favorite`. Text is compared ignoring case.
+#[derive(Debug)]
+pub struct Query {
+    filter: Option<Expr>,
+    sort: Option<(Field, bool)>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Result<Query, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            next: 0,
+        };
+        let rest =
+            parser.peek().is_none() || parser.at_keyword("sort") || parser.at_keyword("limit");
+        let filter = if rest { None } else { Some(parser.or()?) };
+
+        let mut sort = None;
+        if parser.eat_keyword("sort") {
+            parser.expect_keyword("by")?;
+            let field = parser.field()?;
+            let descending = if parser.eat_keyword("desc") {
+                true
+            } else {
+                parser.eat_keyword("asc");
+                false
+            };
+            sort = Some((field, descending));
+        }
+
+        let mut limit = None;
+        if parser.eat_keyword("limit") {
+            limit = match parser.take() {
+                Some(Token::Number(n)) if n >= 0f64 && n.fract() == 0f64 => Some(n as usize),
+                _ => return Err(Error::new("limit needs a whole number")),
+            };
+        }
+
+        match parser.take() {
+            None => Ok(Query {
+                filter,
+                sort,
+                limit,
+            }),
+            Some(t) => Err(Error::new(format!("unexpected {}", t))),
+        }
+    }
+
+    /// The paths of the tracks that match, sorted and limited.
+    pub fn run(&self, tracks: &[Track]) -> Vec<PathBuf> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut matched: Vec<&Track> = tracks
+            .iter()
+            .filter(|t| self.filter.as_ref().is_none_or(|f| f.matches(t, now)))
+            .collect();
+
+        if let Some((field, descending)) = self.sort {
+            let mut keyed: Vec<(Option<Value>, &Track)> = matched
+                .into_iter()
+                .map(|t| (field.value(t, now), t))
+                .collect();
+            // tracks without the field go last either way
+            keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+                (Some(a), Some(b)) => {
+                    let ordering = a.cmp(b).unwrap_or(Ordering::Equal);
+                    if descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            });
+            matched = keyed.into_iter().map(|(_, t)| t).collect();
+        }
+
+        matched
+            .into_iter()
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|t| t.path.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Error {
+    fn new<S: Into<String>>(message: S) -> Error {
+        Error(message.into())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(f64),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "{}", w),
+            Token::Text(s) => write!(f, "\"{}\"", s),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Op(op) => f.write_str(match op {
+                Op::Eq => "=",
+                Op::Ne => "!=",
+                Op::Lt => "<",
+                Op::Le => "<=",
+                Op::Gt => ">",
+                Op::Ge => ">=",
+                Op::Contains => "~",
+            }),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(ch) => text.push(ch),
+                        None => return Err(Error::new("unclosed quote")),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                chars.next();
+                let equals = chars.next_if_eq(&'=').is_some();
+                let op = match (c, equals) {
+                    ('=', _) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    ('~', false) => Op::Contains,
+                    _ => return Err(Error::new(format!("unknown operator {}", c))),
+                };
+                tokens.push(Token::Op(op));
+            }
+            c if c.is_ascii_digit() || c == '.' || c == '-' => {
+                let mut number = String::new();
+                while let Some(ch) = chars.next_if(|ch| ch.is_ascii_digit() || "-.".contains(*ch)) {
+                    number.push(ch);
+                }
+                let n = number
+                    .parse()
+                    .map_err(|_| Error::new(format!("bad number {}", number)))?;
+                tokens.push(Token::Number(n));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(ch) = chars.next_if(|ch| ch.is_alphanumeric() || *ch == '_') {
+                    word.push(ch);
+                }
+                tokens.push(Token::Word(word.to_lowercase()));
+            }
+            c => return Err(Error::new(format!("unexpected {}", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let at = self.at_keyword(keyword);
+        if at {
+            self.next += 1;
+        }
+        at
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(Error::new(format!("expected {}", keyword)))
+        }
+    }
+
+    fn field(&mut self) -> Result<Field, Error> {
+        match self.take() {
+            Some(Token::Word(w)) => {
+                Field::parse(&w).ok_or_else(|| Error::new(format!("unknown field {}", w)))
+            }
+            Some(t) => Err(Error::new(format!("expected a field, got {}", t))),
+            None => Err(Error::new("expected a field")),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.and()?;
+        while self.eat_keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.not()?;
+        while self.eat_keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, Error> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.next += 1;
+            let expr = self.or()?;
+            return match self.take() {
+                Some(Token::Close) => Ok(expr),
+                _ => Err(Error::new("expected )")),
+            };
+        }
+        self.condition()
+    }
+
+    fn condition(&mut self) -> Result<Expr, Error> {
+        let field = self.field()?;
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return Ok(Expr::Set(field)),
+        };
+        self.next += 1;
+
+        let value = match self.take() {
+            Some(Token::Text(s)) => Value::Text(s.to_lowercase()),
+            Some(Token::Number(n)) => Value::Number(n),
+            Some(Token::Word(w)) if w == "true" => Value::Bool(true),
+            Some(Token::Word(w)) if w == "false" => Value::Bool(false),
+            Some(t) => return Err(Error::new(format!("expected a value, got {}", t))),
+            None => return Err(Error::new("expected a value")),
+        };
+        if value.kind() != field.kind() {
+            return Err(Error::new(
+                format!(
+                    "{:?} is {}",
+                    field,
+                    match field.kind() {
+                        Kind::Text => "text",
+                        Kind::Number => "a number",
+                        Kind::Bool => "true or false",
+                    }
+                )
+                .to_lowercase(),
+            ));
+        }
+        if op == Op::Contains && field.kind() != Kind::Text {
+            return Err(Error::new("~ only works on text"));
+        }
+
+        Ok(Expr::Compare(field, op, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::xdg;
+
+    /// A few songs, with the jazz ones under /query-test/jazz rated by how many stars they get.
+    fn tracks() -> Vec<Track> {
+        xdg::isolate();
+        let tracks = [
+            ("jazz/kind of blue.flac", "Miles Davis", "Jazz", 1959, 4),
+            ("jazz/a love supreme.flac", "John Coltrane", "jazz", 1965, 5),
+            ("jazz/head hunters.flac", "Herbie Hancock", "Jazz", 1973, 5),
+            ("jazz/time out.flac", "Dave Brubeck", "Jazz", 1959, 2),
+            ("rock/abbey road.mp3", "The Beatles", "Rock", 1969, 0),
+        ];
+        tracks
+            .iter()
+            .map(|&(path, artist, genre, year, stars)| {
+                let path = PathBuf::from("/query-test").join(path);
+                if stars > 0 {
+                    ratings::rate(std::slice::from_ref(&path), stars);
+                }
+                serde_json::from_value(json!({
+                    "path": path,
+                    "artist": artist,
+                    "album": "",
+                    "title": path.file_stem().unwrap().to_string_lossy(),
+                    "genre": genre,
+                    "year": year,
+                    "track": null,
+                    "duration": null,
+                    "modified": 0,
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn titles(query: &str) -> Vec<String> {
+        Query::parse(query)
+            .unwrap()
+            .run(&tracks())
+            .iter()
+            .map(|p| p.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn error(query: &str) -> String {
+        Query::parse(query).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn example() {
+        assert_eq!(
+            titles(r#"genre = "jazz" and year < 1970 and rating >= 4"#),
+            ["kind of blue", "a love supreme"]
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        // and binds tighter than or
+        assert_eq!(
+            titles("year = 1973 or year = 1959 and rating = 2"),
+            ["head hunters", "time out"]
+        );
+        assert_eq!(
+            titles("(year = 1973 or year = 1959) and rating >= 4"),
+            ["kind of blue", "head hunters"]
+        );
+        // and not only to the condition right after it
+        assert_eq!(
+            titles("not genre = 'jazz' or year > 1970"),
+            ["head hunters", "abbey road"]
+        );
+        assert_eq!(
+            titles("not (genre = 'jazz' or year > 1970)"),
+            ["abbey road"]
+        );
+        assert_eq!(titles("not not rating"), titles("rating"));
+        assert_eq!(titles("artist ~ 'DAVIS' or artist ~ \"beat\"").len(), 2);
+    }
+
+    #[test]
+    fn sort_and_limit() {
+        assert_eq!(
+            titles("genre = 'jazz' sort by year desc limit 2"),
+            ["head hunters", "a love supreme"]
+        );
+        assert_eq!(
+            titles("sort by rating desc limit 3"),
+            ["a love supreme", "head hunters", "kind of blue"]
+        );
+        // songs without the field go last, however it's sorted
+        assert_eq!(titles("sort by track limit 1").len(), 1);
+        assert_eq!(titles("limit 0"), Vec::<String>::new());
+        assert_eq!(titles("").len(), 5);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("tempo > 120"), "unknown field tempo");
+        assert_eq!(error("year = 'old'"), "year is a number");
+        assert_eq!(error("genre = 3"), "genre is text");
+        assert_eq!(error("favorite = 1"), "favorite is true or false");
+        assert_eq!(error("year ~ 19"), "~ only works on text");
+        assert_eq!(error("rating ~ 'x'"), "rating is a number");
+        assert_eq!(error("genre ~ 'jazz' and"), "expected a field");
+        assert_eq!(error("genre = "), "expected a value");
+        assert_eq!(error("genre = 'jazz"), "unclosed quote");
+        assert_eq!(error("(genre = 'jazz'"), "expected )");
+        assert_eq!(error("year > 1 )"), "unexpected )");
+        assert_eq!(error("year =< 1"), "expected a value, got <");
+        assert_eq!(error("year ! 1"), "unknown operator !");
+        assert_eq!(error("sort year"), "expected by");
+        assert_eq!(error("limit 2.5"), "limit needs a whole number");
+        assert_eq!(error("year > 1.2.3"), "bad number 1.2.3");
+        assert_eq!(error("year # 1"), "unexpected #");
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use lofty::config::WriteOptions;
+use lofty::id3::v2::PopularimeterFrame;
+use lofty::prelude::*;
+use lofty::tag::{ItemValue, TagItem, TagType};
+use serde::{Deserialize, Serialize};
+
+use crate::explorer::View;
+use crate::{history, status_bar, xdg};
+
+/// The most stars a song can have.
+pub const MAX_STARS: u8 = 5;
+/// How many stars a song needs to count as highly rated.
+const HIGHLY_RATED: u8 = 4;
+/// How long a song has to go without being played to count as forgotten.
+const FORGOTTEN_AFTER: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How often to check whether another bebop changed the ratings.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+/// Who POPM frames are written as. Most players that read POPM only read Windows Media Player's.
+const POPM_EMAIL: &str = "Windows Media Player 9 Series";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RatingsConfig {
+    /// Also write ratings into the songs' tags, as POPM in ID3v2 and FMPS_RATING in Vorbis
+    /// comments.
+    pub write_tags: bool,
+}
+
+/// What's been said about a song.
+#[derive(Serialize, Deserialize, Copy, Clone, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct Rating {
+    /// From 0 for unrated to MAX_STARS.
+    pub stars: u8,
+    pub favorite: bool,
+}
+
+/// Every rated or favorited song, kept in ratings.json in the state directory.
+#[derive(Default)]
+struct Ratings {
+    songs: HashMap<PathBuf, Rating>,
+    write_tags: bool,
+    modified: Option<SystemTime>,
+    checked: Option<Instant>,
+}
+
+impl Ratings {
+    /// Read the ratings again if another bebop saved them since they were last read.
+    fn refresh(&mut self) {
+        if self.checked.is_some_and(|c| c.elapsed() < RELOAD_INTERVAL) {
+            return;
+        }
+        self.checked = Some(Instant::now());
+
+        let modified = modified();
+        if self.modified == modified {
+            return;
+        }
+        match load() {
+            Ok(songs) => {
+                self.songs = songs;
+                self.modified = modified;
+            }
+            Err(e) => status_bar::error(format!("error reading ratings: {}", e)),
+        }
+    }
+
+    fn change<F: Fn(&mut Rating)>(&mut self, paths: &[PathBuf], f: F) {
+        self.refresh();
+        for path in paths {
+            let rating = self.songs.entry(path.clone()).or_default();
+            let stars = rating.stars;
+            f(rating);
+            if self.write_tags && rating.stars != stars {
+                if let Err(e) = write_tag(path, rating.stars) {
+                    status_bar::error(format!("error tagging {}: {}", path.display(), e));
+                }
+            }
+        }
+        self.songs.retain(|_, r| *r != Rating::default());
+
+        match save(&self.songs) {
+            Ok(()) => self.modified = modified(),
+            Err(e) => status_bar::error(format!("error saving ratings: {}", e)),
+        }
+    }
+}
+
+fn ratings() -> &'static Mutex<Ratings> {
+    static RATINGS: OnceLock<Mutex<Ratings>> = OnceLock::new();
+    RATINGS.get_or_init(Mutex::default)
+}
+
+/// Set things up from the config. Ratings work without it, but don't get written to tags.
+pub fn init(config: &RatingsConfig) {
+    ratings().lock().unwrap().write_tags = config.write_tags;
+}
+
+pub fn get(path: &Path) -> Rating {
+    let mut ratings = ratings().lock().unwrap();
+    ratings.refresh();
+    ratings.songs.get(path).copied().unwrap_or_default()
+}
+
+/// Give each of `paths` a number of stars, up to MAX_STARS. 0 takes the rating away.
+pub fn rate(paths: &[PathBuf], stars: u8) {
+    let stars = stars.min(MAX_STARS);
+    ratings()
+        .lock()
+        .unwrap()
+        .change(paths, |rating| rating.stars = stars);
+}
+
+/// Favorite `paths`, or unfavorite them if they all already are.
+pub fn toggle_favorite(paths: &[PathBuf]) {
+    let mut ratings = ratings().lock().unwrap();
+    ratings.refresh();
+    let favorite = !paths
+        .iter()
+        .all(|p| ratings.songs.get(p).is_some_and(|r| r.favorite));
+    ratings.change(paths, |rating| rating.favorite = favorite);
+}
+
+/// Every song with a rating or a favorite, sorted by path. Songs that were moved or deleted are
+/// left out.
+fn rated() -> Vec<(PathBuf, Rating)> {
+    let mut ratings = ratings().lock().unwrap();
+    ratings.refresh();
+    let mut rated: Vec<(PathBuf, Rating)> = ratings
+        .songs
+        .iter()
+        .filter(|(path, _)| path.exists())
+        .map(|(path, rating)| (path.clone(), *rating))
+        .collect();
+    rated.sort_by(|(a, _), (b, _)| a.cmp(b));
+    rated
+}
+
+fn path() -> Option<PathBuf> {
+    xdg::state_dir().map(|d| d.join("ratings.json"))
+}
+
+fn modified() -> Option<SystemTime> {
+    path()
+        .and_then(|p| fs::metadata(p).ok())
+        .and_then(|m| m.modified().ok())
+}
+
+fn load() -> io::Result<HashMap<PathBuf, Rating>> {
+    let path = match path() {
+        Some(p) => p,
+        None => return Ok(HashMap::new()),
+    };
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(serde_json::from_str(&s)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save(songs: &HashMap<PathBuf, Rating>) -> io::Result<()> {
+    let path =
+        path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(songs)?)?;
+    fs::rename(tmp, path)
+}
+
+/// Write a rating into a song's tag, in whichever way its kind of tag has for it. Tags with no
+/// way to hold a rating are left alone.
+fn write_tag(path: &Path, stars: u8) -> io::Result<()> {
+    let mut tagged = lofty::read_from_path(path).map_err(io::Error::other)?;
+    let tag_type = tagged.primary_tag_type();
+    let tag = match tagged.tag_mut(tag_type) {
+        Some(t) => t,
+        None => {
+            tagged.insert_tag(lofty::tag::Tag::new(tag_type));
+            tagged.tag_mut(tag_type).unwrap()
+        }
+    };
+
+    match tag_type {
+        TagType::Id3v2 => {
+            tag.remove_key(&ItemKey::Popularimeter);
+            if stars > 0 {
+                // the usual mapping of stars to 1-255, from Windows Media Player
+                let rating = [1, 64, 128, 196, 255][stars as usize - 1];
+                let popm = PopularimeterFrame::new(POPM_EMAIL.to_string(), rating, 0);
+                let bytes = popm.as_bytes().map_err(io::Error::other)?;
+                tag.insert_unchecked(TagItem::new(
+                    ItemKey::Popularimeter,
+                    ItemValue::Binary(bytes),
+                ));
+            }
+        }
+        TagType::VorbisComments => {
+            let key = ItemKey::Unknown("FMPS_RATING".to_string());
+            tag.remove_key(&key);
+            if stars > 0 {
+                let rating = f32::from(stars) / f32::from(MAX_STARS);
+                tag.insert_unchecked(TagItem::new(key, ItemValue::Text(rating.to_string())));
+            }
+        }
+        _ => return Ok(()),
+    }
+
+    tag.save_to_path(path, WriteOptions::default())
+        .map_err(io::Error::other)
+}
+
+/// Every favorite song.
+struct Favorites;
+
+impl View for Favorites {
+    fn name(&self) -> &str {
+        "Favorites"
+    }
+
+    fn songs(&self) -> Vec<PathBuf> {
+        rated()
+            .into_iter()
+            .filter(|(_, rating)| rating.favorite)
+            .map(|(path, _)| path)
+            .collect()
+    }
+}
+
+/// Highly rated songs that haven't been played in a while.
+struct Forgotten;
+
+impl View for Forgotten {
+    fn name(&self) -> &str {
+        "Highly rated, not played in 30 days"
+    }
+
+    fn songs(&self) -> Vec<PathBuf> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let since = now.saturating_sub(FORGOTTEN_AFTER.as_secs());
+        rated()
+            .into_iter()
+            .filter(|(_, rating)| rating.stars >= HIGHLY_RATED)
+            .filter(|(path, _)| history::played(path).last.is_none_or(|last| last < since))
+            .map(|(path, _)| path)
+            .collect()
+    }
+}
+
+/// The views of the library that ratings make possible, for the explorer.
+pub fn views() -> Vec<Box<dyn View>> {
+    vec![Box::new(Favorites), Box::new(Forgotten)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdg::TempDir;
+
+    #[test]
+    fn rate_and_favorite() {
+        xdg::isolate();
+        let dir = TempDir::new("ratings");
+        let songs: Vec<PathBuf> = ["a.flac", "b.flac", "c.flac"]
+            .iter()
+            .map(|s| dir.join(s))
+            .collect();
+        for song in &songs {
+            fs::write(song, b"").unwrap();
+        }
+        let (a, b, c) = (&songs[0], &songs[1], &songs[2]);
+
+        rate(&songs[..2], 9);
+        assert_eq!(get(a).stars, MAX_STARS);
+        rate(std::slice::from_ref(b), 3);
+        toggle_favorite(&songs[1..]);
+        assert_eq!(
+            get(b),
+            Rating {
+                stars: 3,
+                favorite: true
+            }
+        );
+        assert!(get(c).favorite);
+        // only unfavorites when they all already are
+        toggle_favorite(&songs[..2]);
+        assert!(get(a).favorite && get(b).favorite);
+        toggle_favorite(&songs[..2]);
+        assert!(!get(a).favorite && !get(b).favorite);
+
+        let saved = load().unwrap();
+        assert_eq!(saved.get(a), Some(&get(a)));
+        assert_eq!(saved.get(b), Some(&get(b)));
+        assert_eq!(saved.get(c), Some(&get(c)));
+
+        // songs with nothing said about them anymore aren't kept
+        rate(std::slice::from_ref(b), 0);
+        assert!(!load().unwrap().contains_key(b));
+
+        let favorites: Vec<PathBuf> = views()[0]
+            .songs()
+            .into_iter()
+            .filter(|p| p.starts_with(&dir))
+            .collect();
+        assert_eq!(favorites, vec![c.clone()]);
+        let forgotten: Vec<PathBuf> = views()[1]
+            .songs()
+            .into_iter()
+            .filter(|p| p.starts_with(&dir))
+            .collect();
+        assert_eq!(forgotten, vec![a.clone()]);
+    }
+}
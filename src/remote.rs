@@ -0,0 +1,147 @@
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::control::{Command, Response, Status};
+use crate::equalizer::Equalizer;
+use crate::{AbLoop, Playback, Repeat};
+
+/// A daemon on the other end of the control socket, standing in for the player. What it's up to
+/// comes from the last status it sent, so call refresh every so often.
+pub struct Remote {
+    path: PathBuf,
+    connection: Option<(UnixStream, BufReader<UnixStream>)>,
+    status: Status,
+    /// When the status came in, to work out the position in between refreshes.
+    fetched: Instant,
+}
+
+impl Remote {
+    pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<Remote> {
+        let mut remote = Remote {
+            path: path.as_ref().to_path_buf(),
+            connection: None,
+            status: Status {
+                state: "stopped".to_string(),
+                song: None,
+                index: 0,
+                queue: Vec::new(),
+                position: 0f64,
+                duration: None,
+                volume: 0f32,
+                cover: None,
+                shuffle: false,
+                repeat: Repeat::Off,
+                equalizer: Equalizer::default(),
+                speed: 1f32,
+                ab_loop: None,
+            },
+            fetched: Instant::now(),
+        };
+        remote.refresh()?;
+        Ok(remote)
+    }
+
+    /// Send a command and wait for the answer, connecting again first if the last try failed.
+    pub fn send(&mut self, command: &Command) -> io::Result<Response> {
+        let result = self.try_send(command);
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+
+    fn try_send(&mut self, command: &Command) -> io::Result<Response> {
+        if self.connection.is_none() {
+            let stream = UnixStream::connect(&self.path)?;
+            let reader = BufReader::new(stream.try_clone()?);
+            self.connection = Some((stream, reader));
+        }
+        let (stream, reader) = self.connection.as_mut().unwrap();
+
+        writeln!(stream, "{}", serde_json::to_string(command)?)?;
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the daemon hung up",
+            ));
+        }
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Ask the daemon what it's up to.
+    pub fn refresh(&mut self) -> io::Result<()> {
+        match self.send(&Command::Status)? {
+            Response::Status(status) => {
+                self.status = status;
+                self.fetched = Instant::now();
+                Ok(())
+            }
+            Response::Error(e) => Err(io::Error::other(e)),
+            Response::Ok => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected a status",
+            )),
+        }
+    }
+}
+
+impl Playback for Remote {
+    fn playing(&self) -> &[PathBuf] {
+        &self.status.queue
+    }
+
+    fn index(&self) -> usize {
+        self.status.index
+    }
+
+    fn position(&self) -> Duration {
+        let mut position = self.status.position;
+        if self.status.state == "playing" {
+            position += self.fetched.elapsed().as_secs_f64() * f64::from(self.status.speed);
+        }
+        if let Some(duration) = self.status.duration {
+            position = position.min(duration);
+        }
+        Duration::from_secs_f64(position)
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        self.status.duration.map(Duration::from_secs_f64)
+    }
+
+    fn is_paused(&self) -> bool {
+        self.status.state == "paused"
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.status.state == "stopped"
+    }
+
+    fn volume(&self) -> f32 {
+        self.status.volume / 100f32
+    }
+
+    fn is_shuffled(&self) -> bool {
+        self.status.shuffle
+    }
+
+    fn repeat(&self) -> Repeat {
+        self.status.repeat
+    }
+
+    fn equalizer(&self) -> Equalizer {
+        self.status.equalizer
+    }
+
+    fn speed(&self) -> f32 {
+        self.status.speed
+    }
+
+    fn ab_loop(&self) -> Option<AbLoop> {
+        self.status.ab_loop
+    }
+}
@@ -0,0 +1,77 @@
+use std::io;
+use std::io::{Stdout, Write};
+use std::panic;
+use std::sync::Mutex;
+
+use termion::cursor;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+
+/// The terminal in raw mode, kept here instead of with the tui backend so it can be put back from
+/// anywhere, like a panic hook.
+static RAW: Mutex<Option<RawTerminal<Stdout>>> = Mutex::new(None);
+
+/// Puts the terminal back the way it was when dropped.
+pub struct Screen {
+    _private: (),
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Switch to raw mode and the alternate screen, with the cursor hidden.
+pub fn enter() -> io::Result<Screen> {
+    resume()?;
+    Ok(Screen { _private: () })
+}
+
+/// Go back to raw mode and the alternate screen after restore, if they're not on already.
+pub fn resume() -> io::Result<()> {
+    let mut raw = RAW.lock().unwrap_or_else(|e| e.into_inner());
+    if raw.is_some() {
+        return Ok(());
+    }
+    *raw = Some(io::stdout().into_raw_mode()?);
+
+    let mut stdout = io::stdout();
+    write!(stdout, "{}{}", ToAlternateScreen, cursor::Hide)?;
+    stdout.flush()
+}
+
+/// Restore the terminal and stop the process, the way SIGTSTP would have without a handler.
+/// Returns once it's been continued, with the terminal still restored.
+pub fn suspend() -> io::Result<()> {
+    restore();
+    // SIGSTOP can't be caught, so this doesn't come back around to the SIGTSTP handler
+    if unsafe { libc::raise(libc::SIGSTOP) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Leave raw mode and the alternate screen, if they're on. Safe to call more than once.
+pub fn restore() {
+    let raw = RAW.lock().unwrap_or_else(|e| e.into_inner()).take();
+    if let Some(raw) = raw {
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}{}", cursor::Show, ToMainScreen);
+        let _ = stdout.flush();
+        // dropping it puts back the settings from before raw mode
+        drop(raw);
+    }
+}
+
+/// Restore the terminal before anything panics, so the message can be read and the shell still
+/// works afterwards. The panic goes to the log too.
+pub fn restore_on_panic() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        log::error!("{}", info);
+        log::logger().flush();
+        default(info);
+    }));
+}
@@ -0,0 +1,563 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::plays::Play;
+use crate::{status_bar, xdg};
+
+/// How long to wait before trying listens that didn't go through again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// How many listens go in one request, which is as many as Last.fm takes.
+const BATCH: usize = 50;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to send what's being listened to. Each service is on once it's configured.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ScrobbleConfig {
+    pub listenbrainz: Option<ListenBrainzConfig>,
+    pub lastfm: Option<LastFmConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct ListenBrainzConfig {
+    /// The API root, for other servers that speak the ListenBrainz API.
+    #[serde(default = "ListenBrainzConfig::default_url")]
+    pub url: String,
+    /// The user token from the ListenBrainz settings page.
+    pub token: String,
+}
+
+impl ListenBrainzConfig {
+    fn default_url() -> String {
+        "https://api.listenbrainz.org".to_string()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LastFmConfig {
+    /// The API endpoint, for other servers that speak the Last.fm API.
+    #[serde(default = "LastFmConfig::default_url")]
+    pub url: String,
+    pub api_key: String,
+    pub api_secret: String,
+    /// From Last.fm's auth flow. Without one, the username and password are traded for one.
+    pub session_key: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl LastFmConfig {
+    fn default_url() -> String {
+        "https://ws.audioscrobbler.com/2.0/".to_string()
+    }
+}
+
+/// A song being listened to, as the services want to hear about it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Listen {
+    artist: String,
+    title: String,
+    album: String,
+    /// In seconds.
+    duration: Option<u64>,
+    /// When it started, in seconds since the epoch.
+    listened_at: u64,
+}
+
+impl Listen {
+    fn new(play: &Play) -> Listen {
+        Listen {
+            artist: play.song.artist.clone(),
+            title: play.song.title.clone(),
+            album: play.song.album.clone(),
+            duration: play.length.map(|d| d.as_secs()),
+            listened_at: play.started,
+        }
+    }
+}
+
+enum Job {
+    NowPlaying(Listen),
+    Listen(Listen),
+}
+
+/// Tells the configured services what's playing, and about every song that was listened to long
+/// enough to count. Listens are kept on disk until they go through, so nothing's lost while
+/// offline. The requests happen on another thread so the event loop never waits on the network.
+pub struct Scrobbler {
+    jobs: Sender<Job>,
+    /// The last play sent as now playing, and the last one sent as a listen.
+    announced: Option<u64>,
+    scrobbled: Option<u64>,
+}
+
+impl Scrobbler {
+    /// Returns None if there's nowhere to scrobble to.
+    pub fn new(config: &ScrobbleConfig) -> Option<Scrobbler> {
+        let mut services: Vec<Box<dyn Service>> = Vec::new();
+        if let Some(config) = &config.listenbrainz {
+            services.push(Box::new(ListenBrainz {
+                agent: agent(),
+                url: config.url.trim_end_matches('/').to_string(),
+                token: config.token.clone(),
+            }));
+        }
+        if let Some(config) = &config.lastfm {
+            services.push(Box::new(LastFm {
+                agent: agent(),
+                url: config.url.clone(),
+                api_key: config.api_key.clone(),
+                api_secret: config.api_secret.clone(),
+                session_key: config.session_key.clone(),
+                username: config.username.clone(),
+                password: config.password.clone(),
+            }));
+        }
+        if services.is_empty() {
+            return None;
+        }
+
+        let (jobs, receiver) = channel();
+        thread::spawn(move || work(services, receiver));
+        Some(Scrobbler {
+            jobs,
+            announced: None,
+            scrobbled: None,
+        })
+    }
+
+    /// Send now playing once the current play's unpaused, and the listen once it counts.
+    pub fn update(&mut self, play: Option<&Play>, paused: bool) {
+        let play = match play {
+            Some(p) => p,
+            None => return,
+        };
+        if !paused && self.announced != Some(play.id) {
+            self.announced = Some(play.id);
+            let _ = self.jobs.send(Job::NowPlaying(Listen::new(play)));
+        }
+        if play.counts() && self.scrobbled != Some(play.id) {
+            self.scrobbled = Some(play.id);
+            log::debug!("listened to {} - {}", play.song.artist, play.song.title);
+            let _ = self.jobs.send(Job::Listen(Listen::new(play)));
+        }
+    }
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+/// Send now playing straight away and listens once they're saved, and try again every so often
+/// when the service can't be reached.
+fn work(services: Vec<Box<dyn Service>>, jobs: Receiver<Job>) {
+    let mut queues: Vec<Queue> = services.into_iter().map(Queue::load).collect();
+    for queue in &mut queues {
+        queue.flush();
+    }
+
+    loop {
+        match jobs.recv_timeout(RETRY_INTERVAL) {
+            Ok(Job::NowPlaying(listen)) => {
+                for queue in &mut queues {
+                    if let Err(e) = queue.service.now_playing(&listen) {
+                        log::warn!("error sending now playing to {}: {}", queue.name(), e);
+                    }
+                }
+            }
+            Ok(Job::Listen(listen)) => {
+                for queue in &mut queues {
+                    queue.listens.push(listen.clone());
+                    queue.save();
+                    queue.flush();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                for queue in &mut queues {
+                    queue.flush();
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// The listens a service hasn't taken yet, kept in the state directory.
+struct Queue {
+    service: Box<dyn Service>,
+    listens: Vec<Listen>,
+    path: Option<PathBuf>,
+}
+
+impl Queue {
+    fn load(service: Box<dyn Service>) -> Queue {
+        let path = xdg::state_dir().map(|d| d.join(format!("scrobbles-{}.json", service.name())));
+        let listens = match path.as_ref().map(fs::read_to_string) {
+            Some(Ok(contents)) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                status_bar::error(format!("error reading unsent listens: {}", e));
+                Vec::new()
+            }),
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                status_bar::error(format!("error reading unsent listens: {}", e));
+                Vec::new()
+            }
+            _ => Vec::new(),
+        };
+
+        Queue {
+            service,
+            listens,
+            path,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        self.service.name()
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return,
+        };
+        let result = (|| -> io::Result<()> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            fs::write(&tmp, serde_json::to_string(&self.listens)?)?;
+            fs::rename(tmp, path)
+        })();
+        if let Err(e) = result {
+            status_bar::error(format!("error saving unsent listens: {}", e));
+        }
+    }
+
+    /// Send everything waiting, stopping at the first batch that can't get through.
+    fn flush(&mut self) {
+        while !self.listens.is_empty() {
+            let n = self.listens.len().min(BATCH);
+            match self.service.submit(&self.listens[..n]) {
+                Ok(()) => log::info!("sent {} listens to {}", n, self.name()),
+                Err(Error::Rejected(e)) => {
+                    status_bar::error(format!("{} turned down {} listens: {}", self.name(), n, e))
+                }
+                Err(Error::Retry(e)) => {
+                    log::warn!(
+                        "error sending listens to {}, trying again later: {}",
+                        self.name(),
+                        e
+                    );
+                    return;
+                }
+            }
+            self.listens.drain(..n);
+            self.save();
+        }
+    }
+}
+
+enum Error {
+    /// Might work later, like when the network's back or the credentials are fixed.
+    Retry(String),
+    /// Never going to work, so the listens might as well be dropped.
+    Rejected(String),
+}
+
+trait Service: Send {
+    fn name(&self) -> &'static str;
+    fn now_playing(&mut self, listen: &Listen) -> Result<(), String>;
+    fn submit(&mut self, listens: &[Listen]) -> Result<(), Error>;
+}
+
+struct ListenBrainz {
+    agent: ureq::Agent,
+    url: String,
+    token: String,
+}
+
+impl ListenBrainz {
+    fn send(&self, body: Value) -> Result<(), Error> {
+        let result = self
+            .agent
+            .post(&format!("{}/1/submit-listens", self.url))
+            .set("Authorization", &format!("Token {}", self.token))
+            .send_json(body);
+        match result {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, response)) => {
+                let message = response
+                    .into_json::<Value>()
+                    .ok()
+                    .and_then(|b| b["error"].as_str().map(str::to_string))
+                    .unwrap_or_else(|| format!("status {}", code));
+                match code {
+                    400 | 413 => Err(Error::Rejected(message)),
+                    _ => Err(Error::Retry(message)),
+                }
+            }
+            Err(e) => Err(Error::Retry(e.to_string())),
+        }
+    }
+}
+
+fn track_metadata(listen: &Listen) -> Value {
+    let mut info = json!({
+        "media_player": "bebop",
+        "submission_client": "bebop",
+        "submission_client_version": env!("CARGO_PKG_VERSION"),
+    });
+    if let Some(duration) = listen.duration {
+        info["duration_ms"] = json!(duration * 1000);
+    }
+    let mut metadata = json!({
+        "artist_name": listen.artist,
+        "track_name": listen.title,
+        "additional_info": info,
+    });
+    if !listen.album.is_empty() {
+        metadata["release_name"] = json!(listen.album);
+    }
+    metadata
+}
+
+impl Service for ListenBrainz {
+    fn name(&self) -> &'static str {
+        "listenbrainz"
+    }
+
+    fn now_playing(&mut self, listen: &Listen) -> Result<(), String> {
+        let body = json!({
+            "listen_type": "playing_now",
+            "payload": [{ "track_metadata": track_metadata(listen) }],
+        });
+        self.send(body).map_err(|e| match e {
+            Error::Retry(e) | Error::Rejected(e) => e,
+        })
+    }
+
+    fn submit(&mut self, listens: &[Listen]) -> Result<(), Error> {
+        let payload: Vec<Value> = listens
+            .iter()
+            .map(|l| json!({ "listened_at": l.listened_at, "track_metadata": track_metadata(l) }))
+            .collect();
+        let listen_type = if listens.len() == 1 {
+            "single"
+        } else {
+            "import"
+        };
+        self.send(json!({ "listen_type": listen_type, "payload": payload }))
+    }
+}
+
+struct LastFm {
+    agent: ureq::Agent,
+    url: String,
+    api_key: String,
+    api_secret: String,
+    session_key: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl LastFm {
+    /// Call a signed API method, with the session key unless it's getting one.
+    fn call(&mut self, method: &str, mut params: Vec<(String, String)>) -> Result<Value, Error> {
+        if method != "auth.getMobileSession" {
+            let session_key = self.session_key()?;
+            params.push(("sk".to_string(), session_key));
+        }
+        params.push(("method".to_string(), method.to_string()));
+        params.push(("api_key".to_string(), self.api_key.clone()));
+        params.sort();
+
+        let mut signature: String = params.iter().map(|(k, v)| format!("{}{}", k, v)).collect();
+        signature.push_str(&self.api_secret);
+        params.push((
+            "api_sig".to_string(),
+            format!("{:x}", md5::compute(signature)),
+        ));
+        params.push(("format".to_string(), "json".to_string()));
+
+        let form: Vec<(&str, &str)> = params
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let body = match self.agent.post(&self.url).send_form(&form) {
+            Ok(response) => response.into_json::<Value>().ok(),
+            Err(ureq::Error::Status(code, response)) => match response.into_json::<Value>() {
+                Ok(body) => Some(body),
+                Err(_) => return Err(Error::Retry(format!("status {}", code))),
+            },
+            Err(e) => return Err(Error::Retry(e.to_string())),
+        };
+        let body = body.ok_or_else(|| Error::Retry("bad response".to_string()))?;
+
+        if let Some(code) = body["error"].as_u64() {
+            let message = body["message"]
+                .as_str()
+                .unwrap_or("unknown error")
+                .to_string();
+            if code == 9 {
+                // the session key's no good, so get a new one next time if possible
+                self.session_key = None;
+            }
+            // authentication, API key, suspension, rate limit and outage errors can clear up
+            return match code {
+                4 | 9 | 10 | 11 | 14 | 16 | 26 | 29 => Err(Error::Retry(message)),
+                _ => Err(Error::Rejected(message)),
+            };
+        }
+
+        Ok(body)
+    }
+
+    fn session_key(&mut self) -> Result<String, Error> {
+        if let Some(key) = &self.session_key {
+            return Ok(key.clone());
+        }
+        let (username, password) = match (&self.username, &self.password) {
+            (Some(u), Some(p)) => (u.clone(), p.clone()),
+            _ => {
+                return Err(Error::Retry(
+                    "no session_key, or username and password to get one".to_string(),
+                ))
+            }
+        };
+        let params = vec![
+            ("username".to_string(), username),
+            ("password".to_string(), password),
+        ];
+        let body = self.call("auth.getMobileSession", params)?;
+        let key = body["session"]["key"]
+            .as_str()
+            .ok_or_else(|| Error::Retry("no session key in the response".to_string()))?
+            .to_string();
+        self.session_key = Some(key.clone());
+        Ok(key)
+    }
+}
+
+/// The parameters for one song, with `[i]` after each name when scrobbling more than one.
+fn track_params(listen: &Listen, suffix: &str, params: &mut Vec<(String, String)>) {
+    params.push((format!("artist{}", suffix), listen.artist.clone()));
+    params.push((format!("track{}", suffix), listen.title.clone()));
+    if !listen.album.is_empty() {
+        params.push((format!("album{}", suffix), listen.album.clone()));
+    }
+    if let Some(duration) = listen.duration {
+        params.push((format!("duration{}", suffix), duration.to_string()));
+    }
+}
+
+impl Service for LastFm {
+    fn name(&self) -> &'static str {
+        "lastfm"
+    }
+
+    fn now_playing(&mut self, listen: &Listen) -> Result<(), String> {
+        let mut params = Vec::new();
+        track_params(listen, "", &mut params);
+        self.call("track.updateNowPlaying", params)
+            .map(|_| ())
+            .map_err(|e| match e {
+                Error::Retry(e) | Error::Rejected(e) => e,
+            })
+    }
+
+    fn submit(&mut self, listens: &[Listen]) -> Result<(), Error> {
+        let mut params = Vec::new();
+        for (i, listen) in listens.iter().enumerate() {
+            let suffix = format!("[{}]", i);
+            track_params(listen, &suffix, &mut params);
+            params.push((
+                format!("timestamp{}", suffix),
+                listen.listened_at.to_string(),
+            ));
+        }
+        self.call("track.scrobble", params).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Takes listens only when told the network's up, and remembers what it got.
+    struct Fake {
+        online: Arc<Mutex<bool>>,
+        got: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl Service for Fake {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn now_playing(&mut self, _: &Listen) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn submit(&mut self, listens: &[Listen]) -> Result<(), Error> {
+            if !*self.online.lock().unwrap() {
+                return Err(Error::Retry("offline".to_string()));
+            }
+            let mut got = self.got.lock().unwrap();
+            got.extend(listens.iter().map(|l| l.listened_at));
+            Ok(())
+        }
+    }
+
+    fn listen(listened_at: u64) -> Listen {
+        Listen {
+            artist: "Artist".to_string(),
+            title: "Title".to_string(),
+            album: String::new(),
+            duration: Some(180),
+            listened_at,
+        }
+    }
+
+    #[test]
+    fn queue_survives_being_offline() {
+        xdg::isolate();
+        let online = Arc::new(Mutex::new(false));
+        let got = Arc::new(Mutex::new(Vec::new()));
+        let fake = || {
+            Box::new(Fake {
+                online: online.clone(),
+                got: got.clone(),
+            })
+        };
+
+        let mut queue = Queue::load(fake());
+        assert!(queue.listens.is_empty());
+        queue.listens.extend((1..=BATCH as u64 + 1).map(listen));
+        queue.save();
+        queue.flush();
+        assert_eq!(queue.listens.len(), BATCH + 1);
+
+        // as if bebop had been restarted
+        let mut queue = Queue::load(fake());
+        assert_eq!(queue.listens.len(), BATCH + 1);
+        assert_eq!(queue.listens[0].artist, "Artist");
+        assert_eq!(queue.listens[0].duration, Some(180));
+
+        *online.lock().unwrap() = true;
+        queue.flush();
+        assert!(queue.listens.is_empty());
+        assert_eq!(
+            *got.lock().unwrap(),
+            (1..=BATCH as u64 + 1).collect::<Vec<_>>()
+        );
+        assert!(Queue::load(fake()).listens.is_empty());
+    }
+}
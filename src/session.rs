@@ -0,0 +1,149 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::equalizer::Equalizer;
+use crate::{xdg, Playback, Player};
+
+/// Everything needed to pick up where the last run left off.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    playing: Vec<PathBuf>,
+    index: usize,
+    /// Milliseconds into the song at `index`.
+    position: u64,
+    volume: f32,
+    location: Vec<PathBuf>,
+    #[serde(default)]
+    equalizer: Equalizer,
+    #[serde(default = "normal_speed")]
+    speed: f32,
+}
+
+fn normal_speed() -> f32 {
+    1f32
+}
+
+impl Session {
+    /// What `player` is up to, with `location` being where the explorer was.
+    pub fn new(location: Vec<PathBuf>, player: &Player) -> Session {
+        let playing = player.playing().to_vec();
+        let index = if playing.is_empty() {
+            0
+        } else {
+            player.index().min(playing.len() - 1)
+        };
+
+        Session {
+            playing,
+            index,
+            position: player.position().as_millis() as u64,
+            volume: player.volume(),
+            location,
+            equalizer: player.equalizer(),
+            speed: player.speed(),
+        }
+    }
+
+    /// Load the saved session, if there is one.
+    pub fn load() -> io::Result<Option<Session>> {
+        let path = match path() {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path =
+            path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Where the explorer was, for Explorer::restore.
+    pub fn location(&self) -> &[PathBuf] {
+        &self.location
+    }
+
+    /// Put the player back the way it was, with playback paused.
+    pub fn restore(&self, player: &mut Player) -> io::Result<Option<Receiver<usize>>> {
+        player.set_volume(self.volume);
+        player.set_equalizer(self.equalizer);
+        player.set_speed(self.speed);
+
+        if self.index >= self.playing.len() || !self.playing.iter().all(|p| p.is_file()) {
+            return Ok(None);
+        }
+        let offset = Duration::from_millis(self.position);
+        player
+            .play_songs_from(self.index, self.playing.clone(), offset, true)
+            .map(Some)
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    xdg::state_dir().map(|d| d.join("session.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::equalizer::Preset;
+
+    #[test]
+    fn save_and_load() {
+        xdg::isolate();
+        let session = Session {
+            playing: vec![
+                PathBuf::from("/music/a.flac"),
+                PathBuf::from("/music/b.mp3"),
+            ],
+            index: 1,
+            position: 61_500,
+            volume: 0.4,
+            location: vec![PathBuf::from("/music")],
+            equalizer: Equalizer::preset(Preset::Vocal),
+            speed: 1.5,
+        };
+        session.save().unwrap();
+        let loaded = Session::load().unwrap().unwrap();
+        assert_eq!(loaded.playing, session.playing);
+        assert_eq!(loaded.index, 1);
+        assert_eq!(loaded.position, 61_500);
+        assert_eq!(loaded.volume, 0.4);
+        assert_eq!(loaded.location(), session.location());
+        assert_eq!(loaded.equalizer, session.equalizer);
+        assert_eq!(loaded.speed, 1.5);
+
+        // from before the equalizer and speed were saved
+        let old =
+            r#"{"playing":["/music/a.flac"],"index":0,"position":0,"volume":1.0,"location":[]}"#;
+        let old: Session = serde_json::from_str(old).unwrap();
+        assert_eq!(old.equalizer, Equalizer::default());
+        assert_eq!(old.speed, 1f32);
+
+        fs::write(path().unwrap(), "{").unwrap();
+        let error = Session::load().err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(path().unwrap()).unwrap();
+        assert!(Session::load().unwrap().is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lofty::file::FileType;
+use lofty::prelude::*;
+
+/// What we know about a song, going by where it sits in the music directory:
+/// artist/album/song.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Song {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+}
+
+impl Song {
+    pub fn new<P: AsRef<Path>>(path: P) -> Song {
+        let path = path.as_ref();
+        let album_dir = path.parent();
+        let artist_dir = album_dir.and_then(Path::parent);
+
+        Song {
+            path: path.to_path_buf(),
+            title: lossy_name(path.file_stem()),
+            artist: lossy_name(artist_dir.and_then(Path::file_name)),
+            album: lossy_name(album_dir.and_then(Path::file_name)),
+        }
+    }
+
+    /// Like Song::new, but with the title, artist and album from the song's tags where it has
+    /// them.
+    pub fn read<P: AsRef<Path>>(path: P) -> Song {
+        match Details::read(&path) {
+            Ok(details) => details.song,
+            Err(_) => Song::new(path),
+        }
+    }
+
+    /// The song's file name, extension and all.
+    pub fn file_name(&self) -> String {
+        lossy_name(self.path.file_name())
+    }
+
+    /// The directory the song is in.
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new(""))
+    }
+}
+
+/// Everything we can find out about a song from its tags and audio properties.
+#[derive(Clone, Debug, Default)]
+pub struct Details {
+    pub song: Song,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub tracks: Option<u32>,
+    pub codec: String,
+    /// In kbps.
+    pub bitrate: Option<u32>,
+    /// In Hz.
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub duration: Option<Duration>,
+}
+
+impl Details {
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Details> {
+        let path = path.as_ref();
+        let tagged = lofty::read_from_path(path).map_err(io::Error::other)?;
+        let properties = tagged.properties();
+        let mut details = Details {
+            song: Song::new(path),
+            codec: codec(tagged.file_type()).to_string(),
+            bitrate: properties.audio_bitrate(),
+            sample_rate: properties.sample_rate(),
+            channels: properties.channels(),
+            duration: Some(properties.duration()).filter(|d| !d.is_zero()),
+            ..Details::default()
+        };
+
+        if let Some(tag) = tagged.primary_tag().or_else(|| tagged.first_tag()) {
+            let set = |field: &mut String, value: Option<std::borrow::Cow<'_, str>>| {
+                if let Some(v) = value.filter(|v| !v.trim().is_empty()) {
+                    *field = v.into_owned();
+                }
+            };
+            set(&mut details.song.title, tag.title());
+            set(&mut details.song.artist, tag.artist());
+            set(&mut details.song.album, tag.album());
+            details.genre = tag.genre().map(|g| g.into_owned());
+            details.year = tag.year();
+            details.track = tag.track();
+            details.tracks = tag.track_total();
+        }
+
+        Ok(details)
+    }
+}
+
+/// How long a song is according to its headers, for when the decoder can't tell.
+pub fn duration<P: AsRef<Path>>(path: P) -> Option<Duration> {
+    let tagged = lofty::read_from_path(path).ok()?;
+    Some(tagged.properties().duration()).filter(|d| !d.is_zero())
+}
+
+fn codec(file_type: FileType) -> &'static str {
+    match file_type {
+        FileType::Aac => "AAC",
+        FileType::Aiff => "AIFF",
+        FileType::Ape => "APE",
+        FileType::Flac => "FLAC",
+        FileType::Mpeg => "MP3",
+        FileType::Mp4 => "MP4",
+        FileType::Mpc => "Musepack",
+        FileType::Opus => "Opus",
+        FileType::Vorbis => "Vorbis",
+        FileType::Speex => "Speex",
+        FileType::Wav => "WAV",
+        FileType::WavPack => "WavPack",
+        FileType::Custom(name) => name,
+        _ => "unknown",
+    }
+}
+
+fn lossy_name(name: Option<&std::ffi::OsStr>) -> String {
+    name.map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Format a duration in seconds like 3:07, or 1:02:07 past an hour.
+pub fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0f64) as u64;
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
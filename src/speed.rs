@@ -0,0 +1,508 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::source::Source;
+use serde::Deserialize;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 2f32;
+/// How long each piece of a time-stretched song is, in seconds.
+const GRAIN: f32 = 0.04;
+/// How far from where a piece should come from it can be taken, in seconds, to line it up with
+/// the piece before it.
+const TOLERANCE: f32 = 0.01;
+
+/// What happens to the pitch when playing faster or slower.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Pitch {
+    /// Time-stretch, so voices sound the same at any speed.
+    #[default]
+    Keep,
+    /// Resample, like a record played too fast or too slow.
+    Follow,
+}
+
+/// The playback speed, shared with everything playing at it so it can change mid-song.
+#[derive(Debug)]
+pub struct Shared(AtomicU32);
+
+impl Default for Shared {
+    fn default() -> Shared {
+        Shared(AtomicU32::new(1f32.to_bits()))
+    }
+}
+
+impl Shared {
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Set the speed, keeping it between MIN_SPEED and MAX_SPEED.
+    pub fn set(&self, speed: f32) {
+        let speed = if speed.is_nan() { 1f32 } else { speed };
+        let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+        self.0.store(speed.to_bits(), Ordering::Relaxed);
+    }
+}
+
+enum Mode {
+    Normal,
+    Resample(Resample),
+    Stretch(Stretch),
+}
+
+/// Plays a source faster or slower, either resampling it or time-stretching it depending on
+/// `pitch`. Positions still count in the source's own time, since Signal sits inside this.
+pub struct Speed<I> {
+    input: I,
+    shared: Arc<Shared>,
+    pitch: Pitch,
+    channels: usize,
+    mode: Mode,
+    /// Samples from the input that haven't been used yet, put back when the mode changed.
+    held: VecDeque<f32>,
+    /// Samples ready to go out.
+    pending: VecDeque<f32>,
+}
+
+impl<I> Speed<I>
+where
+    I: Source<Item = f32>,
+{
+    pub fn new(input: I, shared: Arc<Shared>, pitch: Pitch) -> Speed<I> {
+        Speed {
+            channels: usize::from(input.channels().max(1)),
+            input,
+            shared,
+            pitch,
+            mode: Mode::Normal,
+            held: VecDeque::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The next frame of input, with one sample for each channel, or None once it runs out.
+    fn frame(&mut self) -> Option<Vec<f32>> {
+        let mut frame = Vec::with_capacity(self.channels);
+        for _ in 0..self.channels {
+            match self.held.pop_front().or_else(|| self.input.next()) {
+                Some(s) => frame.push(s),
+                None if frame.is_empty() => return None,
+                None => frame.push(0f32),
+            }
+        }
+        Some(frame)
+    }
+
+    /// Switch to whatever the speed and pitch call for, giving back any input the last mode
+    /// hadn't used.
+    fn switch(&mut self, speed: f32) {
+        let wanted = if speed == 1f32 {
+            0
+        } else if self.pitch == Pitch::Follow {
+            1
+        } else {
+            2
+        };
+        let current = match self.mode {
+            Mode::Normal => 0,
+            Mode::Resample(_) => 1,
+            Mode::Stretch(_) => 2,
+        };
+        if wanted == current {
+            return;
+        }
+
+        let unused = match std::mem::replace(&mut self.mode, Mode::Normal) {
+            Mode::Normal => Vec::new(),
+            Mode::Resample(r) => r.unused(),
+            Mode::Stretch(s) => s.unused(),
+        };
+        for sample in unused.into_iter().rev() {
+            self.held.push_front(sample);
+        }
+        let sample_rate = self.input.sample_rate();
+        self.mode = match wanted {
+            0 => Mode::Normal,
+            1 => Mode::Resample(Resample::default()),
+            _ => Mode::Stretch(Stretch::new(self.channels, sample_rate)),
+        };
+    }
+
+    /// Fill `pending` with the next bit of output, returning false once there's no more.
+    fn produce(&mut self) -> bool {
+        let speed = self.shared.get();
+        self.switch(speed);
+
+        let mut mode = std::mem::replace(&mut self.mode, Mode::Normal);
+        let produced = match &mut mode {
+            Mode::Normal => match self.frame() {
+                Some(frame) => {
+                    self.pending.extend(frame);
+                    true
+                }
+                None => false,
+            },
+            Mode::Resample(r) => r.produce(self, speed),
+            Mode::Stretch(s) => s.produce(self, speed),
+        };
+        self.mode = mode;
+        produced
+    }
+}
+
+impl<I> Iterator for Speed<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.pending.is_empty() {
+            if !self.produce() {
+                return None;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl<I> Source for Speed<I>
+where
+    I: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Reads the input faster or slower than it comes out, filling in between samples with straight
+/// lines.
+#[derive(Default)]
+struct Resample {
+    previous: Vec<f32>,
+    next: Vec<f32>,
+    /// How far from `previous` to `next` the next frame out is.
+    fraction: f32,
+}
+
+impl Resample {
+    fn produce<I: Source<Item = f32>>(&mut self, speed: &mut Speed<I>, rate: f32) -> bool {
+        if self.next.is_empty() {
+            match (speed.frame(), speed.frame()) {
+                (Some(a), Some(b)) => {
+                    self.previous = a;
+                    self.next = b;
+                }
+                (Some(a), None) => {
+                    speed.pending.extend(a);
+                    return true;
+                }
+                _ => return false,
+            }
+        }
+        while self.fraction >= 1f32 {
+            self.fraction -= 1f32;
+            match speed.frame() {
+                Some(frame) => self.previous = std::mem::replace(&mut self.next, frame),
+                None => return false,
+            }
+        }
+
+        let t = self.fraction;
+        let frame = self
+            .previous
+            .iter()
+            .zip(&self.next)
+            .map(|(a, b)| a + (b - a) * t);
+        speed.pending.extend(frame);
+        self.fraction += rate;
+        true
+    }
+
+    fn unused(self) -> Vec<f32> {
+        self.next
+    }
+}
+
+/// WSOLA: cuts the input into overlapping pieces and lays them out further apart or closer
+/// together, each one nudged to line up with the one before it so the joins don't warble.
+struct Stretch {
+    channels: usize,
+    window: Vec<f32>,
+    /// How far apart pieces go in the output, half a piece so they overlap.
+    hop: usize,
+    tolerance: usize,
+    /// The input from frame `start` on, interleaved.
+    buffer: Vec<f32>,
+    start: usize,
+    ended: bool,
+    /// Where the next piece would come from at exactly the right speed, in frames.
+    analysis: f64,
+    /// Where the last piece came from.
+    previous: Option<usize>,
+    /// The output being overlapped and added to, a whole piece long.
+    overlap: Vec<f32>,
+}
+
+impl Stretch {
+    fn new(channels: usize, sample_rate: u32) -> Stretch {
+        let length = ((GRAIN * sample_rate as f32) as usize / 2 * 2).max(4);
+        // a Hann window, which adds up to exactly 1 when overlapped by half
+        let window = (0..length)
+            .map(|i| 0.5 - 0.5 * (2f32 * PI * i as f32 / length as f32).cos())
+            .collect();
+        Stretch {
+            channels,
+            window,
+            hop: length / 2,
+            tolerance: (TOLERANCE * sample_rate as f32) as usize,
+            buffer: Vec::new(),
+            start: 0,
+            ended: false,
+            analysis: 0f64,
+            previous: None,
+            overlap: vec![0f32; length * channels],
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.buffer.len() / self.channels
+    }
+
+    /// Read input until frame `end`, or as far as there is.
+    fn fill<I: Source<Item = f32>>(&mut self, speed: &mut Speed<I>, end: usize) {
+        while !self.ended && self.start + self.frames() < end {
+            match speed.frame() {
+                Some(frame) => self.buffer.extend(frame),
+                None => self.ended = true,
+            }
+        }
+    }
+
+    /// The mono sample at input frame `frame`, or silence past the end.
+    fn mono(&self, frame: usize) -> f32 {
+        let i = (frame - self.start) * self.channels;
+        self.buffer
+            .get(i..i + self.channels)
+            .map_or(0f32, |s| s.iter().sum())
+    }
+
+    /// Where between `from` and `to` lines up best with what followed the last piece, going by
+    /// cross-correlation over a rough sample of it.
+    fn best(&self, target: usize, from: usize, to: usize) -> usize {
+        let mut best = (f32::MIN, from);
+        for candidate in (from..=to).step_by(2) {
+            let (mut correlation, mut energy) = (0f32, 0f32);
+            for i in (0..self.hop).step_by(4) {
+                let x = self.mono(candidate + i);
+                correlation += x * self.mono(target + i);
+                energy += x * x;
+            }
+            let score = correlation / energy.sqrt().max(1e-6);
+            if score > best.0 {
+                best = (score, candidate);
+            }
+        }
+        best.1
+    }
+
+    fn produce<I: Source<Item = f32>>(&mut self, speed: &mut Speed<I>, rate: f32) -> bool {
+        let length = self.window.len();
+        let ideal = self.analysis.round() as usize;
+        self.fill(speed, ideal + self.tolerance + length);
+        if self.ended && ideal >= self.start + self.frames() {
+            // the last piece's tail, once
+            let mut tail = std::mem::take(&mut self.overlap);
+            tail.truncate(self.hop * self.channels);
+            speed.pending.extend(tail);
+            return !speed.pending.is_empty();
+        }
+
+        let position = match self.previous {
+            Some(previous) => {
+                let from = ideal.saturating_sub(self.tolerance).max(self.start);
+                self.best(previous + self.hop, from, ideal + self.tolerance)
+            }
+            None => ideal,
+        };
+
+        for i in 0..length {
+            let at = (position + i - self.start) * self.channels;
+            for c in 0..self.channels {
+                let sample = self.buffer.get(at + c).copied().unwrap_or_default();
+                self.overlap[i * self.channels + c] += sample * self.window[i];
+            }
+        }
+        speed
+            .pending
+            .extend(self.overlap.drain(..self.hop * self.channels));
+        self.overlap
+            .extend(std::iter::repeat_n(0f32, self.hop * self.channels));
+
+        self.previous = Some(position);
+        self.analysis += self.hop as f64 * f64::from(rate);
+
+        // nothing before the next search or what follows this piece gets looked at again
+        let keep = (self.analysis as usize)
+            .saturating_sub(self.tolerance)
+            .min(position + self.hop);
+        if keep > self.start {
+            let drop = ((keep - self.start) * self.channels).min(self.buffer.len());
+            self.buffer.drain(..drop);
+            self.start += drop / self.channels;
+        }
+        true
+    }
+
+    /// The input from where the output so far leaves off, which is the middle of the last piece,
+    /// since its second half only ever gets heard overlapped with the next one.
+    fn unused(self) -> Vec<f32> {
+        let next = match self.previous {
+            Some(previous) => previous + self.hop,
+            None => self.analysis.round() as usize,
+        };
+        let from = next.saturating_sub(self.start) * self.channels;
+        self.buffer
+            .get(from..)
+            .map(<[f32]>::to_vec)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// Two seconds of a 440 Hz sine, in every one of `channels`.
+    fn sine(channels: u16) -> SamplesBuffer<f32> {
+        let samples = (0..SAMPLE_RATE * 2)
+            .flat_map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                std::iter::repeat_n(0.5 * (2f32 * PI * 440f32 * t).sin(), usize::from(channels))
+            })
+            .collect::<Vec<f32>>();
+        SamplesBuffer::new(channels, SAMPLE_RATE, samples)
+    }
+
+    fn play(speed: f32, pitch: Pitch, channels: u16) -> Vec<f32> {
+        let shared = Arc::new(Shared::default());
+        shared.set(speed);
+        Speed::new(sine(channels), shared, pitch).collect()
+    }
+
+    /// The frequency of the first channel going by how often it crosses zero, away from the ends.
+    fn frequency(samples: &[f32], channels: usize) -> f32 {
+        let left: Vec<f32> = samples.iter().step_by(channels).copied().collect();
+        let middle = &left[left.len() / 4..left.len() * 3 / 4];
+        let crossings = middle
+            .windows(2)
+            .filter(|w| (w[0] < 0f32) != (w[1] < 0f32))
+            .count();
+        crossings as f32 / 2f32 / (middle.len() as f32 / SAMPLE_RATE as f32)
+    }
+
+    #[test]
+    fn normal_speed_passes_through() {
+        let out = play(1f32, Pitch::Keep, 2);
+        assert_eq!(out, sine(2).collect::<Vec<f32>>());
+    }
+
+    #[test]
+    fn keeping_pitch() {
+        for speed in [0.5, 1.5, 2f32] {
+            let out = play(speed, Pitch::Keep, 2);
+            let expected = 2f32 * SAMPLE_RATE as f32 * 2f32 / speed;
+            let ratio = out.len() as f32 / expected;
+            assert!((ratio - 1f32).abs() < 0.02, "{}× is {} long", speed, ratio);
+            let heard = frequency(&out, 2);
+            assert!(
+                (heard - 440f32).abs() < 10f32,
+                "{}× sounds at {} Hz",
+                speed,
+                heard
+            );
+            // and the channels stay together
+            assert!(out.chunks(2).all(|f| f[0] == f[1]));
+        }
+    }
+
+    #[test]
+    fn following_pitch() {
+        for speed in [0.5, 1.5, 2f32] {
+            let out = play(speed, Pitch::Follow, 1);
+            let expected = SAMPLE_RATE as f32 * 2f32 / speed;
+            let ratio = out.len() as f32 / expected;
+            assert!((ratio - 1f32).abs() < 0.01, "{}× is {} long", speed, ratio);
+            let heard = frequency(&out, 1);
+            let wanted = 440f32 * speed;
+            assert!(
+                (heard - wanted).abs() < 10f32,
+                "{}× sounds at {} Hz",
+                speed,
+                heard
+            );
+        }
+    }
+
+    #[test]
+    fn changing_speed_mid_song() {
+        for pitch in [Pitch::Follow, Pitch::Keep] {
+            let ramp: Vec<f32> = (0..SAMPLE_RATE).map(|i| i as f32).collect();
+            let shared = Arc::new(Shared::default());
+            let mut speed = Speed::new(
+                SamplesBuffer::new(1, SAMPLE_RATE, ramp),
+                shared.clone(),
+                pitch,
+            );
+            assert_eq!(speed.by_ref().take(1000).last(), Some(999f32));
+            shared.set(2f32);
+            speed.by_ref().take(5000).for_each(drop);
+            shared.set(1f32);
+            // back to normal, carrying on from where it got to without losing or repeating any
+            let rest: Vec<f32> = speed.collect();
+            // what's left of the stretched output leads right into it
+            let join = rest
+                .windows(2)
+                .rposition(|w| w[1] != w[0] + 1f32)
+                .map_or(0, |i| i + 1);
+            if let Some(before) = join.checked_sub(1) {
+                let jump = rest[join] - rest[before];
+                assert!((jump - 1f32).abs() < 0.1, "{:?} jumped {}", pitch, jump);
+            }
+            assert_eq!(rest.last(), Some(&(SAMPLE_RATE as f32 - 1f32)));
+        }
+    }
+
+    #[test]
+    fn speed_stays_in_range() {
+        let shared = Shared::default();
+        assert_eq!(shared.get(), 1f32);
+        shared.set(f32::NAN);
+        assert_eq!(shared.get(), 1f32);
+        shared.set(10f32);
+        assert_eq!(shared.get(), MAX_SPEED);
+        shared.set(f32::NEG_INFINITY);
+        assert_eq!(shared.get(), MIN_SPEED);
+    }
+}
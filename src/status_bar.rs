@@ -0,0 +1,88 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How long messages stay up.
+const INFO_TIMEOUT: Duration = Duration::from_secs(3);
+const ERROR_TIMEOUT: Duration = Duration::from_secs(8);
+/// How many messages to hold onto when nothing's picking them up, like with --daemon.
+const MAX_PENDING: usize = 16;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+struct Message {
+    level: Level,
+    text: String,
+    at: Instant,
+}
+
+/// Messages from anywhere, waiting for the status bar to pick them up on the next draw.
+fn pending() -> &'static Mutex<Vec<Message>> {
+    static PENDING: OnceLock<Mutex<Vec<Message>>> = OnceLock::new();
+    PENDING.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn push(level: Level, text: String) {
+    let mut pending = pending().lock().unwrap();
+    if pending.len() >= MAX_PENDING {
+        pending.remove(0);
+    }
+    pending.push(Message {
+        level,
+        text,
+        at: Instant::now(),
+    });
+}
+
+/// Show something in the status bar for a bit. Safe to call from any thread.
+pub fn info<S: Into<String>>(text: S) {
+    let text = text.into();
+    log::info!("{}", text);
+    push(Level::Info, text)
+}
+
+/// Show an error in the status bar for a bit, instead of printing it over the screen. It goes in
+/// the log too.
+pub fn error<S: Into<String>>(text: S) {
+    let text = text.into();
+    log::error!("{}", text);
+    push(Level::Error, text)
+}
+
+/// The line at the bottom, keeping track of which message is up.
+#[derive(Default)]
+pub struct StatusBar {
+    message: Option<Message>,
+}
+
+impl StatusBar {
+    /// The message to show, if there is one. New messages replace older ones, errors are kept
+    /// over info that comes in after them, and each goes away once it's been up long enough.
+    pub fn message(&mut self) -> Option<(Level, &str)> {
+        for message in pending().lock().unwrap().drain(..) {
+            let keep = match &self.message {
+                Some(m) => m.level == Level::Error && message.level == Level::Info && !expired(m),
+                None => false,
+            };
+            if !keep {
+                self.message = Some(message);
+            }
+        }
+        if self.message.as_ref().is_some_and(expired) {
+            self.message = None;
+        }
+
+        self.message.as_ref().map(|m| (m.level, m.text.as_str()))
+    }
+}
+
+fn expired(message: &Message) -> bool {
+    let timeout = match message.level {
+        Level::Info => INFO_TIMEOUT,
+        Level::Error => ERROR_TIMEOUT,
+    };
+    message.at.elapsed() > timeout
+}
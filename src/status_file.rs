@@ -0,0 +1,239 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::config::StatusConfig;
+use crate::control::Status;
+use crate::song::{format_time, Song};
+
+/// Keeps a file up to date with what's playing, for status bars and the like to read.
+pub struct StatusFile {
+    path: PathBuf,
+    format: String,
+    json: bool,
+    /// Whether what's written shows how far into the song it is, so it has to keep up.
+    elapsed: bool,
+    last: Option<Status>,
+}
+
+#[derive(Serialize)]
+struct JsonStatus<'a> {
+    state: &'a str,
+    title: &'a str,
+    artist: &'a str,
+    album: &'a str,
+    path: Option<&'a PathBuf>,
+    elapsed: f64,
+    duration: Option<f64>,
+    volume: f32,
+    cover: Option<&'a PathBuf>,
+}
+
+impl StatusFile {
+    /// Returns None if there's nowhere to write the status to.
+    pub fn new(config: &StatusConfig) -> Option<StatusFile> {
+        let path = config.path.clone().filter(|p| !p.as_os_str().is_empty())?;
+
+        Some(StatusFile {
+            path,
+            format: config.format.clone(),
+            json: config.json,
+            elapsed: config.json || config.format.contains("{elapsed}"),
+            last: None,
+        })
+    }
+
+    /// Rewrite the file if the song, the play/pause state or the volume changed since last time, or
+    /// the elapsed time ticked over to another second when that's shown.
+    pub fn update(&mut self, status: &Status) -> io::Result<()> {
+        if let Some(last) = &self.last {
+            let same = last.song == status.song
+                && last.state == status.state
+                && last.duration == status.duration
+                && (last.volume - status.volume).abs() <= f32::EPSILON
+                && (!self.elapsed || last.position as u64 == status.position as u64);
+            if same {
+                return Ok(());
+            }
+        }
+
+        let contents = self.render(status)?;
+        // write to the side and rename so nobody reads a half written file
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)?;
+
+        self.last = Some(status.clone());
+        Ok(())
+    }
+
+    fn render(&self, status: &Status) -> io::Result<String> {
+        let song = status.song.as_ref().map(Song::read).unwrap_or_default();
+
+        if self.json {
+            let json = JsonStatus {
+                state: &status.state,
+                title: &song.title,
+                artist: &song.artist,
+                album: &song.album,
+                path: status.song.as_ref(),
+                elapsed: status.position,
+                duration: status.duration,
+                volume: status.volume,
+                cover: status.cover.as_ref(),
+            };
+            let mut s = serde_json::to_string(&json)?;
+            s.push('\n');
+            return Ok(s);
+        }
+
+        Ok(fill(&self.format, |field| {
+            let value = match field {
+                "title" => song.title.clone(),
+                "artist" => song.artist.clone(),
+                "album" => song.album.clone(),
+                "file" => song.file_name(),
+                "dir" => song.dir().to_string_lossy().into_owned(),
+                "path" => song.path.to_string_lossy().into_owned(),
+                "elapsed" => format_time(status.position),
+                "duration" => status.duration.map(format_time).unwrap_or_default(),
+                "state" => status.state.clone(),
+                "volume" => format!("{:.0}", status.volume),
+                "cover" => status
+                    .cover
+                    .as_ref()
+                    .map(|c| c.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                _ => return None,
+            };
+            Some(value)
+        }))
+    }
+}
+
+/// Replace each {field} in `template` with whatever `value` gives back for it, leaving the ones it
+/// doesn't know about alone.
+pub fn fill<F>(template: &str, value: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, value(&after[..end])?)))
+        {
+            Some((end, v)) => {
+                filled.push_str(&v);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::equalizer::Equalizer;
+    use crate::xdg::TempDir;
+    use crate::Repeat;
+
+    fn status(position: f64, volume: f32) -> Status {
+        Status {
+            state: "playing".to_string(),
+            song: Some(PathBuf::from("/music/Artist/Album/01 Song.flac")),
+            index: 0,
+            queue: Vec::new(),
+            position,
+            duration: Some(200f64),
+            volume,
+            cover: None,
+            shuffle: false,
+            repeat: Repeat::Off,
+            equalizer: Equalizer::default(),
+            speed: 1f32,
+            ab_loop: None,
+        }
+    }
+
+    fn status_file(dir: &Path, format: &str, json: bool) -> StatusFile {
+        let config = StatusConfig {
+            path: Some(dir.join("status")),
+            format: format.to_string(),
+            json,
+        };
+        StatusFile::new(&config).unwrap()
+    }
+
+    #[test]
+    fn fill_fields() {
+        let value = |field: &str| match field {
+            "title" => Some("Song".to_string()),
+            "elapsed" => Some("1:05".to_string()),
+            _ => None,
+        };
+        assert_eq!(fill("{title} [{elapsed}]", value), "Song [1:05]");
+        assert_eq!(fill("{nope} {title", value), "{nope} {title");
+        assert_eq!(fill("{{title}}", value), "{Song}");
+    }
+
+    #[test]
+    fn elapsed_keeps_up() {
+        let dir = TempDir::new("status-elapsed");
+        let mut file = status_file(&dir, "{file} {elapsed}/{duration}", false);
+        file.update(&status(5.2, 50f32)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file.path).unwrap(),
+            "01 Song.flac 0:05/3:20"
+        );
+        file.update(&status(5.9, 50f32)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file.path).unwrap(),
+            "01 Song.flac 0:05/3:20"
+        );
+        file.update(&status(6.1, 50f32)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&file.path).unwrap(),
+            "01 Song.flac 0:06/3:20"
+        );
+    }
+
+    #[test]
+    fn only_rewritten_on_change() {
+        let dir = TempDir::new("status-volume");
+        let mut file = status_file(&dir, "{volume}", false);
+        file.update(&status(1f64, 50f32)).unwrap();
+        fs::remove_file(&file.path).unwrap();
+        // nothing shown changed, so the file isn't written again
+        file.update(&status(9f64, 50f32)).unwrap();
+        assert!(!file.path.exists());
+        file.update(&status(9f64, 60f32)).unwrap();
+        assert_eq!(fs::read_to_string(&file.path).unwrap(), "60");
+    }
+
+    #[test]
+    fn json() {
+        let dir = TempDir::new("status-json");
+        let mut file = status_file(&dir, "", true);
+        file.update(&status(3f64, 50f32)).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&file.path).unwrap()).unwrap();
+        assert_eq!(written["state"], "playing");
+        assert_eq!(written["elapsed"], 3f64);
+        assert_eq!(written["duration"], 200f64);
+    }
+}
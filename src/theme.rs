@@ -0,0 +1,347 @@
+use std::convert::TryFrom;
+use std::env;
+
+use serde::Deserialize;
+use tui::style::{Color, Modifier, Style};
+
+/// How many colors the terminal can show.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+pub enum ColorDepth {
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "16")]
+    Ansi16,
+}
+
+impl ColorDepth {
+    fn detect() -> ColorDepth {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        let term = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+/// A color from the config: a name like "green" or "lightblue", "#rrggbb", or a 256 color index.
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<ThemeColor, String> {
+        let name = s.to_lowercase().replace(['-', '_', ' '], "");
+        let color = match name.as_str() {
+            "reset" | "default" => Color::Reset,
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "gray" | "grey" => Color::Gray,
+            "darkgray" | "darkgrey" => Color::DarkGray,
+            "lightred" => Color::LightRed,
+            "lightgreen" => Color::LightGreen,
+            "lightyellow" => Color::LightYellow,
+            "lightblue" => Color::LightBlue,
+            "lightmagenta" => Color::LightMagenta,
+            "lightcyan" => Color::LightCyan,
+            "white" => Color::White,
+            _ => match name.strip_prefix('#') {
+                Some(hex) if hex.len() == 6 => {
+                    let n = u32::from_str_radix(hex, 16).map_err(|_| bad_color(&s))?;
+                    Color::Rgb((n >> 16) as u8, (n >> 8) as u8, n as u8)
+                }
+                Some(_) => return Err(bad_color(&s)),
+                None => Color::Indexed(name.parse().map_err(|_| bad_color(&s))?),
+            },
+        };
+
+        Ok(ThemeColor(color))
+    }
+}
+
+fn bad_color(s: &str) -> String {
+    format!("unknown color \"{}\", expected a name, #rrggbb or 0-255", s)
+}
+
+/// Changes to one of a theme's styles. Anything left out stays the way the theme has it.
+#[derive(Deserialize, Default, Copy, Clone)]
+#[serde(default)]
+pub struct StyleConfig {
+    pub fg: Option<ThemeColor>,
+    pub bg: Option<ThemeColor>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub reversed: Option<bool>,
+}
+
+impl StyleConfig {
+    fn apply(&self, mut style: Style) -> Style {
+        if let Some(ThemeColor(fg)) = self.fg {
+            style.fg = fg;
+        }
+        if let Some(ThemeColor(bg)) = self.bg {
+            style.bg = bg;
+        }
+        let modifiers = [
+            (self.bold, Modifier::BOLD),
+            (self.italic, Modifier::ITALIC),
+            (self.underline, Modifier::UNDERLINED),
+            (self.reversed, Modifier::REVERSED),
+        ];
+        for (set, modifier) in modifiers.iter() {
+            match set {
+                Some(true) => style.modifier.insert(*modifier),
+                Some(false) => style.modifier.remove(*modifier),
+                None => (),
+            }
+        }
+
+        style
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// One of the built-in themes: "default", "gruvbox", "nord" or "mono".
+    pub name: String,
+    /// One of "auto", "truecolor", "256" or "16". Colors the terminal can't show get swapped for
+    /// the closest one it can.
+    pub colors: ColorDepth,
+    pub highlight: StyleConfig,
+    pub inactive: StyleConfig,
+    pub border: StyleConfig,
+    pub title: StyleConfig,
+    pub playing: StyleConfig,
+    pub paused: StyleConfig,
+    pub search: StyleConfig,
+    pub error: StyleConfig,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> ThemeConfig {
+        ThemeConfig {
+            name: "default".to_string(),
+            colors: ColorDepth::Auto,
+            highlight: StyleConfig::default(),
+            inactive: StyleConfig::default(),
+            border: StyleConfig::default(),
+            title: StyleConfig::default(),
+            playing: StyleConfig::default(),
+            paused: StyleConfig::default(),
+            search: StyleConfig::default(),
+            error: StyleConfig::default(),
+        }
+    }
+}
+
+/// The styles everything gets drawn with.
+#[derive(Copy, Clone)]
+pub struct Theme {
+    /// The selected entry in the explorer.
+    pub highlight: Style,
+    /// The selected entries in the explorer columns that aren't the current one.
+    pub inactive: Style,
+    pub border: Style,
+    pub title: Style,
+    /// The song that's playing, in the queue and the lyrics.
+    pub playing: Style,
+    /// The song that's playing while it's paused.
+    pub paused: Style,
+    pub search: Style,
+    /// Errors in the status bar.
+    pub error: Style,
+}
+
+impl Theme {
+    pub fn new(config: &ThemeConfig) -> Result<Theme, String> {
+        let theme = Theme::builtin(&config.name)
+            .ok_or_else(|| format!("unknown theme \"{}\"", config.name))?;
+        let theme = Theme {
+            highlight: config.highlight.apply(theme.highlight),
+            inactive: config.inactive.apply(theme.inactive),
+            border: config.border.apply(theme.border),
+            title: config.title.apply(theme.title),
+            playing: config.playing.apply(theme.playing),
+            paused: config.paused.apply(theme.paused),
+            search: config.search.apply(theme.search),
+            error: config.error.apply(theme.error),
+        };
+
+        let depth = match config.colors {
+            ColorDepth::Auto => ColorDepth::detect(),
+            d => d,
+        };
+        Ok(theme.map(|style| Style {
+            fg: downgrade(style.fg, depth),
+            bg: downgrade(style.bg, depth),
+            ..style
+        }))
+    }
+
+    fn builtin(name: &str) -> Option<Theme> {
+        let plain = Style::default();
+        let bold = plain.modifier(Modifier::BOLD);
+        let theme = match name {
+            "default" => Theme {
+                highlight: bold.bg(Color::Green),
+                inactive: bold.fg(Color::Green),
+                border: plain,
+                title: plain,
+                playing: bold.bg(Color::Green),
+                paused: bold.bg(Color::Yellow),
+                search: plain,
+                error: bold.fg(Color::Red),
+            },
+            "gruvbox" => Theme {
+                highlight: bold
+                    .fg(Color::Rgb(0x28, 0x28, 0x28))
+                    .bg(Color::Rgb(0xd7, 0x99, 0x21)),
+                inactive: bold.fg(Color::Rgb(0xd7, 0x99, 0x21)),
+                border: plain.fg(Color::Rgb(0x66, 0x5c, 0x54)),
+                title: bold.fg(Color::Rgb(0xfe, 0x80, 0x19)),
+                playing: bold
+                    .fg(Color::Rgb(0x28, 0x28, 0x28))
+                    .bg(Color::Rgb(0x98, 0x97, 0x1a)),
+                paused: bold
+                    .fg(Color::Rgb(0x28, 0x28, 0x28))
+                    .bg(Color::Rgb(0x92, 0x83, 0x74)),
+                search: plain.fg(Color::Rgb(0x83, 0xa5, 0x98)),
+                error: bold.fg(Color::Rgb(0xfb, 0x49, 0x34)),
+            },
+            "nord" => Theme {
+                highlight: bold
+                    .fg(Color::Rgb(0x2e, 0x34, 0x40))
+                    .bg(Color::Rgb(0x88, 0xc0, 0xd0)),
+                inactive: bold.fg(Color::Rgb(0x88, 0xc0, 0xd0)),
+                border: plain.fg(Color::Rgb(0x4c, 0x56, 0x6a)),
+                title: bold.fg(Color::Rgb(0x81, 0xa1, 0xc1)),
+                playing: bold
+                    .fg(Color::Rgb(0x2e, 0x34, 0x40))
+                    .bg(Color::Rgb(0xa3, 0xbe, 0x8c)),
+                paused: bold
+                    .fg(Color::Rgb(0x2e, 0x34, 0x40))
+                    .bg(Color::Rgb(0xeb, 0xcb, 0x8b)),
+                search: plain.fg(Color::Rgb(0x8f, 0xbc, 0xbb)),
+                error: bold.fg(Color::Rgb(0xbf, 0x61, 0x6a)),
+            },
+            // no colors at all, for terminals that barely have any
+            "mono" => Theme {
+                highlight: plain.modifier(Modifier::REVERSED),
+                inactive: plain.modifier(Modifier::BOLD | Modifier::UNDERLINED),
+                border: plain,
+                title: bold,
+                playing: plain.modifier(Modifier::BOLD | Modifier::REVERSED),
+                paused: plain.modifier(Modifier::REVERSED | Modifier::DIM),
+                search: plain,
+                error: plain.modifier(Modifier::BOLD | Modifier::REVERSED),
+            },
+            _ => return None,
+        };
+
+        Some(theme)
+    }
+
+    fn map(self, f: impl Fn(Style) -> Style) -> Theme {
+        Theme {
+            highlight: f(self.highlight),
+            inactive: f(self.inactive),
+            border: f(self.border),
+            title: f(self.title),
+            playing: f(self.playing),
+            paused: f(self.paused),
+            search: f(self.search),
+            error: f(self.error),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::builtin("default").unwrap()
+    }
+}
+
+/// The 16 colors, roughly how xterm shows them.
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// The levels each channel can be at in the 6x6x6 part of the 256 colors.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Swap `color` for the closest one the terminal can show.
+fn downgrade(color: Color, depth: ColorDepth) -> Color {
+    let rgb = match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(i) if depth == ColorDepth::Ansi16 => indexed_rgb(i),
+        c => return c,
+    };
+
+    match depth {
+        ColorDepth::Ansi256 => {
+            let level = |c: u8| {
+                (0..CUBE.len())
+                    .min_by_key(|&i| (i32::from(CUBE[i]) - i32::from(c)).abs())
+                    .unwrap() as u8
+            };
+            Color::Indexed(16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2))
+        }
+        ColorDepth::Ansi16 => ANSI
+            .iter()
+            .min_by_key(|(_, ansi)| distance(*ansi, rgb))
+            .map(|(c, _)| *c)
+            .unwrap(),
+        _ => color,
+    }
+}
+
+/// What one of the 256 colors looks like.
+fn indexed_rgb(i: u8) -> (u8, u8, u8) {
+    match i {
+        0..=15 => ANSI[usize::from(i)].1,
+        16..=231 => {
+            let i = usize::from(i - 16);
+            (CUBE[i / 36], CUBE[i / 6 % 6], CUBE[i % 6])
+        }
+        _ => {
+            let level = 8 + (i - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (i32::from(x) - i32::from(y)).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rodio::source::Source;
+
+/// How many of the latest samples are kept to draw from, enough for one FFT.
+const WINDOW: usize = 2048;
+/// How many samples the audio thread gathers before handing them over, so it rarely has to lock.
+const CHUNK: usize = 256;
+/// How long without new samples before playback counts as stopped, and the bars fall.
+const STALE_AFTER: Duration = Duration::from_millis(150);
+/// The range of frequencies the spectrum covers, in Hz.
+const LOWEST: f32 = 40f32;
+const HIGHEST: f32 = 16000f32;
+/// How quiet a band can be before its bar is empty, in dB.
+const FLOOR: f32 = -60f32;
+/// How much of the height a bar falls in a second, so it doesn't flicker.
+const FALL: f32 = 1.5;
+/// The most times a second the visualizer gets redrawn.
+pub const FPS: u32 = 30;
+
+/// The latest samples played, mixed down to mono, shared between the audio thread and whatever
+/// draws them.
+#[derive(Default)]
+pub struct Samples {
+    latest: Mutex<Latest>,
+}
+
+#[derive(Default)]
+struct Latest {
+    samples: VecDeque<f32>,
+    sample_rate: u32,
+    updated: Option<Instant>,
+}
+
+impl Samples {
+    /// Hand over newly played samples, unless whoever's drawing has them locked. Returns whether
+    /// they were taken, since the audio thread can't wait around.
+    fn push(&self, chunk: &[f32], sample_rate: u32) -> bool {
+        let mut latest = match self.latest.try_lock() {
+            Ok(l) => l,
+            Err(_) => return false,
+        };
+        latest.samples.extend(chunk);
+        let extra = latest.samples.len().saturating_sub(WINDOW);
+        latest.samples.drain(..extra);
+        latest.sample_rate = sample_rate;
+        latest.updated = Some(Instant::now());
+        true
+    }
+
+    /// The latest WINDOW samples and their sample rate, or None if nothing's played lately.
+    fn latest(&self) -> Option<(Vec<f32>, u32)> {
+        let latest = self.latest.lock().unwrap();
+        match latest.updated {
+            Some(u) if u.elapsed() < STALE_AFTER && latest.samples.len() == WINDOW => {
+                Some((latest.samples.iter().copied().collect(), latest.sample_rate))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Passes a source through untouched, copying what goes by into Samples for the visualizer. Like
+/// Signal, but for what's being played instead of when it ends.
+pub struct Tap<I> {
+    input: I,
+    samples: Arc<Samples>,
+    chunk: Vec<f32>,
+    /// The current frame's samples so far, added up.
+    frame: f32,
+    /// Which channel the next sample is for.
+    channel: u16,
+}
+
+impl<I> Tap<I>
+where
+    I: Source<Item = f32>,
+{
+    pub fn new(input: I, samples: Arc<Samples>) -> Tap<I> {
+        Tap {
+            input,
+            samples,
+            chunk: Vec::with_capacity(WINDOW),
+            frame: 0f32,
+            channel: 0,
+        }
+    }
+}
+
+impl<I> Iterator for Tap<I>
+where
+    I: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.input.next()?;
+        let channels = self.input.channels().max(1);
+        self.frame += sample;
+        self.channel += 1;
+        if self.channel >= channels {
+            self.chunk.push(self.frame / f32::from(channels));
+            self.frame = 0f32;
+            self.channel = 0;
+
+            if self.chunk.len() >= CHUNK {
+                let taken = self.samples.push(&self.chunk, self.input.sample_rate());
+                // if they weren't, keep them for next time, as long as there's room
+                if taken || self.chunk.len() >= WINDOW {
+                    self.chunk.clear();
+                }
+            }
+        }
+        Some(sample)
+    }
+}
+
+impl<I> Source for Tap<I>
+where
+    I: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    /// Bars for how loud each range of frequencies is, low on the left.
+    Spectrum,
+    /// The shape of the sound itself, like an oscilloscope.
+    Waveform,
+}
+
+/// The visualizer panel, toggled on and off, drawing from the player's samples when the player's
+/// in this process. A daemon's samples stay in the daemon.
+pub struct VisualizerView {
+    shown: Arc<AtomicBool>,
+    mode: Mode,
+    samples: Option<Arc<Samples>>,
+    window: Vec<f32>,
+    /// How high each bar was last drawn, from 0 to 1.
+    bars: Vec<f32>,
+    drawn: Instant,
+}
+
+impl VisualizerView {
+    pub fn new(samples: Option<Arc<Samples>>) -> VisualizerView {
+        VisualizerView {
+            shown: Arc::default(),
+            mode: Mode::Spectrum,
+            samples,
+            // a Hann window, so the edges of what's looked at don't smear across frequencies
+            window: (0..WINDOW)
+                .map(|i| 0.5 - 0.5 * (2f32 * PI * i as f32 / WINDOW as f32).cos())
+                .collect(),
+            bars: Vec::new(),
+            drawn: Instant::now(),
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.shown.load(Ordering::Relaxed)
+    }
+
+    /// Whether it's shown, for whatever keeps redrawing it.
+    pub fn shown(&self) -> Arc<AtomicBool> {
+        self.shown.clone()
+    }
+
+    pub fn toggle(&mut self) {
+        self.shown.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn next_mode(&mut self) {
+        self.mode = match self.mode {
+            Mode::Spectrum => Mode::Waveform,
+            Mode::Waveform => Mode::Spectrum,
+        };
+    }
+
+    /// Whether there's anything to draw from here at all.
+    pub fn is_available(&self) -> bool {
+        self.samples.is_some()
+    }
+
+    /// How high each of `count` bars should be, from 0 to 1, spread over the frequencies on a log
+    /// scale. Bars rise right away but fall slowly.
+    pub fn spectrum(&mut self, count: usize) -> Vec<f32> {
+        let levels = match self.samples.as_ref().and_then(|s| s.latest()) {
+            Some((samples, sample_rate)) => self.levels(&samples, sample_rate, count),
+            None => vec![0f32; count],
+        };
+
+        let fall = self.drawn.elapsed().as_secs_f32() * FALL;
+        self.drawn = Instant::now();
+        self.bars.resize(count, 0f32);
+        for (bar, level) in self.bars.iter_mut().zip(levels) {
+            *bar = level.max(*bar - fall);
+        }
+        self.bars.clone()
+    }
+
+    fn levels(&self, samples: &[f32], sample_rate: u32, count: usize) -> Vec<f32> {
+        let mut bins: Vec<(f32, f32)> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| (s * w, 0f32))
+            .collect();
+        fft(&mut bins);
+
+        // what a full scale sine would come out as, after the window
+        let full = self.window.iter().sum::<f32>() / 2f32;
+        let highest = HIGHEST.min(sample_rate as f32 / 2f32);
+        let bin = |frequency: f32| (frequency * WINDOW as f32 / sample_rate as f32) as usize;
+        (0..count)
+            .map(|i| {
+                let low = LOWEST * (highest / LOWEST).powf(i as f32 / count as f32);
+                let high = LOWEST * (highest / LOWEST).powf((i + 1) as f32 / count as f32);
+                let (low, high) = (bin(low), bin(high).max(bin(low)).min(WINDOW / 2));
+                let peak = bins[low..=high]
+                    .iter()
+                    .map(|(re, im)| (re * re + im * im).sqrt())
+                    .fold(0f32, f32::max);
+                let db = 20f32 * (peak / full).max(1e-9).log10();
+                ((db - FLOOR) / -FLOOR).clamp(0f32, 1f32)
+            })
+            .collect()
+    }
+
+    /// The lowest and highest sample under each of `count` columns, from -1 to 1.
+    pub fn waveform(&self, count: usize) -> Vec<(f32, f32)> {
+        let samples = match self.samples.as_ref().and_then(|s| s.latest()) {
+            Some((samples, _)) => samples,
+            None => return vec![(0f32, 0f32); count],
+        };
+        let per_column = (WINDOW / count.max(1)).max(1);
+        samples
+            .chunks(per_column)
+            .take(count)
+            .map(|chunk| {
+                let low = chunk.iter().copied().fold(f32::MAX, f32::min);
+                let high = chunk.iter().copied().fold(f32::MIN, f32::max);
+                (low.max(-1f32), high.min(1f32))
+            })
+            .collect()
+    }
+}
+
+/// An in-place radix-2 FFT, with each value as (real, imaginary). The length has to be a power
+/// of two.
+fn fft(values: &mut [(f32, f32)]) {
+    let n = values.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            values.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= n {
+        let angle = -2f32 * PI / length as f32;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (re, im) = values[start + k + length / 2];
+                let twiddled = (re * cos - im * sin, re * sin + im * cos);
+                let even = values[start + k];
+                values[start + k] = (even.0 + twiddled.0, even.1 + twiddled.1);
+                values[start + k + length / 2] = (even.0 - twiddled.0, even.1 - twiddled.1);
+            }
+        }
+        length <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A tenth of a second of a full scale sine at `frequency`, in both channels, played through a
+    /// tap into a view.
+    fn tapped(frequency: f32) -> (Vec<f32>, Vec<f32>, VisualizerView) {
+        let input: Vec<f32> = (0..SAMPLE_RATE / 10)
+            .flat_map(|i| {
+                let sample = (2f32 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin();
+                [sample, sample]
+            })
+            .collect();
+        let samples = Arc::new(Samples::default());
+        let source = SamplesBuffer::new(2, SAMPLE_RATE, input.clone());
+        let output = Tap::new(source, samples.clone()).collect();
+        (input, output, VisualizerView::new(Some(samples)))
+    }
+
+    /// Whether `bar` is as high as any, since neighbouring bars can share the frequency between
+    /// them.
+    fn is_loudest(bars: &[f32], bar: usize) -> bool {
+        bars.iter().all(|b| *b <= bars[bar])
+    }
+
+    #[test]
+    fn tap_passes_through() {
+        let (input, output, _) = tapped(1000f32);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn spectrum() {
+        for (frequency, bar) in [(100f32, 3), (1000f32, 12), (8000f32, 21)] {
+            let (_, _, mut view) = tapped(frequency);
+            let bars = view.spectrum(24);
+            assert_eq!(bars.len(), 24);
+            assert!(is_loudest(&bars, bar), "{} Hz: {:?}", frequency, bars);
+            assert!(bars[bar] > 0.9, "{} Hz: {}", frequency, bars[bar]);
+            // a clean sine leaves the far end quiet
+            let far = if bar > 12 { 0 } else { 23 };
+            assert!(bars[far] < 0.3, "{} Hz: {:?}", frequency, bars);
+        }
+    }
+
+    #[test]
+    fn nothing_playing() {
+        let mut view = VisualizerView::new(Some(Arc::default()));
+        assert_eq!(view.spectrum(8), vec![0f32; 8]);
+        assert_eq!(view.waveform(8), vec![(0f32, 0f32); 8]);
+        let mut remote = VisualizerView::new(None);
+        assert!(!remote.is_available());
+        assert_eq!(remote.spectrum(4), vec![0f32; 4]);
+    }
+
+    #[test]
+    fn waveform() {
+        let (_, _, view) = tapped(1000f32);
+        let columns = view.waveform(16);
+        assert_eq!(columns.len(), 16);
+        // 128 samples a column is more than a whole cycle of 1 kHz
+        for (low, high) in columns {
+            assert!(low < -0.99 && high > 0.99, "{} {}", low, high);
+        }
+    }
+
+    #[test]
+    fn fft_finds_the_frequency() {
+        let mut values: Vec<(f32, f32)> = (0..64)
+            .map(|i| ((2f32 * PI * 5f32 * i as f32 / 64f32).cos(), 0f32))
+            .collect();
+        fft(&mut values);
+        let magnitudes: Vec<f32> = values.iter().map(|(re, im)| re.hypot(*im)).collect();
+        // half in bin 5 and half in its mirror image
+        assert!((magnitudes[5] - 32f32).abs() < 1e-3);
+        assert!((magnitudes[59] - 32f32).abs() < 1e-3);
+        let rest: f32 = magnitudes.iter().sum::<f32>() - magnitudes[5] - magnitudes[59];
+        assert!(rest < 1e-2, "{}", rest);
+    }
+}
@@ -0,0 +1,86 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Directory for the config file.
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Directory for things that can be thrown away and made again, like extracted cover art.
+pub fn cache_dir() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+/// Directory for state that should persist between restarts, like the session.
+pub fn state_dir() -> Option<PathBuf> {
+    base_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// Directory for sockets and other things that only make sense while bebop is running.
+pub fn runtime_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from) {
+        Some(p) if p.is_absolute() => p,
+        _ => {
+            let user = env::var("USER").unwrap_or_default();
+            env::temp_dir().join(format!("bebop-{}", user))
+        }
+    }
+}
+
+fn base_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = match env::var_os(var).map(PathBuf::from) {
+        Some(p) if p.is_absolute() => p,
+        _ => PathBuf::from(env::var_os("HOME")?).join(fallback),
+    };
+    Some(base.join("bebop"))
+}
+
+/// Point the state and cache directories at a directory of the tests' own, so they never touch
+/// the real ones. Every test that reads or writes them calls this first.
+#[cfg(test)]
+pub fn isolate() {
+    use std::sync::Once;
+
+    static ISOLATE: Once = Once::new();
+    ISOLATE.call_once(|| {
+        let dir = env::temp_dir().join(format!("bebop-test-{}", std::process::id()));
+        env::set_var("XDG_STATE_HOME", dir.join("state"));
+        env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+    });
+}
+
+/// A directory of a test's own under the temporary directory, deleted again when it's dropped.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("bebop-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<std::path::Path> for TempDir {
+    fn as_ref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}